@@ -22,6 +22,8 @@ use tonic::{Code, Status};
 pub enum YaufsError {
     #[error("{0}")]
     NotFound(&'static str),
+    #[error("{0}")]
+    InvalidArgument(&'static str),
     #[error("Unauthorized")]
     Unauthorized,
     #[cfg(feature = "surrealdb")]
@@ -52,6 +54,7 @@ impl From<YaufsError> for Status {
 
         match value {
             YaufsError::NotFound(message) => Status::not_found(message),
+            YaufsError::InvalidArgument(message) => Status::invalid_argument(message),
             YaufsError::Unauthorized => Status::unauthenticated(value.to_string()),
             _ => Status::internal("Error occurred while processing the request"),
         }
@@ -65,6 +68,7 @@ impl From<Status> for YaufsError {
         match status.code() {
             Code::Unauthenticated => Self::Unauthorized,
             Code::NotFound => Self::NotFound("Not found"),
+            Code::InvalidArgument => Self::InvalidArgument("Invalid argument"),
             _ => Self::InternalServerError(status.message().to_string()),
         }
     }
@@ -17,3 +17,4 @@
 pub use mcproto_rs::status;
 
 pub mod packet;
+pub mod play;
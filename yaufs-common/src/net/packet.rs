@@ -17,6 +17,8 @@
 use mcproto_rs::{types::*, uuid::*, *};
 
 // based on https://github.com/Twister915/mcproto-rs/blob/master/src/v1_16_3.rs
+// TODO: transfers to other proxies (transfer and cookie packets) require protocol 766 (1.20.5)
+//  including the configuration state, which needs a protocol definition per version first
 define_protocol!(762, Packet762, RawPacket762, RawPacket762Body, Packet762Kind => {
     Handshake, 0x00, Handshaking, ServerBound => HandshakeSpec {
         version: VarInt,
@@ -155,7 +157,7 @@ define_protocol!(762, Packet762, RawPacket762, RawPacket762Body, Packet762Kind =
         data: RemainingBytes
     },
     PlayDisconnect, 0x1A, Play, ClientBound => PlayDisconnectSpec {
-        data: RemainingBytes
+        reason: Chat
     },
     PlayDisguisedChatMessage, 0x1B, Play, ClientBound => PlayDisguisedChatMessageSpec {
         data: RemainingBytes
@@ -182,7 +184,7 @@ define_protocol!(762, Packet762, RawPacket762, RawPacket762Body, Packet762Kind =
         data: RemainingBytes
     },
     PlayServerKeepAlive, 0x23, Play, ClientBound => PlayServerKeepAliveSpec {
-        data: RemainingBytes
+        id: i64
     },
     PlayChunkDataAndUpdateLight, 0x24, Play, ClientBound => PlayChunkDataAndUpdateLightSpec {
         data: RemainingBytes
@@ -197,7 +199,23 @@ define_protocol!(762, Packet762, RawPacket762, RawPacket762Body, Packet762Kind =
         data: RemainingBytes
     },
     PlayLogin, 0x28, Play, ClientBound => PlayLoginSpec {
-        data: RemainingBytes
+        entity_id: i32,
+        hardcore: bool,
+        game_mode: u8,
+        previous_game_mode: i8,
+        dimension_names: CountedArray<String, VarInt>,
+        registry_codec: NamedNbtTag,
+        dimension_type: String,
+        dimension_name: String,
+        hashed_seed: i64,
+        max_players: VarInt,
+        view_distance: VarInt,
+        simulation_distance: VarInt,
+        reduced_debug_info: bool,
+        enable_respawn_screen: bool,
+        is_debug: bool,
+        is_flat: bool,
+        death_location: Option<DeathLocationSpec>
     },
     PlayMapData, 0x29, Play, ClientBound => PlayMapDataSpec {
         data: RemainingBytes
@@ -248,7 +266,7 @@ define_protocol!(762, Packet762, RawPacket762, RawPacket762Body, Packet762Kind =
         data: RemainingBytes
     },
     PlayPlayerInfoRemove, 0x39, Play, ClientBound => PlayPlayerInfoRemoveSpec {
-        data: RemainingBytes
+        players: CountedArray<UUID4, VarInt>
     },
     PlayPlayerInfoUpdate, 0x3A, Play, ClientBound => PlayPlayerInfoUpdateSpec {
         data: RemainingBytes
@@ -257,7 +275,13 @@ define_protocol!(762, Packet762, RawPacket762, RawPacket762Body, Packet762Kind =
         data: RemainingBytes
     },
     PlaySynchronizePlayerPosition, 0x3C, Play, ClientBound => PlaySynchronizePlayerPositionSpec {
-        data: RemainingBytes
+        x: f64,
+        y: f64,
+        z: f64,
+        yaw: f32,
+        pitch: f32,
+        flags: u8,
+        teleport_id: VarInt
     },
     PlayUpdateRecipeBook, 0x3D, Play, ClientBound => PlayUpdateRecipeBookSpec {
         data: RemainingBytes
@@ -269,10 +293,21 @@ define_protocol!(762, Packet762, RawPacket762, RawPacket762Body, Packet762Kind =
         data: RemainingBytes
     },
     PlayResourcePack, 0x40, Play, ClientBound => PlayResourcePackSpec {
-        data: RemainingBytes
+        url: String,
+        hash: String,
+        forced: bool,
+        prompt_message: Option<Chat>
     },
     PlayRespawn, 0x41, Play, ClientBound => PlayRespawnSpec {
-        data: RemainingBytes
+        dimension_type: String,
+        dimension_name: String,
+        hashed_seed: i64,
+        game_mode: u8,
+        previous_game_mode: i8,
+        is_debug: bool,
+        is_flat: bool,
+        data_kept: u8,
+        death_location: Option<DeathLocationSpec>
     },
     PlaySetHeadRotation, 0x42, Play, ClientBound => PlaySetHeadRotationSpec {
         data: RemainingBytes
@@ -287,7 +322,7 @@ define_protocol!(762, Packet762, RawPacket762, RawPacket762Body, Packet762Kind =
         data: RemainingBytes
     },
     PlaySetActionBarText, 0x46, Play, ClientBound => PlaySetActionBarTextSpec {
-        data: RemainingBytes
+        text: Chat
     },
     PlaySetBorderCenter, 0x47, Play, ClientBound => PlaySetBorderCenterSpec {
         data: RemainingBytes
@@ -317,7 +352,8 @@ define_protocol!(762, Packet762, RawPacket762, RawPacket762Body, Packet762Kind =
         data: RemainingBytes
     },
     PlaySetDefaultSpawnLocation, 0x50, Play, ClientBound => PlaySetDefaultSpawnLocationSpec {
-        data: RemainingBytes
+        location: IntPosition,
+        angle: f32
     },
     PlayDisplayObjective, 0x51, Play, ClientBound => PlayDisplayObjectiveSpec {
         data: RemainingBytes
@@ -356,16 +392,18 @@ define_protocol!(762, Packet762, RawPacket762, RawPacket762Body, Packet762Kind =
         data: RemainingBytes
     },
     PlaySetSubtitleText, 0x5D, Play, ClientBound => PlaySetSubtitleTextSpec {
-        data: RemainingBytes
+        text: Chat
     },
     PlayUpdateTime, 0x5E, Play, ClientBound => PlayUpdateTimeSpec {
         data: RemainingBytes
     },
     PlaySetTitleText, 0x5F, Play, ClientBound => PlaySetTitleTextSpec {
-        data: RemainingBytes
+        text: Chat
     },
     PlaySetTitleAnimationTimes, 0x60, Play, ClientBound => PlaySetTitleAnimationTimesSpec {
-        data: RemainingBytes
+        fade_in: i32,
+        stay: i32,
+        fade_out: i32
     },
     PlayEntitySoundEffect, 0x61, Play, ClientBound => PlayEntitySoundEffectSpec {
         data: RemainingBytes
@@ -377,10 +415,12 @@ define_protocol!(762, Packet762, RawPacket762, RawPacket762Body, Packet762Kind =
         data: RemainingBytes
     },
     PlaySystemChatMessage, 0x64, Play, ClientBound => PlaySystemChatMessageSpec {
-        data: RemainingBytes
+        content: Chat,
+        overlay: bool
     },
     PlaySetTabListHeaderAndFooter, 0x65, Play, ClientBound => PlaySetTabListHeaderAndFooter {
-        data: RemainingBytes
+        header: Chat,
+        footer: Chat
     },
     PlayTagQueryResponse, 0x66, Play, ClientBound => PlaySetTagQueryResponse {
         data: RemainingBytes
@@ -417,142 +457,159 @@ define_protocol!(762, Packet762, RawPacket762, RawPacket762Body, Packet762Kind =
     PlayQueryBlockNbt, 0x01, Play, ServerBound => PlayQueryBlockNbtSpec {
         data: RemainingBytes
     },
-    PlayQueryEntityNbt, 0x0D, Play, ServerBound => PlayQueryEntityNbtSpec {
+    PlaySetDifficulty, 0x02, Play, ServerBound => PlaySetDifficultySpec {
         data: RemainingBytes
     },
-    PlaySetDifficulty, 0x02, Play, ServerBound => PlaySetDifficultySpec {
+    PlayMessageAcknowledgement, 0x03, Play, ServerBound => PlayMessageAcknowledgementSpec {
         data: RemainingBytes
     },
-    PlayClientChatMessage, 0x03, Play, ServerBound => PlayClientChatMessageSpec {
+    PlayChatCommand, 0x04, Play, ServerBound => PlayChatCommandSpec {
         data: RemainingBytes
     },
-    PlayClientStatus, 0x04, Play, ServerBound => PlayClientStatusSpec {
+    PlayClientChatMessage, 0x05, Play, ServerBound => PlayClientChatMessageSpec {
         data: RemainingBytes
     },
-    PlayClientSettings, 0x05, Play, ServerBound => PlayClientSettingsSpec {
+    PlayPlayerSession, 0x06, Play, ServerBound => PlayPlayerSessionSpec {
         data: RemainingBytes
     },
-    PlayClientTabComplete, 0x06, Play, ServerBound => PlayClientTabCompleteSpec {
+    PlayClientStatus, 0x07, Play, ServerBound => PlayClientStatusSpec {
         data: RemainingBytes
     },
-    PlayClientWindowConfirmation, 0x07, Play, ServerBound => PlayClientWindowConfirmationSpec {
+    PlayClientSettings, 0x08, Play, ServerBound => PlayClientSettingsSpec {
+        locale: String,
+        view_distance: i8,
+        chat_mode: VarInt,
+        chat_colors: bool,
+        displayed_skin_parts: u8,
+        main_hand: VarInt,
+        enable_text_filtering: bool,
+        allow_server_listings: bool
+    },
+    PlayClientTabComplete, 0x09, Play, ServerBound => PlayClientTabCompleteSpec {
         data: RemainingBytes
     },
-    PlayClickWindowButton, 0x08, Play, ServerBound => PlayClickWindowButtonSpec {
+    PlayClickWindowButton, 0x0A, Play, ServerBound => PlayClickWindowButtonSpec {
         data: RemainingBytes
     },
-    PlayClickWindow, 0x09, Play, ServerBound => PlayClickWindowSpec {
+    PlayClickWindow, 0x0B, Play, ServerBound => PlayClickWindowSpec {
         data: RemainingBytes
     },
-    PlayClientCloseWindow, 0x0A, Play, ServerBound => PlayClientCloseWindowSpec {
+    PlayClientCloseWindow, 0x0C, Play, ServerBound => PlayClientCloseWindowSpec {
         data: RemainingBytes
     },
-    PlayClientPluginMessage, 0x0B, Play, ServerBound => PlayClientPluginMessageSpec {
+    PlayClientPluginMessage, 0x0D, Play, ServerBound => PlayClientPluginMessageSpec {
+        channel: String,
         data: RemainingBytes
     },
-    PlayEditBook, 0x0C, Play, ServerBound => PlayEditBookSpec {
+    PlayEditBook, 0x0E, Play, ServerBound => PlayEditBookSpec {
         data: RemainingBytes
     },
-    PlayInteractEntity, 0x0E, Play, ServerBound => PlayInteractEntitySpec {
+    PlayQueryEntityNbt, 0x0F, Play, ServerBound => PlayQueryEntityNbtSpec {
         data: RemainingBytes
     },
-    PlayGenerateStructure, 0x0F, Play, ServerBound => PlayGenerateStructureSpec {
+    PlayInteractEntity, 0x10, Play, ServerBound => PlayInteractEntitySpec {
         data: RemainingBytes
     },
-    PlayClientKeepAlive, 0x10, Play, ServerBound => PlayClientKeepAliveSpec {
+    PlayGenerateStructure, 0x11, Play, ServerBound => PlayGenerateStructureSpec {
         data: RemainingBytes
     },
-    PlayLockDifficulty, 0x11, Play, ServerBound => PlayLockDifficultySpec {
+    PlayClientKeepAlive, 0x12, Play, ServerBound => PlayClientKeepAliveSpec {
+        id: i64
+    },
+    PlayLockDifficulty, 0x13, Play, ServerBound => PlayLockDifficultySpec {
         data: RemainingBytes
     },
-    PlayPlayerPosition, 0x12, Play, ServerBound => PlayPlayerPositionSpec {
+    PlayPlayerPosition, 0x14, Play, ServerBound => PlayPlayerPositionSpec {
         data: RemainingBytes
     },
-    PlayClientPlayerPositionAndRotation, 0x13, Play, ServerBound => PlayClientPlayerPositionAndRotationSpec {
+    PlayClientPlayerPositionAndRotation, 0x15, Play, ServerBound => PlayClientPlayerPositionAndRotationSpec {
         data: RemainingBytes
     },
-    PlayPlayerRotation, 0x14, Play, ServerBound => PlayPlayerRotationSpec {
+    PlayPlayerRotation, 0x16, Play, ServerBound => PlayPlayerRotationSpec {
         data: RemainingBytes
     },
-    PlayPlayerMovement, 0x15, Play, ServerBound => PlayPlayerMovementSpec {
+    PlayPlayerMovement, 0x17, Play, ServerBound => PlayPlayerMovementSpec {
         data: RemainingBytes
     },
-    PlayClientVehicleMove, 0x16, Play, ServerBound => PlayClientVehicleMoveSpec {
+    PlayClientVehicleMove, 0x18, Play, ServerBound => PlayClientVehicleMoveSpec {
         data: RemainingBytes
     },
-    PlaySteerBoat, 0x17, Play, ServerBound => PlaySteerBoatSpec {
+    PlaySteerBoat, 0x19, Play, ServerBound => PlaySteerBoatSpec {
         data: RemainingBytes
     },
-    PlayPickItem, 0x18, Play, ServerBound => PlayPickItemSpec {
+    PlayPickItem, 0x1A, Play, ServerBound => PlayPickItemSpec {
         data: RemainingBytes
     },
-    PlayCraftRecipeRequest, 0x19, Play, ServerBound => PlayCraftRecipeRequestSpec {
+    PlayCraftRecipeRequest, 0x1B, Play, ServerBound => PlayCraftRecipeRequestSpec {
         data: RemainingBytes
     },
-    PlayClientPlayerAbilities, 0x1A, Play, ServerBound => PlayClientPlayerAbilitiesSpec {
+    PlayClientPlayerAbilities, 0x1C, Play, ServerBound => PlayClientPlayerAbilitiesSpec {
         data: RemainingBytes
     },
-    PlayPlayerDigging, 0x1B, Play, ServerBound => PlayPlayerDiggingSpec {
+    PlayPlayerDigging, 0x1D, Play, ServerBound => PlayPlayerDiggingSpec {
         data: RemainingBytes
     },
-    PlayEntityAction, 0x1C, Play, ServerBound => PlayEntityActionSpec {
+    PlayEntityAction, 0x1E, Play, ServerBound => PlayEntityActionSpec {
         data: RemainingBytes
     },
-    PlaySteerVehicle, 0x1D, Play, ServerBound => PlaySteerVehicleSpec {
+    PlaySteerVehicle, 0x1F, Play, ServerBound => PlaySteerVehicleSpec {
         data: RemainingBytes
     },
-    PlaySetDisplayedRecipe, 0x1E, Play, ServerBound => PlaySetDisplayedRecipeSpec {
+    PlayPong, 0x20, Play, ServerBound => PlayPongSpec {
         data: RemainingBytes
     },
-    PlaySetRecipeBookState, 0x1F, Play, ServerBound => PlaySetRecipeBookStateSpec {
+    PlaySetRecipeBookState, 0x21, Play, ServerBound => PlaySetRecipeBookStateSpec {
         data: RemainingBytes
     },
-    PlayNameItem, 0x20, Play, ServerBound => PlayNameItemSpec {
+    PlaySetDisplayedRecipe, 0x22, Play, ServerBound => PlaySetDisplayedRecipeSpec {
         data: RemainingBytes
     },
-    PlayResourcePackStatus, 0x21, Play, ServerBound => PlayResourcePackStatusSpec {
+    PlayNameItem, 0x23, Play, ServerBound => PlayNameItemSpec {
         data: RemainingBytes
     },
-    PlayAdvancementTab, 0x22, Play, ServerBound => PlayAdvancementTabSpec {
+    PlayResourcePackStatus, 0x24, Play, ServerBound => PlayResourcePackStatusSpec {
+        result: VarInt
+    },
+    PlayAdvancementTab, 0x25, Play, ServerBound => PlayAdvancementTabSpec {
         data: RemainingBytes
     },
-    PlaySelectTrade, 0x23, Play, ServerBound => PlaySelectTradeSpec {
+    PlaySelectTrade, 0x26, Play, ServerBound => PlaySelectTradeSpec {
         data: RemainingBytes
     },
-    PlaySetBeaconEffect, 0x24, Play, ServerBound => PlaySetBeaconEffectSpec {
+    PlaySetBeaconEffect, 0x27, Play, ServerBound => PlaySetBeaconEffectSpec {
         data: RemainingBytes
     },
-    PlayClientHeldItemChange, 0x25, Play, ServerBound => PlayClientHeldItemChangeSpec {
+    PlayClientHeldItemChange, 0x28, Play, ServerBound => PlayClientHeldItemChangeSpec {
         data: RemainingBytes
     },
-    PlayUpdateCommandBlock, 0x26, Play, ServerBound => PlayUpdateCommandBlockSpec {
+    PlayUpdateCommandBlock, 0x29, Play, ServerBound => PlayUpdateCommandBlockSpec {
         data: RemainingBytes
     },
-    PlayUpdateCommandBlockMinecart, 0x27, Play, ServerBound => PlayUpdateCommandBlockMinecartSpec {
+    PlayUpdateCommandBlockMinecart, 0x2A, Play, ServerBound => PlayUpdateCommandBlockMinecartSpec {
         data: RemainingBytes
     },
-    PlayUpdateJigsawBlock, 0x28, Play, ServerBound => PlayUpdateJigsawBlockSpec {
+    PlayCreativeInventoryAction, 0x2B, Play, ServerBound => PlayCreativeInventoryActionSpec {
         data: RemainingBytes
     },
-    PlayCreativeInventoryAction, 0x29, Play, ServerBound => PlayCreativeInventoryActionSpec {
+    PlayUpdateJigsawBlock, 0x2C, Play, ServerBound => PlayUpdateJigsawBlockSpec {
         data: RemainingBytes
     },
-    PlayUpdateStructureBlock, 0x2A, Play, ServerBound => PlayUpdateStructureBlockSpec {
+    PlayUpdateStructureBlock, 0x2D, Play, ServerBound => PlayUpdateStructureBlockSpec {
         data: RemainingBytes
     },
-    PlayUpdateSign, 0x2B, Play, ServerBound => PlayUpdateSignSpec {
+    PlayUpdateSign, 0x2E, Play, ServerBound => PlayUpdateSignSpec {
         data: RemainingBytes
     },
-    PlayClientAnimation, 0x2C, Play, ServerBound => PlayClientAnimationSpec {
+    PlayClientAnimation, 0x2F, Play, ServerBound => PlayClientAnimationSpec {
         data: RemainingBytes
     },
-    PlaySpectate, 0x2D, Play, ServerBound => PlaySpectateSpec {
+    PlaySpectate, 0x30, Play, ServerBound => PlaySpectateSpec {
         data: RemainingBytes
     },
-    PlayBlockPlacement, 0x2E, Play, ServerBound => PlayBlockPlacementSpec {
+    PlayBlockPlacement, 0x31, Play, ServerBound => PlayBlockPlacementSpec {
         data: RemainingBytes
     },
-    PlayUseItem, 0x2F, Play, ServerBound => PlayUseItemSpec {
+    PlayUseItem, 0x32, Play, ServerBound => PlayUseItemSpec {
         data: RemainingBytes
     }
 });
@@ -564,6 +621,11 @@ proto_struct!(LoginSuccessPropertiesSpec {
     signature: String
 });
 
+proto_struct!(DeathLocationSpec {
+    dimension_name: String,
+    location: IntPosition
+});
+
 proto_byte_enum!(HandshakeNextState,
     0x01 :: Status,
     0x02 :: Login
@@ -0,0 +1,382 @@
+/*
+ *    Copyright  2023.  Fritz Ochsmann
+ *
+ *    Licensed under the Apache License, Version 2.0 (the "License");
+ *    you may not use this file except in compliance with the License.
+ *    You may obtain a copy of the License at
+ *
+ *        http://www.apache.org/licenses/LICENSE-2.0
+ *
+ *    Unless required by applicable law or agreed to in writing, software
+ *    distributed under the License is distributed on an "AS IS" BASIS,
+ *    WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ *    See the License for the specific language governing permissions and
+ *    limitations under the License.
+ */
+
+use crate::net::packet::{
+    LoginSuccessPropertiesSpec, PlayChunkDataAndUpdateLightSpec, PlayLoginSpec,
+    PlayPlayerInfoUpdateSpec, PlayRespawnSpec, PlaySystemChatMessageSpec,
+};
+use mcproto_rs::nbt::{NamedTag, Tag};
+use mcproto_rs::types::{Chat, CountedArray, NamedNbtTag, RemainingBytes, VarInt};
+use mcproto_rs::uuid::UUID4;
+use mcproto_rs::{
+    BytesSerializer, Deserialize, DeserializeErr, Serialize, SerializeErr, Serializer,
+};
+
+// chat signatures are always 256 bytes long and are not prefixed with their length
+const SIGNATURE_LENGTH: usize = 256;
+
+// the actions of the player info update are sent as a bit set
+pub const PLAYER_INFO_ADD: u8 = 0x01;
+pub const PLAYER_INFO_INITIALIZE_CHAT: u8 = 0x02;
+pub const PLAYER_INFO_GAME_MODE: u8 = 0x04;
+pub const PLAYER_INFO_LISTED: u8 = 0x08;
+pub const PLAYER_INFO_LATENCY: u8 = 0x10;
+pub const PLAYER_INFO_DISPLAY_NAME: u8 = 0x20;
+
+// the results of a resource pack status sent by the client
+pub const RESOURCE_PACK_LOADED: i32 = 0;
+pub const RESOURCE_PACK_DECLINED: i32 = 1;
+pub const RESOURCE_PACK_FAILED: i32 = 2;
+pub const RESOURCE_PACK_ACCEPTED: i32 = 3;
+
+/// A player entry of the tab list.
+#[derive(Debug, Clone)]
+pub struct PlayerInfoEntry {
+    pub uuid: UUID4,
+    pub name: String,
+    pub properties: Vec<LoginSuccessPropertiesSpec>,
+    pub latency: i32,
+}
+
+/// Build a player info update adding the given players to the tab list of the client.
+pub fn player_info_add(
+    entries: &[PlayerInfoEntry],
+) -> Result<PlayPlayerInfoUpdateSpec, SerializeErr> {
+    let mut serializer = BytesSerializer::default();
+    (PLAYER_INFO_ADD | PLAYER_INFO_LISTED | PLAYER_INFO_LATENCY).mc_serialize(&mut serializer)?;
+    VarInt(entries.len() as i32).mc_serialize(&mut serializer)?;
+
+    for entry in entries {
+        entry.uuid.mc_serialize(&mut serializer)?;
+        // add player
+        entry.name.mc_serialize(&mut serializer)?;
+        VarInt(entry.properties.len() as i32).mc_serialize(&mut serializer)?;
+        for property in entry.properties.iter() {
+            property.name.mc_serialize(&mut serializer)?;
+            property.value.mc_serialize(&mut serializer)?;
+            property.signed.mc_serialize(&mut serializer)?;
+            if property.signed {
+                property.signature.mc_serialize(&mut serializer)?;
+            }
+        }
+        // update listed
+        true.mc_serialize(&mut serializer)?;
+        // update latency
+        VarInt(entry.latency).mc_serialize(&mut serializer)?;
+    }
+
+    Ok(PlayPlayerInfoUpdateSpec {
+        data: RemainingBytes {
+            data: serializer.into_bytes(),
+        },
+    })
+}
+
+/// Read the plain message of a chat message sent by the client. The signature and the
+/// acknowledgements following the message are not touched.
+pub fn chat_message(data: &[u8]) -> Result<String, DeserializeErr> {
+    Ok(String::mc_deserialize(data)?.value)
+}
+
+/// Read the command (without the leading slash) of a chat command sent by the client.
+pub fn chat_command(data: &[u8]) -> Result<String, DeserializeErr> {
+    Ok(String::mc_deserialize(data)?.value)
+}
+
+/// Check whether a chat message of the client carries a signature.
+pub fn chat_signed(data: &[u8]) -> Result<bool, DeserializeErr> {
+    let mut reader = Reader(data);
+    reader.read::<String>()?;
+    reader.read::<i64>()?;
+    reader.read::<i64>()?;
+
+    reader.read::<bool>()
+}
+
+/// Remove the signature of a chat message sent by the client, which turns it into an unsigned
+/// message for the backend.
+pub fn strip_chat_signature(data: &[u8]) -> Result<Vec<u8>, DeserializeErr> {
+    let mut reader = Reader(data);
+    reader.read::<String>()?;
+    reader.read::<i64>()?;
+    reader.read::<i64>()?;
+    let prefix = data.len() - reader.0.len();
+    if reader.read::<bool>()? {
+        reader.skip(SIGNATURE_LENGTH)?;
+    }
+
+    let mut stripped = Vec::from(&data[..prefix]);
+    stripped.push(0);
+    stripped.extend_from_slice(reader.0);
+
+    Ok(stripped)
+}
+
+/// Remove the argument signatures of a chat command sent by the client.
+pub fn strip_command_signatures(data: &[u8]) -> Result<Vec<u8>, DeserializeErr> {
+    let mut reader = Reader(data);
+    reader.read::<String>()?;
+    reader.read::<i64>()?;
+    reader.read::<i64>()?;
+    let prefix = data.len() - reader.0.len();
+    for _ in 0..reader.read::<VarInt>()?.0 {
+        reader.read::<String>()?;
+        reader.skip(SIGNATURE_LENGTH)?;
+    }
+
+    // an empty array only consists of its length
+    let mut stripped = Vec::from(&data[..prefix]);
+    stripped.push(0);
+    stripped.extend_from_slice(reader.0);
+
+    Ok(stripped)
+}
+
+/// Overwrite whether the server enforces secure chat in the server data sent by the backend,
+/// which is the last field of the packet.
+pub fn set_enforces_secure_chat(data: &[u8], enforces: bool) -> Vec<u8> {
+    let mut data = Vec::from(data);
+    if let Some(last) = data.last_mut() {
+        *last = enforces as u8;
+    }
+
+    data
+}
+
+/// Build a message of the proxy itself. System messages are never signed, so they can be
+/// injected without breaking the chat of the client.
+pub fn system_message(content: Chat) -> PlaySystemChatMessageSpec {
+    PlaySystemChatMessageSpec {
+        content,
+        overlay: false,
+    }
+}
+
+/// Read the players added to the tab list by a player info update of the backend.
+pub fn player_info_added(data: &[u8]) -> Result<Vec<UUID4>, DeserializeErr> {
+    let mut reader = Reader(data);
+    let actions = reader.read::<u8>()?;
+    let count = reader.read::<VarInt>()?.0;
+
+    let mut added = Vec::new();
+    for _ in 0..count {
+        let uuid = reader.read::<UUID4>()?;
+        // every entry contains the data of all actions in the order of their bits
+        if actions & PLAYER_INFO_ADD != 0 {
+            reader.read::<String>()?;
+            for _ in 0..reader.read::<VarInt>()?.0 {
+                reader.read::<String>()?;
+                reader.read::<String>()?;
+                if reader.read::<bool>()? {
+                    reader.read::<String>()?;
+                }
+            }
+            added.push(uuid);
+        }
+        if actions & PLAYER_INFO_INITIALIZE_CHAT != 0 && reader.read::<bool>()? {
+            reader.read::<UUID4>()?;
+            reader.read::<i64>()?;
+            reader.read::<CountedArray<u8, VarInt>>()?;
+            reader.read::<CountedArray<u8, VarInt>>()?;
+        }
+        if actions & PLAYER_INFO_GAME_MODE != 0 {
+            reader.read::<VarInt>()?;
+        }
+        if actions & PLAYER_INFO_LISTED != 0 {
+            reader.read::<bool>()?;
+        }
+        if actions & PLAYER_INFO_LATENCY != 0 {
+            reader.read::<VarInt>()?;
+        }
+        if actions & PLAYER_INFO_DISPLAY_NAME != 0 && reader.read::<bool>()? {
+            reader.read::<Chat>()?;
+        }
+    }
+
+    Ok(added)
+}
+
+/// Build the respawn into the world of a join game. Sent after a join game while the client is
+/// already playing, which forces the client to drop the world of the previous backend.
+pub fn respawn_of(login: &PlayLoginSpec) -> PlayRespawnSpec {
+    PlayRespawnSpec {
+        dimension_type: login.dimension_type.clone(),
+        dimension_name: login.dimension_name.clone(),
+        hashed_seed: login.hashed_seed,
+        game_mode: login.game_mode,
+        previous_game_mode: login.previous_game_mode,
+        is_debug: login.is_debug,
+        is_flat: login.is_flat,
+        // nothing of the previous backend is kept
+        data_kept: 0,
+        death_location: login.death_location.clone(),
+    }
+}
+
+/// Build a chunk without any blocks. The number of sections has to match the height of the
+/// dimension (which is 24 for the overworld).
+pub fn empty_chunk(
+    x: i32,
+    z: i32,
+    sections: usize,
+) -> Result<PlayChunkDataAndUpdateLightSpec, SerializeErr> {
+    // every section consists of single valued palettes for the blocks (air) and the biomes
+    let mut data = BytesSerializer::default();
+    for _ in 0..sections {
+        0i16.mc_serialize(&mut data)?;
+        for _ in 0..2 {
+            0u8.mc_serialize(&mut data)?;
+            VarInt(0).mc_serialize(&mut data)?;
+            VarInt(0).mc_serialize(&mut data)?;
+        }
+    }
+    let data = data.into_bytes();
+
+    let mut serializer = BytesSerializer::default();
+    x.mc_serialize(&mut serializer)?;
+    z.mc_serialize(&mut serializer)?;
+    // no heightmaps
+    NamedNbtTag {
+        root: NamedTag {
+            name: String::new(),
+            payload: Tag::Compound(vec![]),
+        },
+    }
+    .mc_serialize(&mut serializer)?;
+    VarInt(data.len() as i32).mc_serialize(&mut serializer)?;
+    serializer.serialize_bytes(data.as_slice())?;
+    // no block entities
+    VarInt(0).mc_serialize(&mut serializer)?;
+    // trust edges, followed by the empty light masks and arrays
+    true.mc_serialize(&mut serializer)?;
+    for _ in 0..6 {
+        VarInt(0).mc_serialize(&mut serializer)?;
+    }
+
+    Ok(PlayChunkDataAndUpdateLightSpec {
+        data: RemainingBytes {
+            data: serializer.into_bytes(),
+        },
+    })
+}
+
+/// Resolve the height of a dimension type defined in the registry codec of a join game.
+pub fn dimension_height(registry_codec: &NamedNbtTag, dimension_type: &str) -> Option<i32> {
+    let registry = compound_entry(&registry_codec.root.payload, "minecraft:dimension_type")?;
+    let values = match compound_entry(registry, "value")? {
+        Tag::List(values) => values,
+        _ => return None,
+    };
+
+    values.iter().find_map(|value| {
+        match compound_entry(value, "name")? {
+            Tag::String(name) if dimension_type.eq(name) => {}
+            _ => return None,
+        }
+        match compound_entry(compound_entry(value, "element")?, "height")? {
+            Tag::Int(height) => Some(*height),
+            _ => None,
+        }
+    })
+}
+
+fn compound_entry<'a>(tag: &'a Tag, name: &str) -> Option<&'a Tag> {
+    match tag {
+        Tag::Compound(entries) => entries
+            .iter()
+            .find(|entry| name.eq(&entry.name))
+            .map(|entry| &entry.payload),
+        _ => None,
+    }
+}
+
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn read<T: Deserialize>(&mut self) -> Result<T, DeserializeErr> {
+        let deserialized = T::mc_deserialize(self.0)?;
+        self.0 = deserialized.data;
+
+        Ok(deserialized.value)
+    }
+
+    fn skip(&mut self, length: usize) -> Result<(), DeserializeErr> {
+        if self.0.len() < length {
+            return Err(DeserializeErr::Eof);
+        }
+        self.0 = &self.0[length..];
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_player_info_roundtrip() {
+        let entries = vec![
+            PlayerInfoEntry {
+                uuid: UUID4::random(),
+                name: "yaufs".to_owned(),
+                properties: vec![LoginSuccessPropertiesSpec {
+                    name: "textures".to_owned(),
+                    value: "e30=".to_owned(),
+                    signed: true,
+                    signature: "c2lnbmF0dXJl".to_owned(),
+                }],
+                latency: 42,
+            },
+            PlayerInfoEntry {
+                uuid: UUID4::random(),
+                name: "steve".to_owned(),
+                properties: vec![],
+                latency: 0,
+            },
+        ];
+
+        let update = player_info_add(entries.as_slice()).unwrap();
+        let added = player_info_added(update.data.data.as_slice()).unwrap();
+        assert_eq!(
+            added,
+            entries
+                .iter()
+                .map(|entry| entry.uuid)
+                .collect::<Vec<UUID4>>()
+        );
+    }
+
+    #[test]
+    fn test_strip_chat_signature() {
+        let mut serializer = BytesSerializer::default();
+        "hello".to_owned().mc_serialize(&mut serializer).unwrap();
+        42i64.mc_serialize(&mut serializer).unwrap();
+        7i64.mc_serialize(&mut serializer).unwrap();
+        true.mc_serialize(&mut serializer).unwrap();
+        let mut data = serializer.into_bytes();
+        data.extend_from_slice(&[1; SIGNATURE_LENGTH]);
+        // message count and acknowledgements
+        data.extend_from_slice(&[3, 0, 0, 0]);
+
+        assert!(chat_signed(data.as_slice()).unwrap());
+        let stripped = strip_chat_signature(data.as_slice()).unwrap();
+        assert!(!chat_signed(stripped.as_slice()).unwrap());
+        assert_eq!(chat_message(stripped.as_slice()).unwrap(), "hello");
+        assert_eq!(stripped.len(), data.len() - SIGNATURE_LENGTH);
+        assert_eq!(&stripped[stripped.len() - 4..], &[3, 0, 0, 0]);
+    }
+}
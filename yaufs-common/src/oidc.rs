@@ -17,8 +17,12 @@
 use crate::error::{Result, YaufsError};
 use crate::map_internal_error;
 use cached::proc_macro::once;
+use openidconnect::http::header::{AUTHORIZATION, CONTENT_TYPE};
+use openidconnect::http::{HeaderMap, HeaderValue, Method};
 use openidconnect::reqwest::async_http_client;
-use openidconnect::{IssuerUrl, Scope, TokenIntrospectionResponse};
+use openidconnect::url::Url;
+use openidconnect::{HttpRequest, IssuerUrl, Scope, TokenIntrospectionResponse};
+use serde::Deserialize;
 use zitadel::credentials::{Application, AuthenticationOptions, ServiceAccount};
 use zitadel::oidc::discovery::ZitadelProviderMetadata;
 use zitadel::oidc::introspection::{AuthorityAuthentication, ZitadelIntrospectionResponse};
@@ -55,6 +59,39 @@ pub async fn obtain_access_token(
     format!("Bearer {access_token}")
 }
 
+/// Same as [`obtain_access_token`], but the token grants access to the apis of zitadel itself.
+#[once(time = 1800)]
+pub async fn obtain_api_access_token(service_account: &ServiceAccount, issuer: &str) -> String {
+    let span = tracing::info_span!("Authenticating on OIDC provider for api access");
+    let _ = span.enter();
+
+    let authentication_options = AuthenticationOptions {
+        api_access: true,
+        scopes: Vec::new(),
+        roles: Vec::new(),
+        project_audiences: Vec::new(),
+    };
+    let access_token = service_account
+        .authenticate_with_options(issuer, &authentication_options)
+        .await
+        .unwrap();
+    format!("Bearer {access_token}")
+}
+
+#[derive(Deserialize)]
+struct UserGrantsResponse {
+    #[serde(default)]
+    result: Vec<UserGrant>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct UserGrant {
+    project_id: String,
+    #[serde(default)]
+    role_keys: Vec<String>,
+}
+
 impl OIDCClient {
     /// Create a new instance based on the set env variables. This will panic if they're set in an
     /// incompatible matter since the security of all applications rely on it.
@@ -122,6 +159,65 @@ impl OIDCClient {
         .await)
     }
 
+    /// Fetch the roles granted to a user in the project of this client. This requires the
+    /// service account to be allowed to read the user grants of the organization.
+    #[tracing::instrument(skip(self), err)]
+    pub async fn user_roles(&self, user_id: &str) -> Result<Vec<String>> {
+        let access_token =
+            obtain_api_access_token(&self.service_account, self.issuer.as_str()).await;
+        let url = map_internal_error!(
+            Url::parse(
+                format!(
+                    "{}/management/v1/users/grants/_search",
+                    self.issuer.trim_end_matches('/')
+                )
+                .as_str()
+            ),
+            "Invalid issuer url"
+        )?;
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            AUTHORIZATION,
+            map_internal_error!(
+                HeaderValue::from_str(access_token.as_str()),
+                "Invalid access token"
+            )?,
+        );
+        headers.insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+        let body = serde_json::to_vec(&serde_json::json!({
+            "queries": [{ "userIdQuery": { "userId": user_id } }]
+        }))?;
+
+        let response = map_internal_error!(
+            async_http_client(HttpRequest {
+                url,
+                method: Method::POST,
+                headers,
+                body,
+            })
+            .await,
+            "Error occurred while searching the user grants"
+        )?;
+        if !response.status_code.is_success() {
+            return Err(YaufsError::InternalServerError(format!(
+                "Searching the user grants returned {}",
+                response.status_code
+            )));
+        }
+
+        let grants = serde_json::from_slice::<UserGrantsResponse>(response.body.as_slice())?;
+        Ok(grants
+            .result
+            .into_iter()
+            .filter(|grant| {
+                self.authentication_options
+                    .project_audiences
+                    .contains(&grant.project_id)
+            })
+            .flat_map(|grant| grant.role_keys)
+            .collect())
+    }
+
     /// Introspect a given access token. This validates the integrity of an sent access token
     /// on the oidc provider. Caching is not supported due the ability of revocation.
     #[tracing::instrument(skip_all, err)]
@@ -0,0 +1,372 @@
+/*
+ *    Copyright  2023.  Fritz Ochsmann
+ *
+ *    Licensed under the Apache License, Version 2.0 (the "License");
+ *    you may not use this file except in compliance with the License.
+ *    You may obtain a copy of the License at
+ *
+ *        http://www.apache.org/licenses/LICENSE-2.0
+ *
+ *    Unless required by applicable law or agreed to in writing, software
+ *    distributed under the License is distributed on an "AS IS" BASIS,
+ *    WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ *    See the License for the specific language governing permissions and
+ *    limitations under the License.
+ */
+
+use crate::controller::crd::instance::{Instance, InstancePhase};
+use crate::controller::crd::template::{AutoscalingPolicy, Template};
+use crate::controller::lease::Lease;
+use crate::controller::lifecycle;
+use crate::controller::{ControlPlaneError, ControllerContext};
+use chrono::{DateTime, Utc};
+use fluvio::dataplane::record::ConsumerRecord;
+use fluvio::Offset;
+use futures::StreamExt;
+use kube::api::{ListParams, Patch, PatchParams};
+use kube::Api;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::RwLock;
+use yaufs_common::error::YaufsError;
+use yaufs_common::fluvio_err;
+use yaufs_common::skytable::actions::AsyncActions;
+use yaufs_common::skytable::ddl::AsyncDdl;
+use yaufs_common::skytable::pool::AsyncPool;
+use yaufs_common::yaufs_proto::fluvio::{PlayerJoined, PlayerLeft, YaufsEvent};
+
+const EVALUATION_INTERVAL: Duration = Duration::from_secs(30);
+// the delay before the event stream is opened again, doubled on every failed attempt
+const RECONNECT_DELAY: Duration = Duration::from_secs(1);
+const MAX_RECONNECT_DELAY: Duration = Duration::from_secs(60);
+// the time of the last scaling of a template, stored on the template so the cooldown survives
+// a takeover of the lease
+const LAST_SCALE_ANNOTATION: &str = "yaufs.io/last-scale";
+// only the replica holding the lease evaluates the policies, which is taken over by another
+// replica after three missed evaluations
+const LEASE: &str = "yaufs-autoscaler";
+const LEASE_DURATION: Duration = Duration::from_secs(90);
+// the sessions stored by the proxies
+const SESSIONS: &str = "default:sessions";
+
+// players mapped to the address of their backend
+type Presence = Arc<RwLock<HashMap<String, String>>>;
+
+/// Start and stop instances of the templates with an autoscaling policy. The players of the
+/// instances are tracked through the events of the proxies.
+pub fn start(context: Arc<ControllerContext>) {
+    let presence = Presence::default();
+
+    let consumer = presence.clone();
+    let pool = context.skytable.clone();
+    tokio::spawn(track_presence(consumer, pool));
+
+    let lease = Lease::new(context.kube_client.clone(), LEASE, LEASE_DURATION);
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(EVALUATION_INTERVAL);
+        loop {
+            interval.tick().await;
+            match lease.acquire().await {
+                Ok(true) => {}
+                Ok(false) => continue,
+                Err(error) => {
+                    warn!("Error while acquiring the autoscaler lease: {:?}", error);
+                    continue;
+                }
+            }
+            if let Err(error) = evaluate(context.as_ref(), &presence).await {
+                warn!("Error while evaluating the autoscaling: {:?}", error);
+            }
+        }
+    });
+}
+
+/// Track the players of the backends for the lifetime of the control plane. The stream of events
+/// is opened again whenever it ends, the sessions are reloaded to cover the events missed.
+async fn track_presence(presence: Presence, skytable: AsyncPool) {
+    let mut delay = RECONNECT_DELAY;
+    loop {
+        // the players already connected are only known to the session store
+        match load_sessions(&skytable).await {
+            Ok(sessions) => *presence.write().await = sessions,
+            Err(error) => warn!("Error while loading the sessions: {:?}", error),
+        }
+
+        match consume_events(&presence, &mut delay).await {
+            Ok(()) => warn!("The event stream ended, reconnecting in {:?}", delay),
+            Err(error) => warn!(
+                "Error while tracking the players, reconnecting in {:?}: {:?}",
+                delay, error
+            ),
+        }
+        tokio::time::sleep(delay).await;
+        delay = (delay * 2).min(MAX_RECONNECT_DELAY);
+    }
+}
+
+async fn consume_events(presence: &Presence, delay: &mut Duration) -> Result<(), YaufsError> {
+    let consumer = yaufs_common::fluvio_util::consumer().await?;
+    let mut stream = fluvio_err!(consumer.stream(Offset::end()).await)?;
+    while let Some(record) = stream.next().await {
+        let record: ConsumerRecord = fluvio_err!(record)?;
+        // the stream works again, so the next reconnect starts with the initial delay
+        *delay = RECONNECT_DELAY;
+        let event = match record.key() {
+            Some(key) => String::from_utf8_lossy(key),
+            None => continue,
+        };
+
+        match event.as_ref() {
+            YaufsEvent::PLAYER_JOINED => {
+                match serde_json::from_slice::<PlayerJoined>(record.value()) {
+                    Ok(data) => {
+                        presence.write().await.insert(data.uuid, data.backend);
+                    }
+                    Err(error) => warn!("Skipping malformed {} event: {:?}", event, error),
+                }
+            }
+            YaufsEvent::PLAYER_LEFT => match serde_json::from_slice::<PlayerLeft>(record.value()) {
+                Ok(data) => {
+                    presence.write().await.remove(&data.uuid);
+                }
+                Err(error) => warn!("Skipping malformed {} event: {:?}", event, error),
+            },
+            _ => {}
+        }
+    }
+
+    Ok(())
+}
+
+async fn load_sessions(skytable: &AsyncPool) -> Result<HashMap<String, String>, YaufsError> {
+    let mut connection = skytable.get().await?;
+    connection.switch(SESSIONS).await?;
+
+    let mut sessions = HashMap::new();
+    for key in connection.lskeys::<Vec<String>>(10000).await? {
+        let raw = connection.get::<String>(key.as_str()).await?;
+        let player = serde_json::from_str::<PlayerJoined>(raw.as_str())?;
+        sessions.insert(key, player.backend);
+    }
+
+    Ok(sessions)
+}
+
+#[tracing::instrument(skip_all)]
+async fn evaluate(
+    context: &ControllerContext,
+    presence: &Presence,
+) -> Result<(), ControlPlaneError> {
+    let client = &context.kube_client;
+    let templates = Api::<Template>::all(client.clone())
+        .list(&ListParams::default())
+        .await?;
+    let instances = context
+        .namespaces
+        .list::<Instance>(client, &ListParams::default())
+        .await?
+        .into_iter()
+        .filter(|instance| instance.metadata.deletion_timestamp.is_none())
+        .collect::<Vec<Instance>>();
+
+    // count the players of each backend host
+    let mut hosts = HashMap::<String, usize>::new();
+    for backend in presence.read().await.values() {
+        *hosts.entry(host(backend).to_owned()).or_default() += 1;
+    }
+
+    for template in templates.items.iter() {
+        let (id, policy) = match (template.spec.id(), template.spec.autoscaling()) {
+            (Some(id), Some(policy)) => (id, policy),
+            _ => continue,
+        };
+
+        let instances = instances
+            .iter()
+            .filter(|instance| id.eq(&instance.spec.template().to_string()))
+            .map(|instance| (instance, players(instance, &hosts)))
+            .collect::<Vec<(&Instance, usize)>>();
+        let current = instances.len();
+        let desired = desired_instances(policy, instances.iter().map(|(_, players)| players).sum());
+
+        if desired > current {
+            info!(
+                "Scaling template {} up from {} to {} instances",
+                id, current, desired
+            );
+            let namespace = context
+                .namespaces
+                .resolve(None, template.spec.namespace())?;
+            let started = (current..desired)
+                .map(|_| lifecycle::record(id, 1, None, namespace.clone()))
+                .collect::<Vec<crate::prelude::Instance>>();
+            lifecycle::start_instances(
+                &context.skytable,
+                context.kube_client.clone(),
+                id,
+                started.as_slice(),
+            )
+            .await?;
+            scaled(context, template).await?;
+        } else if desired < current {
+            let cooldown = chrono::Duration::seconds(policy.scale_down_cooldown as i64);
+            if last_scale(template).map_or(false, |last| Utc::now() - last < cooldown) {
+                continue;
+            }
+
+            // only empty instances are stopped, the ones not running yet first
+            let mut empty = instances
+                .iter()
+                .filter(|(_, players)| *players == 0)
+                .map(|(instance, _)| *instance)
+                .collect::<Vec<&Instance>>();
+            empty.sort_by_key(|instance| {
+                instance
+                    .status
+                    .as_ref()
+                    .map_or(false, |status| status.phase == InstancePhase::Running)
+            });
+            let count = empty.len().min(current - desired);
+            if count == 0 {
+                continue;
+            }
+
+            info!(
+                "Scaling template {} down from {} to {} instances",
+                id,
+                current,
+                current - count
+            );
+            for instance in empty.into_iter().take(count) {
+                lifecycle::stop_instance(
+                    &context.skytable,
+                    context.kube_client.clone(),
+                    id,
+                    instance.metadata.name.as_deref().expect("name on metadata"),
+                    instance
+                        .metadata
+                        .namespace
+                        .as_deref()
+                        .expect("namespace on metadata"),
+                )
+                .await?;
+            }
+            scaled(context, template).await?;
+        }
+    }
+
+    Ok(())
+}
+
+// the time of the last scaling of the template by any replica of the control plane
+fn last_scale(template: &Template) -> Option<DateTime<Utc>> {
+    let annotation = template
+        .metadata
+        .annotations
+        .as_ref()?
+        .get(LAST_SCALE_ANNOTATION)?;
+
+    DateTime::parse_from_rfc3339(annotation)
+        .ok()
+        .map(|time| time.with_timezone(&Utc))
+}
+
+async fn scaled(context: &ControllerContext, template: &Template) -> Result<(), ControlPlaneError> {
+    let patch = serde_json::json!({
+        "metadata": {
+            "annotations": {
+                LAST_SCALE_ANNOTATION: Utc::now().to_rfc3339(),
+            },
+        },
+    });
+    Api::<Template>::namespaced(
+        context.kube_client.clone(),
+        template
+            .metadata
+            .namespace
+            .as_deref()
+            .expect("namespace on metadata"),
+    )
+    .patch(
+        template.metadata.name.as_deref().expect("name on metadata"),
+        &PatchParams::default(),
+        &Patch::Merge(&patch),
+    )
+    .await?;
+
+    Ok(())
+}
+
+/// The number of instances required for the players of a template.
+fn desired_instances(policy: &AutoscalingPolicy, players: usize) -> usize {
+    let target = policy.target_players.max(1) as usize;
+    let desired = (players + target - 1) / target;
+
+    desired.clamp(policy.min_instances as usize, policy.max_instances as usize)
+}
+
+// the host of a backend address, which may contain a port
+fn host(backend: &str) -> &str {
+    backend.rsplit_once(':').map_or(backend, |(host, _)| host)
+}
+
+// players are connected either through the address of the service or a pod directly
+fn players(instance: &Instance, hosts: &HashMap<String, usize>) -> usize {
+    instance.status.as_ref().map_or(0, |status| {
+        status
+            .pod_ips
+            .iter()
+            .map(String::as_str)
+            .chain(status.address.as_deref().map(host))
+            .filter_map(|host| hosts.get(host))
+            .sum()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_desired_instances() {
+        let policy = AutoscalingPolicy {
+            min_instances: 1,
+            max_instances: 4,
+            target_players: 20,
+            scale_down_cooldown: 300,
+        };
+
+        assert_eq!(desired_instances(&policy, 0), 1);
+        assert_eq!(desired_instances(&policy, 20), 1);
+        assert_eq!(desired_instances(&policy, 21), 2);
+        assert_eq!(desired_instances(&policy, 500), 4);
+    }
+
+    #[test]
+    fn test_last_scale() {
+        let mut template = Template::new(
+            "test",
+            serde_json::from_value(serde_json::json!({ "image": "test-image" })).unwrap(),
+        );
+        assert_eq!(last_scale(&template), None);
+
+        template.metadata.annotations = Some(
+            [(
+                LAST_SCALE_ANNOTATION.to_owned(),
+                "2023-04-01T12:00:00+00:00".to_owned(),
+            )]
+            .into(),
+        );
+        assert_eq!(
+            last_scale(&template).map(|time| time.timestamp()),
+            Some(1680350400)
+        );
+    }
+
+    #[test]
+    fn test_host() {
+        assert_eq!(host("10.0.0.1:25565"), "10.0.0.1");
+        assert_eq!(host("10.0.0.1"), "10.0.0.1");
+    }
+}
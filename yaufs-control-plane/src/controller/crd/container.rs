@@ -0,0 +1,297 @@
+/*
+ *    Copyright  2023.  Fritz Ochsmann
+ *
+ *    Licensed under the Apache License, Version 2.0 (the "License");
+ *    you may not use this file except in compliance with the License.
+ *    You may obtain a copy of the License at
+ *
+ *        http://www.apache.org/licenses/LICENSE-2.0
+ *
+ *    Unless required by applicable law or agreed to in writing, software
+ *    distributed under the License is distributed on an "AS IS" BASIS,
+ *    WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ *    See the License for the specific language governing permissions and
+ *    limitations under the License.
+ */
+
+use crate::controller::crd::instance::OverridesSpec;
+use serde_json::{json, Map, Value};
+use yaufs_common::yaufs_proto::template_service_v1::{
+    Container, EnvVar, Probe, ProbeType, Protocol, Resources, Template,
+};
+
+// the port the game servers are listening on
+pub const GAME_PORT: i32 = 25565;
+
+/// Build the container of an instance of the template. Templates without a container only
+/// define the image. The env and the resources of the overrides replace the ones of the template.
+pub fn container(template: &Template, overrides: Option<&OverridesSpec>) -> Value {
+    let default = Container::default();
+    let spec = template.container.as_ref().unwrap_or(&default);
+    let overrides = overrides.cloned().unwrap_or_default();
+
+    // the game port is exposed by the service, so it has to exist on every container
+    let mut ports = vec![json!({
+        "name": "minecraft",
+        "containerPort": GAME_PORT,
+        "protocol": "TCP",
+    })];
+    ports.extend(
+        spec.ports
+            .iter()
+            .filter(|port| port.port != GAME_PORT)
+            .map(|port| {
+                let protocol = match port.protocol() {
+                    Protocol::Tcp => "TCP",
+                    Protocol::Udp => "UDP",
+                };
+                json!({
+                    "name": port.name.as_str(),
+                    "containerPort": port.port,
+                    "protocol": protocol,
+                })
+            }),
+    );
+
+    let mut container = Map::new();
+    container.insert("name".to_owned(), json!("instance"));
+    container.insert("image".to_owned(), json!(template.image.as_str()));
+    container.insert("ports".to_owned(), Value::Array(ports));
+    if !spec.command.is_empty() {
+        container.insert("command".to_owned(), json!(spec.command));
+    }
+    if !spec.args.is_empty() {
+        container.insert("args".to_owned(), json!(spec.args));
+    }
+    let variables = spec
+        .env
+        .iter()
+        .filter(|env| !overrides.env.contains_key(&env.name))
+        .map(env)
+        .chain(
+            overrides
+                .env
+                .iter()
+                .map(|(name, value)| json!({ "name": name, "value": value })),
+        )
+        .collect::<Vec<Value>>();
+    if !variables.is_empty() {
+        container.insert("env".to_owned(), Value::Array(variables));
+    }
+    let mut requirements = spec.resources.clone();
+    if let Some(replaced) = overrides.resources.as_ref() {
+        let requirements = requirements.get_or_insert_with(Resources::default);
+        for (quantity, replaced) in [
+            (&mut requirements.cpu_request, &replaced.requests.cpu),
+            (&mut requirements.cpu_limit, &replaced.limits.cpu),
+            (&mut requirements.memory_request, &replaced.requests.memory),
+            (&mut requirements.memory_limit, &replaced.limits.memory),
+        ] {
+            if replaced.is_some() {
+                *quantity = replaced.clone();
+            }
+        }
+    }
+    if let Some(value) = requirements.as_ref() {
+        container.insert("resources".to_owned(), resources(value));
+    }
+    if let Some(value) = spec.readiness_probe.as_ref() {
+        container.insert("readinessProbe".to_owned(), probe(value));
+    }
+    if let Some(value) = spec.liveness_probe.as_ref() {
+        container.insert("livenessProbe".to_owned(), probe(value));
+    }
+
+    Value::Object(container)
+}
+
+fn env(env: &EnvVar) -> Value {
+    match env.secret.as_ref() {
+        Some(secret) => json!({
+            "name": env.name.as_str(),
+            "valueFrom": {
+                "secretKeyRef": {
+                    "name": secret.name.as_str(),
+                    "key": secret.key.as_str(),
+                },
+            },
+        }),
+        None => json!({
+            "name": env.name.as_str(),
+            "value": env.value.as_str(),
+        }),
+    }
+}
+
+fn resources(resources: &Resources) -> Value {
+    let quantities = |cpu: &Option<String>, memory: &Option<String>| {
+        let mut quantities = Map::new();
+        if let Some(cpu) = cpu {
+            quantities.insert("cpu".to_owned(), json!(cpu));
+        }
+        if let Some(memory) = memory {
+            quantities.insert("memory".to_owned(), json!(memory));
+        }
+        Value::Object(quantities)
+    };
+
+    json!({
+        "requests": quantities(&resources.cpu_request, &resources.memory_request),
+        "limits": quantities(&resources.cpu_limit, &resources.memory_limit),
+    })
+}
+
+fn probe(probe: &Probe) -> Value {
+    let port = probe.port.unwrap_or(GAME_PORT);
+    let mut value = match probe.r#type() {
+        // kubernetes has no probe speaking the protocol of the game, so the status ping is
+        // sent from within the container
+        ProbeType::Minecraft => json!({
+            "exec": {
+                "command": [
+                    "mc-monitor",
+                    "status",
+                    "--host",
+                    "127.0.0.1",
+                    "--port",
+                    port.to_string(),
+                ],
+            },
+        }),
+        ProbeType::Tcp => json!({
+            "tcpSocket": {
+                "port": port,
+            },
+        }),
+        ProbeType::Http => json!({
+            "httpGet": {
+                "path": probe.path.as_str(),
+                "port": port,
+            },
+        }),
+        ProbeType::Exec => json!({
+            "exec": {
+                "command": probe.command,
+            },
+        }),
+    };
+
+    // unset values fall back to the defaults of kubernetes
+    for (key, seconds) in [
+        ("initialDelaySeconds", probe.initial_delay_seconds),
+        ("periodSeconds", probe.period_seconds),
+        ("timeoutSeconds", probe.timeout_seconds),
+        ("failureThreshold", probe.failure_threshold),
+    ] {
+        if seconds > 0 {
+            value[key] = json!(seconds);
+        }
+    }
+
+    value
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::controller::crd::template::{ResourceQuantities, ResourcesSpec};
+    use yaufs_common::yaufs_proto::template_service_v1::{ContainerPort, SecretKeySelector};
+
+    #[test]
+    fn test_container() {
+        let template = Template {
+            image: "test-image".to_owned(),
+            container: Some(Container {
+                ports: vec![
+                    ContainerPort {
+                        name: "game".to_owned(),
+                        port: GAME_PORT,
+                        ..Default::default()
+                    },
+                    ContainerPort {
+                        name: "metrics".to_owned(),
+                        port: 9100,
+                        ..Default::default()
+                    },
+                ],
+                env: vec![EnvVar {
+                    name: "RCON_PASSWORD".to_owned(),
+                    secret: Some(SecretKeySelector {
+                        name: "rcon".to_owned(),
+                        key: "password".to_owned(),
+                    }),
+                    ..Default::default()
+                }],
+                readiness_probe: Some(Probe {
+                    period_seconds: 5,
+                    ..Default::default()
+                }),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+
+        let container = container(&template, None);
+        let ports = container["ports"].as_array().unwrap();
+        assert_eq!(ports.len(), 2);
+        assert_eq!(ports[1]["containerPort"], 9100);
+        assert_eq!(ports[1]["protocol"], "TCP");
+        assert_eq!(
+            container["env"][0]["valueFrom"]["secretKeyRef"]["key"],
+            "password"
+        );
+        assert_eq!(
+            container["readinessProbe"]["exec"]["command"][0],
+            "mc-monitor"
+        );
+        assert_eq!(container["readinessProbe"]["periodSeconds"], 5);
+        assert!(container.get("livenessProbe").is_none());
+    }
+
+    #[test]
+    fn test_container_overrides() {
+        let template = Template {
+            image: "test-image".to_owned(),
+            container: Some(Container {
+                env: vec![
+                    EnvVar {
+                        name: "MODE".to_owned(),
+                        value: "survival".to_owned(),
+                        ..Default::default()
+                    },
+                    EnvVar {
+                        name: "MOTD".to_owned(),
+                        value: "hello".to_owned(),
+                        ..Default::default()
+                    },
+                ],
+                resources: Some(Resources {
+                    cpu_limit: Some("1".to_owned()),
+                    memory_limit: Some("2Gi".to_owned()),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        let mut overrides = OverridesSpec::default();
+        overrides
+            .env
+            .insert("MODE".to_owned(), "tournament".to_owned());
+        overrides.resources = Some(ResourcesSpec {
+            limits: ResourceQuantities {
+                memory: Some("4Gi".to_owned()),
+                ..Default::default()
+            },
+            ..Default::default()
+        });
+
+        let container = container(&template, Some(&overrides));
+        let env = container["env"].as_array().unwrap();
+        assert_eq!(env.len(), 2);
+        assert_eq!(env[0]["name"], "MOTD");
+        assert_eq!(env[1]["value"], "tournament");
+        assert_eq!(container["resources"]["limits"]["cpu"], "1");
+        assert_eq!(container["resources"]["limits"]["memory"], "4Gi");
+    }
+}
@@ -14,50 +14,207 @@
  *    limitations under the License.
  */
 
+use crate::controller::crd::container::{container, GAME_PORT};
+use crate::controller::crd::template::{ResourceQuantities, ResourcesSpec};
 use crate::controller::crd::{apply_finalizer, remove_finalizer, ActionDeterminable, CRDAction};
-use crate::controller::{default_error_policy, ControlPlaneError, ControllerContext, INSTANCE};
+use crate::controller::{default_error_policy, ControlPlaneError, ControllerContext, Namespaces};
 use futures::StreamExt;
 use k8s_openapi::api::apps::v1::Deployment;
-use kube::api::{DeleteParams, ListParams, PostParams};
+use k8s_openapi::api::core::v1::{ConfigMap, PersistentVolumeClaim, Pod, Service};
+use kube::api::{DeleteParams, ListParams, Patch, PatchParams, PostParams};
 use kube::runtime::controller::Action;
 use kube::runtime::Controller;
-use kube::{Api, Client};
+use kube::{Api, Client, Resource};
+use std::collections::{BTreeMap, HashMap};
 use std::sync::Arc;
 use tonic::{Request, Response};
 use yaufs_common::database::id::Id;
 use yaufs_common::error::YaufsError;
 use yaufs_common::fluvio_err;
+use yaufs_common::skytable::actions::AsyncActions;
+use yaufs_common::skytable::ddl::AsyncDdl;
 use yaufs_common::yaufs_proto::fluvio::{InstanceDeployed, InstanceStopped, YaufsEvent};
-use yaufs_common::yaufs_proto::template_service_v1::{Template, TemplateId};
+use yaufs_common::yaufs_proto::template_service_v1::{
+    ReclaimPolicy, Storage, Template, TemplateId,
+};
+
+// the field manager of the server side applied resources
+const FIELD_MANAGER: &str = "yaufs-control-plane";
+const WORLD_VOLUME: &str = "world";
+const CONFIG_VOLUME: &str = "config";
+// labels of the resources pointing to the instance crd owning them
+pub const INSTANCE_LABEL: &str = "yaufs.io/instance";
+pub const INSTANCE_NAMESPACE_LABEL: &str = "yaufs.io/instance-namespace";
+// retained claims outlive their instance and are adopted by the next instance of the template
+const RETAINED_LABEL: &str = "yaufs.io/retained";
+const TEMPLATE_ANNOTATION: &str = "yaufs.io/template";
 
 #[derive(Serialize, Deserialize, CustomResource, Debug, Clone, JsonSchema)]
 #[kube(group = "yaufs.io", version = "v1alpha1", kind = "Instance")]
-#[kube(namespaced)]
+#[kube(namespaced, status = "InstanceStatus")]
+#[kube(
+    scale = r#"{"specReplicasPath":".spec.replicas","statusReplicasPath":".status.readyReplicas","labelSelectorPath":".status.selector"}"#
+)]
 pub struct InstanceSpec {
     // this is the id of the template
     template: Id,
-    replicas: u8,
+    replicas: i32,
+    #[serde(default)]
+    overrides: Option<OverridesSpec>,
+}
+
+impl InstanceSpec {
+    pub fn template(&self) -> &Id {
+        &self.template
+    }
+}
+
+/// Changes of a single instance merged on top of its template at reconcile.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct OverridesSpec {
+    /// added to the env of the template, replacing variables of the same name
+    #[serde(default)]
+    pub env: BTreeMap<String, String>,
+    /// replaces the resources of the template
+    pub resources: Option<ResourcesSpec>,
+    /// added to the labels of the pods
+    #[serde(default)]
+    pub labels: BTreeMap<String, String>,
+    #[serde(default)]
+    pub node_selector: BTreeMap<String, String>,
+    /// files mounted into the container, keyed by their absolute path
+    #[serde(default)]
+    pub config_files: BTreeMap<String, String>,
+}
+
+impl From<&crate::prelude::InstanceOverrides> for OverridesSpec {
+    fn from(overrides: &crate::prelude::InstanceOverrides) -> Self {
+        let resources = ResourcesSpec {
+            requests: ResourceQuantities {
+                cpu: overrides.cpu_request.clone(),
+                memory: overrides.memory_request.clone(),
+            },
+            limits: ResourceQuantities {
+                cpu: overrides.cpu_limit.clone(),
+                memory: overrides.memory_limit.clone(),
+            },
+        };
+        let resources = [
+            &overrides.cpu_request,
+            &overrides.cpu_limit,
+            &overrides.memory_request,
+            &overrides.memory_limit,
+        ]
+        .iter()
+        .any(|quantity| quantity.is_some())
+        .then_some(resources);
+
+        Self {
+            env: overrides.env.clone().into_iter().collect(),
+            resources,
+            labels: overrides.labels.clone().into_iter().collect(),
+            node_selector: overrides.node_selector.clone().into_iter().collect(),
+            config_files: overrides.config_files.clone().into_iter().collect(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq, JsonSchema)]
+pub enum InstancePhase {
+    #[default]
+    Pending,
+    Running,
+    Degraded,
+    Stopped,
+}
+
+/// The observed state of the deployment of an instance. The status is updated whenever the
+/// deployment changes.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct InstanceStatus {
+    pub phase: InstancePhase,
+    pub ready_replicas: i32,
+    pub pod_ips: Vec<String>,
+    pub last_error: Option<String>,
+    /// the generation of the spec the deployment was created from
+    pub observed_generation: Option<i64>,
+    /// label selector of the pods, used by the scale subresource
+    pub selector: Option<String>,
+    /// the stable address of the instance within the cluster
+    pub address: Option<String>,
+}
+
+impl Instance {
+    /// Write the replicas and the status of the crd into the instance message, since both may
+    /// be changed through kubernetes directly. Records stored without a namespace receive the
+    /// one of the crd.
+    pub fn describe(&self, instance: &mut crate::prelude::Instance) {
+        instance.replicas = self.spec.replicas;
+        if let Some(namespace) = self.metadata.namespace.as_ref() {
+            instance.namespace = namespace.clone();
+        }
+        instance.status = self.status.as_ref().map(Into::into);
+        if let Some(address) = self
+            .status
+            .as_ref()
+            .and_then(|status| status.address.clone())
+        {
+            instance.address = Some(address);
+        }
+    }
+}
+
+impl From<&InstanceStatus> for crate::prelude::InstanceStatus {
+    fn from(status: &InstanceStatus) -> Self {
+        let phase = match status.phase {
+            InstancePhase::Pending => crate::prelude::InstancePhase::Pending,
+            InstancePhase::Running => crate::prelude::InstancePhase::Running,
+            InstancePhase::Degraded => crate::prelude::InstancePhase::Degraded,
+            InstancePhase::Stopped => crate::prelude::InstancePhase::Stopped,
+        };
+
+        Self {
+            phase: phase as i32,
+            ready_replicas: status.ready_replicas,
+            pod_ips: status.pod_ips.clone(),
+            last_error: status.last_error.clone(),
+            observed_generation: status.observed_generation.unwrap_or_default(),
+        }
+    }
 }
 
 pub async fn init(context: Arc<ControllerContext>) -> Result<(), Box<dyn std::error::Error>> {
     debug!("Starting Controller for Instances-CRD");
     let kube_client = &context.kube_client;
-    // load the crd
-    let instances = Api::<Instance>::all(kube_client.clone());
-
-    // run the controller
-    tokio::spawn(async move {
-        Controller::new(instances, ListParams::default())
-            .shutdown_on_signal()
-            .run(reconcile, default_error_policy, context)
-            .for_each(|response| async move {
-                match response {
-                    Ok(data) => info!("reconciled {:?}", data),
-                    Err(error) => warn!("reconcile failed: {}", error),
-                }
-            })
-            .await;
-    });
+    let namespaces = &context.namespaces;
+    // load the crd of every watched namespace
+    let apis = namespaces
+        .apis::<Instance>(kube_client)
+        .into_iter()
+        .zip(namespaces.apis::<Deployment>(kube_client))
+        .zip(namespaces.apis::<Service>(kube_client));
+
+    // run a controller per namespace
+    for ((instances, deployments), services) in apis {
+        let context = context.clone();
+        tokio::spawn(async move {
+            Controller::new(instances, ListParams::default())
+                // changes of the owned resources are reflected in the status of their instance
+                .owns(deployments, ListParams::default().labels(INSTANCE_LABEL))
+                .owns(services, ListParams::default().labels(INSTANCE_LABEL))
+                .shutdown_on_signal()
+                .run(reconcile, default_error_policy, context)
+                .for_each(|response| async move {
+                    match response {
+                        Ok(data) => info!("reconciled {:?}", data),
+                        Err(error) => warn!("reconcile failed: {}", error),
+                    }
+                })
+                .await;
+        });
+    }
 
     Ok(())
 }
@@ -84,43 +241,174 @@ async fn reconcile(
         .as_ref()
         .expect("namespace on metadata");
 
+    let create = matches!(action, CRDAction::Create);
     // match the event type
-    match action {
-        CRDAction::Create => {
-            info!("Starting instance {}", id);
-            create_deployment(id.as_str(), &instance, context.clone()).await?;
-            // finalize the crd
-            apply_finalizer::<Instance>(id.as_str(), namespace.as_str(), client.clone()).await?;
+    let result = match action {
+        // the desired deployment is applied on every reconcile, which leaves an unchanged
+        // deployment untouched and rolls out changes in place
+        CRDAction::Create | CRDAction::Update => {
+            match apply_deployment(id.as_str(), &instance, context.clone()).await {
+                Ok(()) => apply_service(id.as_str(), &instance, context.clone()).await,
+                error => error,
+            }
         }
         CRDAction::Delete => {
             info!("Stopping instance {}", id);
-            delete_deployment(id, context.clone()).await?;
+            delete_deployment(id, namespace, context.clone()).await?;
             // delete the finalizer
             remove_finalizer::<Instance>(id.as_str(), namespace.as_str(), client.clone()).await?;
+
+            return Ok(Action::await_change());
         }
-        CRDAction::Update => {
-            info!("Redeploying instance {}", id);
-            // delete the deployment
-            delete_deployment(id, context.clone()).await?;
-            // deploy a new instance
-            create_deployment(id.as_str(), &instance, context.clone()).await?;
-        }
+    };
+
+    if create && result.is_ok() {
+        // finalize the crd
+        apply_finalizer::<Instance>(id.as_str(), namespace.as_str(), client.clone()).await?;
     }
 
+    // keep the error in the status until the next successful reconcile
+    let last_error = result.as_ref().err().map(ToString::to_string);
+    update_status(
+        id.as_str(),
+        namespace.as_str(),
+        &instance,
+        last_error,
+        client.clone(),
+    )
+    .await?;
+    result?;
+
     Ok(Action::await_change())
 }
 
-/// Create a new deployment based on the given instance crd. The function ensures that the deployments
-/// starts and emits `YaufsEvent::INSTANCE_DEPLOYED` therefor. The integrity of the deployment as such
-/// is not given here, because this is handled by the specific controller for the `Deployment`.
+fn observed_generation(instance: &Instance) -> Option<i64> {
+    instance
+        .status
+        .as_ref()
+        .and_then(|status| status.observed_generation)
+}
+
+/// Observe the deployment, the service and the pods of the instance and write them into its
+/// status.
+#[tracing::instrument(skip(instance, client))]
+async fn update_status(
+    id: &str,
+    namespace: &str,
+    instance: &Instance,
+    error: Option<String>,
+    client: Client,
+) -> Result<(), ControlPlaneError> {
+    let deployment = Api::<Deployment>::namespaced(client.clone(), namespace)
+        .get_opt(id)
+        .await?;
+    let pods = Api::<Pod>::namespaced(client.clone(), namespace)
+        .list(&ListParams::default().labels(format!("app={id}").as_str()))
+        .await?;
+    let service = Api::<Service>::namespaced(client.clone(), namespace)
+        .get_opt(id)
+        .await?;
+
+    // the instance is the source of truth, its deployment may not be applied (yet)
+    let desired = instance.spec.replicas;
+    let deployment_status = deployment
+        .as_ref()
+        .and_then(|deployment| deployment.status.as_ref());
+    let ready = deployment_status
+        .and_then(|status| status.ready_replicas)
+        .unwrap_or_default();
+    // failures of the rollout are reported through the conditions of the deployment
+    let condition_error = deployment_status
+        .and_then(|status| status.conditions.as_ref())
+        .and_then(|conditions| {
+            conditions
+                .iter()
+                .find(|condition| {
+                    (condition.type_ == "ReplicaFailure" && condition.status == "True")
+                        || (condition.type_ == "Progressing" && condition.status == "False")
+                })
+                .and_then(|condition| condition.message.clone())
+        });
+
+    let previous = instance
+        .status
+        .as_ref()
+        .map(|status| status.phase)
+        .unwrap_or_default();
+    // a failed deployment has to be retried with the same generation
+    let generation = match error {
+        Some(_) => observed_generation(instance),
+        None => instance.metadata.generation,
+    };
+    let status = InstanceStatus {
+        phase: phase(previous, deployment.is_some(), desired, ready),
+        ready_replicas: ready,
+        pod_ips: pods
+            .items
+            .into_iter()
+            .filter_map(|pod| pod.status?.pod_ip)
+            .collect(),
+        last_error: error.or(condition_error),
+        observed_generation: generation,
+        selector: Some(format!("app={id}")),
+        address: service.map(|_| address(id, namespace)),
+    };
+
+    let patch = serde_json::json!({ "status": status });
+    Api::<Instance>::namespaced(client, namespace)
+        .patch_status(id, &PatchParams::default(), &Patch::Merge(&patch))
+        .await?;
+
+    Ok(())
+}
+
+fn phase(previous: InstancePhase, deployed: bool, desired: i32, ready: i32) -> InstancePhase {
+    if !deployed || desired == 0 {
+        InstancePhase::Stopped
+    } else if ready >= desired {
+        InstancePhase::Running
+    } else if ready > 0 {
+        InstancePhase::Degraded
+    } else {
+        // instances losing all of their pods are degraded, new ones are still pending
+        match previous {
+            InstancePhase::Running | InstancePhase::Degraded => InstancePhase::Degraded,
+            _ => InstancePhase::Pending,
+        }
+    }
+}
+
+/// Fetch all instance crds of the watched namespaces matching the parameters, mapped by their
+/// name.
+pub async fn crds(
+    namespaces: &Namespaces,
+    client: Client,
+    params: &ListParams,
+) -> yaufs_common::error::Result<HashMap<String, Instance>> {
+    let instances = namespaces
+        .list::<Instance>(&client, params)
+        .await
+        .map_err(|error| YaufsError::InternalServerError(error.to_string()))?;
+
+    Ok(instances
+        .into_iter()
+        .filter_map(|instance| Some((instance.metadata.name.clone()?, instance)))
+        .collect())
+}
+
+/// Apply the deployment of the given instance crd with server side apply. Emits
+/// `YaufsEvent::INSTANCE_DEPLOYED` if the deployment did not exist before. The integrity of the
+/// deployment as such is not given here, because this is handled by the specific controller for
+/// the `Deployment`. The deployment is created in the namespace of the crd and owned by it, so it
+/// is garbage collected together with the crd.
 #[tracing::instrument(skip_all)]
-async fn create_deployment(
+async fn apply_deployment(
     id: &str,
     instance: &Instance,
     context: Arc<ControllerContext>,
 ) -> Result<(), ControlPlaneError> {
-    tracing::warn!(
-        "Creating deployment for template '{}'",
+    debug!(
+        "Applying deployment for template '{}'",
         instance.spec.template.to_string()
     );
     // fetch the template
@@ -130,23 +418,194 @@ async fn create_deployment(
     let request = context.authorize_request(request).await?;
     let mut template_client = context.template_client.lock().await;
     let response: Response<Template> = template_client.get_template(request).await?;
+    drop(template_client);
     let template = response.into_inner();
 
     // setup the api
-    let deployments = Api::<Deployment>::namespaced(context.kube_client.clone(), INSTANCE);
+    let namespace = instance
+        .metadata
+        .namespace
+        .as_deref()
+        .expect("namespace on metadata");
+    let deployments = Api::<Deployment>::namespaced(context.kube_client.clone(), namespace);
+    let created = deployments.get_opt(id).await?.is_none();
+    // the claim has to exist before the pods are scheduled
+    let claim = match template.storage.as_ref() {
+        Some(storage) => {
+            // the claim can only be attached to a single node, so the pods can't be spread
+            if instance.spec.replicas > 1 {
+                return Err(YaufsError::InvalidArgument(
+                    "instances with storage can not have more than one replica",
+                )
+                .into());
+            }
+            Some(apply_claim(id, instance, storage, context.kube_client.clone()).await?)
+        }
+        None => None,
+    };
+    if let Some(config_map) = config_map(id, instance)? {
+        Api::<ConfigMap>::namespaced(context.kube_client.clone(), namespace)
+            .patch(
+                config_map_name(id).as_str(),
+                &PatchParams::apply(FIELD_MANAGER).force(),
+                &Patch::Apply(&config_map),
+            )
+            .await?;
+    }
+    // apply the configuration, conflicting fields of other managers are taken over
+    let deployment = deployment(id, instance, &template, claim.as_deref())?;
+    deployments
+        .patch(
+            id,
+            &PatchParams::apply(FIELD_MANAGER).force(),
+            &Patch::Apply(&deployment),
+        )
+        .await?;
+
+    if !created {
+        return Ok(());
+    }
+    info!("Deployed instance {}", id);
+
+    // emit the instance deployed event
+    fluvio_err!(
+        context
+            .producer
+            .send(
+                YaufsEvent::INSTANCE_DEPLOYED,
+                InstanceDeployed {
+                    id: id.to_string(),
+                    // TODO
+                    issuer: None,
+                },
+            )
+            .await
+    )?;
+
+    Ok(())
+}
+
+/// Apply the service giving the instance a stable address and record the address in the skytable
+/// record of the instance.
+#[tracing::instrument(skip_all)]
+async fn apply_service(
+    id: &str,
+    instance: &Instance,
+    context: Arc<ControllerContext>,
+) -> Result<(), ControlPlaneError> {
+    let namespace = instance
+        .metadata
+        .namespace
+        .as_deref()
+        .expect("namespace on metadata");
+    let service = service(id, instance)?;
+    Api::<Service>::namespaced(context.kube_client.clone(), namespace)
+        .patch(
+            id,
+            &PatchParams::apply(FIELD_MANAGER).force(),
+            &Patch::Apply(&service),
+        )
+        .await?;
+
+    let address = address(id, namespace);
+    let mut connection = context.skytable.get().await.map_err(YaufsError::from)?;
+    connection
+        .switch(format!("instances:{}", instance.spec.template.to_string()))
+        .await
+        .map_err(YaufsError::from)?;
+    // instances created through kubernetes directly have no record
+    if connection.exists(id).await.map_err(YaufsError::from)? == 0 {
+        return Ok(());
+    }
+    let mut record = connection
+        .get::<crate::prelude::Instance>(id)
+        .await
+        .map_err(YaufsError::from)?;
+    if record.address.as_ref() != Some(&address) {
+        record.address = Some(address);
+        connection
+            .update(id, &record)
+            .await
+            .map_err(YaufsError::from)?;
+    }
+
+    Ok(())
+}
+
+fn address(id: &str, namespace: &str) -> String {
+    format!("{id}.{namespace}.svc.cluster.local:{GAME_PORT}")
+}
+
+fn labels(id: &str, namespace: &str) -> serde_json::Value {
+    serde_json::json!({
+        "app": id,
+        INSTANCE_LABEL: id,
+        INSTANCE_NAMESPACE_LABEL: namespace,
+    })
+}
+
+/// Build the desired service of the instance.
+fn service(id: &str, instance: &Instance) -> Result<Service, ControlPlaneError> {
+    let namespace = instance
+        .metadata
+        .namespace
+        .as_deref()
+        .expect("namespace on metadata");
+    let owner = instance.controller_owner_ref(&()).expect("uid on metadata");
+
+    Ok(serde_json::from_value(serde_json::json!({
+        "apiVersion": "v1",
+        "kind": "Service",
+        "metadata": {
+            "name": id,
+            "namespace": namespace,
+            "ownerReferences": [owner],
+            "labels": labels(id, namespace),
+        },
+        "spec": {
+            "type": "ClusterIP",
+            "selector": {
+                "app": id,
+            },
+            "ports": [
+                {
+                    "name": "minecraft",
+                    "protocol": "TCP",
+                    "port": GAME_PORT,
+                    "targetPort": GAME_PORT,
+                }
+            ]
+        }
+    }))?)
+}
+
+/// Build the desired deployment of the instance. The overrides of the instance are merged on top
+/// of the template and the claim of its world is mounted if the template has storage.
+fn deployment(
+    id: &str,
+    instance: &Instance,
+    template: &Template,
+    claim: Option<&str>,
+) -> Result<Deployment, ControlPlaneError> {
+    let namespace = instance
+        .metadata
+        .namespace
+        .as_deref()
+        .expect("namespace on metadata");
+    let owner = instance.controller_owner_ref(&()).expect("uid on metadata");
+
     // build the yaml configuration
-    let deployment: Deployment = serde_json::from_value(serde_json::json!({
+    let mut deployment = serde_json::json!({
         "apiVersion": "apps/v1",
         "kind": "Deployment",
         "metadata": {
             "name": id,
-            "namespace": INSTANCE,
+            "namespace": namespace,
+            "ownerReferences": [owner],
             "annotations": {
                 "linkerd.io/inject": "enabled",
             },
-            "labels": {
-                "app": id,
-            },
+            "labels": labels(id, namespace),
         },
         "spec": {
             "selector": {
@@ -159,52 +618,263 @@ async fn create_deployment(
                 "metadata": {
                     "labels": {
                         "app": id,
-                    },            
+                    },
                 },
                 "spec": {
-                    "containers": [
-                        {
-                            "name": "instance",
-                            "image": template.image.as_str(),
-                        }
-                    ]
+                    "containers": [container(template, instance.spec.overrides.as_ref())]
                 }
             }
         }
-    }))?;
-    // apply the configuration
-    deployments
-        .create(&PostParams::default(), &deployment)
-        .await?;
+    });
 
-    // emit the instance deployed event
-    fluvio_err!(
-        context
-            .producer
-            .send(
-                YaufsEvent::INSTANCE_DEPLOYED,
-                InstanceDeployed {
-                    id: id.to_string(),
-                    // TODO
-                    issuer: None,
+    let mut volumes = Vec::new();
+    let mut mounts = Vec::new();
+    // the claim can only be attached to a single node. Therefore the old pod has to be stopped
+    // before the new one starts.
+    if let Some((storage, claim)) = template.storage.as_ref().zip(claim) {
+        deployment["spec"]["strategy"] = serde_json::json!({ "type": "Recreate" });
+        volumes.push(serde_json::json!({
+            "name": WORLD_VOLUME,
+            "persistentVolumeClaim": {
+                "claimName": claim,
+            },
+        }));
+        mounts.push(serde_json::json!({
+            "name": WORLD_VOLUME,
+            "mountPath": storage.mount_path.as_str(),
+        }));
+    }
+
+    if let Some(overrides) = instance.spec.overrides.as_ref() {
+        let pod = &mut deployment["spec"]["template"];
+        // the app label selects the pods of the deployment, so it is kept
+        for (key, value) in overrides.labels.iter().filter(|(key, _)| *key != "app") {
+            pod["metadata"]["labels"][key] = serde_json::json!(value);
+        }
+        if !overrides.node_selector.is_empty() {
+            pod["spec"]["nodeSelector"] = serde_json::json!(overrides.node_selector);
+        }
+
+        if !overrides.config_files.is_empty() {
+            volumes.push(serde_json::json!({
+                "name": CONFIG_VOLUME,
+                "configMap": {
+                    "name": config_map_name(id),
                 },
-            )
-            .await
-    )?;
+            }));
+            mounts.extend(config_files(&overrides.config_files).map(|(key, path, _)| {
+                serde_json::json!({
+                    "name": CONFIG_VOLUME,
+                    "mountPath": path,
+                    "subPath": key,
+                })
+            }));
+        }
+    }
 
-    Ok(())
+    if !volumes.is_empty() {
+        deployment["spec"]["template"]["spec"]["volumes"] = serde_json::json!(volumes);
+        deployment["spec"]["template"]["spec"]["containers"][0]["volumeMounts"] =
+            serde_json::json!(mounts);
+    }
+
+    Ok(serde_json::from_value(deployment)?)
+}
+
+fn config_map_name(id: &str) -> String {
+    format!("{id}-{CONFIG_VOLUME}")
+}
+
+// the keys of a config map must not contain slashes, so the files are numbered by their path
+fn config_files(
+    files: &BTreeMap<String, String>,
+) -> impl Iterator<Item = (String, &String, &String)> {
+    files
+        .iter()
+        .enumerate()
+        .map(|(index, (path, content))| (format!("file-{index}"), path, content))
+}
+
+/// Build the config map holding the config files of the overrides, if there are any.
+fn config_map(id: &str, instance: &Instance) -> Result<Option<ConfigMap>, ControlPlaneError> {
+    let files = match instance.spec.overrides.as_ref() {
+        Some(overrides) if !overrides.config_files.is_empty() => &overrides.config_files,
+        _ => return Ok(None),
+    };
+    let namespace = instance
+        .metadata
+        .namespace
+        .as_deref()
+        .expect("namespace on metadata");
+    let owner = instance.controller_owner_ref(&()).expect("uid on metadata");
+    let data = config_files(files)
+        .map(|(key, _, content)| (key, content.clone()))
+        .collect::<BTreeMap<String, String>>();
+
+    Ok(Some(serde_json::from_value(serde_json::json!({
+        "apiVersion": "v1",
+        "kind": "ConfigMap",
+        "metadata": {
+            "name": config_map_name(id),
+            "namespace": namespace,
+            "ownerReferences": [owner],
+            "labels": labels(id, namespace),
+        },
+        "data": data,
+    }))?))
+}
+
+fn claim_name(id: &str) -> String {
+    format!("{id}-{WORLD_VOLUME}")
+}
+
+/// Apply the claim of the world of the instance and return its name. A retained claim is kept
+/// once its instance is stopped and adopted by the next instance of the same template, so the
+/// world is reused instead of leaking a claim per instance.
+#[tracing::instrument(skip_all)]
+async fn apply_claim(
+    id: &str,
+    instance: &Instance,
+    storage: &Storage,
+    client: Client,
+) -> Result<String, ControlPlaneError> {
+    let namespace = instance
+        .metadata
+        .namespace
+        .as_deref()
+        .expect("namespace on metadata");
+    let claims = Api::<PersistentVolumeClaim>::namespaced(client.clone(), namespace);
+    let mut claim = persistent_volume_claim(id, claim_name(id).as_str(), instance, storage)?;
+
+    if storage.reclaim_policy() == ReclaimPolicy::Retain {
+        let retained = claims
+            .list(&ListParams::default().labels(RETAINED_LABEL))
+            .await?
+            .items;
+        let instances = Api::<Instance>::namespaced(client, namespace);
+        let template = instance.spec.template.to_string();
+        if let Some(owned) = retained
+            .iter()
+            .find(|claim| claim_instance(claim) == Some(id))
+        {
+            claim.metadata.name = owned.metadata.name.clone();
+        } else {
+            for candidate in retained
+                .iter()
+                .filter(|candidate| claim_template(candidate) == Some(template.as_str()))
+            {
+                let released = match claim_instance(candidate) {
+                    Some(owner) => instances.get_opt(owner).await?.is_none(),
+                    None => true,
+                };
+                if released {
+                    debug!(
+                        "Adopting retained claim {:?} for instance {}",
+                        candidate.metadata.name, id
+                    );
+                    // the claim may be adopted by another instance at the same time, the
+                    // resource version lets the apply fail and the reconcile retry
+                    claim.metadata.name = candidate.metadata.name.clone();
+                    claim.metadata.resource_version = candidate.metadata.resource_version.clone();
+                    break;
+                }
+            }
+        }
+    }
+
+    let name = claim.metadata.name.clone().expect("name on claim");
+    claims
+        .patch(
+            name.as_str(),
+            &PatchParams::apply(FIELD_MANAGER).force(),
+            &Patch::Apply(&claim),
+        )
+        .await?;
+
+    Ok(name)
+}
+
+fn claim_instance(claim: &PersistentVolumeClaim) -> Option<&str> {
+    claim
+        .metadata
+        .labels
+        .as_ref()?
+        .get(INSTANCE_LABEL)
+        .map(String::as_str)
+}
+
+fn claim_template(claim: &PersistentVolumeClaim) -> Option<&str> {
+    claim
+        .metadata
+        .annotations
+        .as_ref()?
+        .get(TEMPLATE_ANNOTATION)
+        .map(String::as_str)
+}
+
+/// Build the claim of the world of the instance. Retained claims are not owned by the instance,
+/// so they are not garbage collected once the instance is stopped, and are marked for adoption.
+fn persistent_volume_claim(
+    id: &str,
+    name: &str,
+    instance: &Instance,
+    storage: &Storage,
+) -> Result<PersistentVolumeClaim, ControlPlaneError> {
+    let namespace = instance
+        .metadata
+        .namespace
+        .as_deref()
+        .expect("namespace on metadata");
+    let mut labels = labels(id, namespace);
+    let mut annotations = serde_json::json!({});
+    let owners = match storage.reclaim_policy() {
+        ReclaimPolicy::Retain => {
+            labels[RETAINED_LABEL] = serde_json::json!("true");
+            annotations[TEMPLATE_ANNOTATION] = serde_json::json!(instance.spec.template);
+            Vec::new()
+        }
+        ReclaimPolicy::Delete => vec![instance.controller_owner_ref(&()).expect("uid on metadata")],
+    };
+
+    Ok(serde_json::from_value(serde_json::json!({
+        "apiVersion": "v1",
+        "kind": "PersistentVolumeClaim",
+        "metadata": {
+            "name": name,
+            "namespace": namespace,
+            "ownerReferences": owners,
+            "labels": labels,
+            "annotations": annotations,
+        },
+        "spec": {
+            "accessModes": ["ReadWriteOnce"],
+            "storageClassName": storage.storage_class.as_deref(),
+            "resources": {
+                "requests": {
+                    "storage": storage.size.as_str(),
+                },
+            },
+        }
+    }))?)
 }
 
 /// Delete a deployment identified by the given instance.
 #[tracing::instrument(skip_all)]
 async fn delete_deployment(
     id: &str,
+    namespace: &str,
     context: Arc<ControllerContext>,
 ) -> Result<(), ControlPlaneError> {
     // setup the api
-    let deployments = Api::<Deployment>::namespaced(context.kube_client.clone(), INSTANCE);
-    // delete the deployment
-    deployments.delete(id, &DeleteParams::default()).await?;
+    let deployments = Api::<Deployment>::namespaced(context.kube_client.clone(), namespace);
+    // delete the deployment, which may already be collected by the garbage collector
+    match deployments.delete(id, &DeleteParams::default()).await {
+        Ok(_) => {}
+        Err(kube::Error::Api(response)) if response.code == 404 => {
+            debug!("Deployment of instance {} is already deleted", id)
+        }
+        Err(error) => return Err(error.into()),
+    }
     info!("Starting termination of instance {}", id);
 
     // emit the instance stopped event
@@ -227,7 +897,8 @@ async fn delete_deployment(
 
 /// Create a new instance crd to interact with the controller. This function is used by the grpc
 /// endpoint of this very specific control plane. The creation will trigger a reconciliation cycle
-/// and issue the deployment.
+/// and issue the deployment. The crd is created in the namespace recorded on the instance, all
+/// resources of the instance follow it.
 #[instrument(skip(client))]
 pub async fn create_crd(
     instance: &crate::prelude::Instance,
@@ -239,17 +910,193 @@ pub async fn create_crd(
         "kind": "Instance",
         "metadata": {
             "name": instance.id.as_str(),
+            "namespace": instance.namespace.as_str(),
         },
         "spec": {
             "template": instance.template_id.as_str(),
-            "replicas": 1,
+            "replicas": instance.replicas,
+            "overrides": instance.overrides.as_ref().map(OverridesSpec::from),
         }
     }))?;
     // post the crd to the cluster
-    Api::<Instance>::all(client)
+    Api::<Instance>::namespaced(client, instance.namespace.as_str())
         .create(&PostParams::default(), &crd)
         .await
         .map_err(|error| YaufsError::InternalServerError(error.to_string()))?;
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_phase() {
+        use InstancePhase::*;
+
+        assert_eq!(phase(Pending, false, 1, 0), Stopped);
+        assert_eq!(phase(Running, true, 0, 0), Stopped);
+        assert_eq!(phase(Pending, true, 1, 0), Pending);
+        assert_eq!(phase(Pending, true, 2, 2), Running);
+        assert_eq!(phase(Running, true, 2, 1), Degraded);
+        // instances losing all of their pods stay degraded
+        assert_eq!(phase(Running, true, 1, 0), Degraded);
+    }
+
+    #[test]
+    fn test_deployment_owned_by_instance() {
+        let mut instance = Instance::new(
+            "test",
+            InstanceSpec {
+                template: Id::new(("template", "test")),
+                replicas: 2,
+                overrides: None,
+            },
+        );
+        instance.metadata.namespace = Some("games".to_owned());
+        instance.metadata.uid = Some("uid".to_owned());
+        let template = Template {
+            image: "test-image".to_owned(),
+            ..Default::default()
+        };
+
+        let deployment = deployment("test", &instance, &template, None).unwrap();
+        assert_eq!(deployment.metadata.namespace.as_deref(), Some("games"));
+        let owner = &deployment.metadata.owner_references.unwrap()[0];
+        assert_eq!(owner.uid, "uid");
+        assert_eq!(owner.controller, Some(true));
+        let spec = deployment.spec.unwrap();
+        assert_eq!(spec.replicas, Some(2));
+        let container = &spec.template.spec.unwrap().containers[0];
+        assert_eq!(container.image.as_deref(), Some("test-image"));
+    }
+
+    #[test]
+    fn test_deployment_overrides() {
+        let overrides = OverridesSpec {
+            labels: BTreeMap::from([
+                ("app".to_owned(), "other".to_owned()),
+                ("event".to_owned(), "tournament".to_owned()),
+            ]),
+            node_selector: BTreeMap::from([("pool".to_owned(), "events".to_owned())]),
+            config_files: BTreeMap::from([(
+                "/data/server.properties".to_owned(),
+                "pvp=true".to_owned(),
+            )]),
+            ..Default::default()
+        };
+        let mut instance = Instance::new(
+            "test",
+            InstanceSpec {
+                template: Id::new(("template", "test")),
+                replicas: 1,
+                overrides: Some(overrides),
+            },
+        );
+        instance.metadata.namespace = Some("games".to_owned());
+        instance.metadata.uid = Some("uid".to_owned());
+        let template = Template {
+            image: "test-image".to_owned(),
+            ..Default::default()
+        };
+
+        let pod = deployment("test", &instance, &template, None)
+            .unwrap()
+            .spec
+            .unwrap()
+            .template;
+        let labels = pod.metadata.unwrap().labels.unwrap();
+        assert_eq!(labels.get("app").map(String::as_str), Some("test"));
+        assert_eq!(labels.get("event").map(String::as_str), Some("tournament"));
+        let spec = pod.spec.unwrap();
+        assert_eq!(
+            spec.node_selector.unwrap().get("pool").map(String::as_str),
+            Some("events")
+        );
+        let mount = &spec.containers[0].volume_mounts.as_ref().unwrap()[0];
+        assert_eq!(mount.mount_path, "/data/server.properties");
+        assert_eq!(mount.sub_path.as_deref(), Some("file-0"));
+
+        let config_map = config_map("test", &instance).unwrap().unwrap();
+        assert_eq!(
+            config_map.data.unwrap().get("file-0").map(String::as_str),
+            Some("pvp=true")
+        );
+    }
+
+    #[test]
+    fn test_retained_claim_not_owned() {
+        let mut instance = Instance::new(
+            "test",
+            InstanceSpec {
+                template: Id::new(("template", "test")),
+                replicas: 1,
+                overrides: None,
+            },
+        );
+        instance.metadata.namespace = Some("games".to_owned());
+        instance.metadata.uid = Some("uid".to_owned());
+        let mut storage = Storage {
+            size: "10Gi".to_owned(),
+            mount_path: "/data".to_owned(),
+            ..Default::default()
+        };
+
+        let claim = persistent_volume_claim("test", "test-world", &instance, &storage).unwrap();
+        assert_eq!(claim.metadata.name.as_deref(), Some("test-world"));
+        assert_eq!(claim.metadata.owner_references.unwrap().len(), 1);
+
+        storage.set_reclaim_policy(ReclaimPolicy::Retain);
+        let claim = persistent_volume_claim("test", "test-world", &instance, &storage).unwrap();
+        assert!(claim
+            .metadata
+            .owner_references
+            .as_ref()
+            .map_or(true, |owners| owners.is_empty()));
+        // the next instance of the template finds the claim to adopt it
+        assert!(claim
+            .metadata
+            .labels
+            .as_ref()
+            .unwrap()
+            .contains_key(RETAINED_LABEL));
+        assert_eq!(claim_template(&claim), Some("template:test"));
+        assert_eq!(claim_instance(&claim), Some("test"));
+    }
+
+    #[test]
+    fn test_adopted_claim_mounted() {
+        let mut instance = Instance::new(
+            "test",
+            InstanceSpec {
+                template: Id::new(("template", "test")),
+                replicas: 1,
+                overrides: None,
+            },
+        );
+        instance.metadata.namespace = Some("games".to_owned());
+        instance.metadata.uid = Some("uid".to_owned());
+        let template = Template {
+            image: "test-image".to_owned(),
+            storage: Some(Storage {
+                size: "10Gi".to_owned(),
+                mount_path: "/data".to_owned(),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+
+        let deployment = deployment("test", &instance, &template, Some("previous-world")).unwrap();
+        let spec = deployment.spec.unwrap().template.spec.unwrap();
+        let claim = spec.volumes.unwrap()[0]
+            .persistent_volume_claim
+            .clone()
+            .unwrap();
+        assert_eq!(claim.claim_name, "previous-world");
+        assert_eq!(
+            spec.containers[0].volume_mounts.as_ref().unwrap()[0].mount_path,
+            "/data"
+        );
+    }
+}
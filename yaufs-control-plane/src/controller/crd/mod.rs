@@ -20,6 +20,7 @@ use kube::api::{Patch, PatchParams};
 use kube::{Api, Client, Resource};
 use serde::de::DeserializeOwned;
 
+pub mod container;
 pub mod instance;
 pub mod template;
 
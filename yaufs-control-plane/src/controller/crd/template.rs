@@ -20,10 +20,13 @@ use futures::StreamExt;
 use kube::api::{ListParams, Patch, PatchParams};
 use kube::runtime::controller::Action;
 use kube::runtime::Controller;
-use kube::Api;
+use kube::{Api, Client};
 use std::sync::Arc;
 use tonic::{Request, Response};
-use yaufs_common::yaufs_proto::template_service_v1::{CreateTemplateRequest, TemplateId};
+use yaufs_common::yaufs_proto::template_service_v1::{
+    Container, ContainerPort, CreateTemplateRequest, EnvVar, Probe, ProbeType, Protocol,
+    ReclaimPolicy, Resources, SecretKeySelector, Storage, TemplateId,
+};
 
 #[derive(Serialize, Deserialize, CustomResource, Debug, Clone, JsonSchema)]
 #[kube(group = "yaufs.io", version = "v1alpha1", kind = "Template")]
@@ -31,6 +34,266 @@ use yaufs_common::yaufs_proto::template_service_v1::{CreateTemplateRequest, Temp
 pub struct TemplateSpec {
     image: String,
     id: Option<String>,
+    autoscaling: Option<AutoscalingPolicy>,
+    storage: Option<StorageSpec>,
+    container: Option<ContainerSpec>,
+    namespace: Option<String>,
+}
+
+impl TemplateSpec {
+    /// The id of the template in the template service, set once the template is created.
+    pub fn id(&self) -> Option<&str> {
+        self.id.as_deref()
+    }
+
+    pub fn autoscaling(&self) -> Option<&AutoscalingPolicy> {
+        self.autoscaling.as_ref()
+    }
+
+    pub fn storage(&self) -> Option<&StorageSpec> {
+        self.storage.as_ref()
+    }
+
+    /// The namespace of the instances of the template, the default namespace if not set.
+    pub fn namespace(&self) -> Option<&str> {
+        self.namespace.as_deref()
+    }
+}
+
+/// Find the crd of the template with the given id in the template service.
+pub async fn find(client: Client, id: &str) -> Result<Option<Template>, kube::Error> {
+    let templates = Api::<Template>::all(client)
+        .list(&ListParams::default())
+        .await?;
+
+    Ok(templates
+        .items
+        .into_iter()
+        .find(|template| template.spec.id() == Some(id)))
+}
+
+/// Scaling of the number of instances of a template based on the players connected to them.
+/// Only instances without players are stopped.
+#[derive(Serialize, Deserialize, Debug, Clone, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct AutoscalingPolicy {
+    pub min_instances: u32,
+    pub max_instances: u32,
+    /// the average number of players per instance
+    pub target_players: u32,
+    /// the minimum time between a scaling of the template and the next scale down (in seconds)
+    #[serde(default = "default_scale_down_cooldown")]
+    pub scale_down_cooldown: u64,
+}
+
+fn default_scale_down_cooldown() -> u64 {
+    300
+}
+
+/// A persistent volume claim provisioned for every instance of the template. Instances with
+/// storage are limited to a single replica.
+#[derive(Serialize, Deserialize, Debug, Clone, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct StorageSpec {
+    /// the requested size as kubernetes quantity (e.g. 10Gi)
+    pub size: String,
+    pub storage_class: Option<String>,
+    pub mount_path: String,
+    /// keep the claim once the instance is stopped, the next instance of the template adopts it
+    #[serde(default)]
+    pub retain: bool,
+}
+
+impl From<&StorageSpec> for Storage {
+    fn from(spec: &StorageSpec) -> Self {
+        let reclaim_policy = match spec.retain {
+            true => ReclaimPolicy::Retain,
+            false => ReclaimPolicy::Delete,
+        };
+
+        Self {
+            size: spec.size.clone(),
+            storage_class: spec.storage_class.clone(),
+            mount_path: spec.mount_path.clone(),
+            reclaim_policy: reclaim_policy as i32,
+        }
+    }
+}
+
+/// The container running the game server of every instance.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ContainerSpec {
+    #[serde(default)]
+    pub command: Vec<String>,
+    #[serde(default)]
+    pub args: Vec<String>,
+    /// further ports of the container, the game port is always exposed
+    #[serde(default)]
+    pub ports: Vec<PortSpec>,
+    #[serde(default)]
+    pub env: Vec<EnvVarSpec>,
+    pub resources: Option<ResourcesSpec>,
+    pub readiness_probe: Option<ProbeSpec>,
+    pub liveness_probe: Option<ProbeSpec>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, JsonSchema)]
+pub struct PortSpec {
+    pub name: String,
+    pub port: i32,
+    #[serde(default)]
+    pub protocol: ProtocolSpec,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, JsonSchema)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum ProtocolSpec {
+    #[default]
+    Tcp,
+    Udp,
+}
+
+/// An environment variable with either a plain value or the value of a secret.
+#[derive(Serialize, Deserialize, Debug, Clone, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct EnvVarSpec {
+    pub name: String,
+    #[serde(default)]
+    pub value: String,
+    pub secret_key_ref: Option<SecretKeySpec>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, JsonSchema)]
+pub struct SecretKeySpec {
+    pub name: String,
+    pub key: String,
+}
+
+/// Quantities as used by kubernetes (e.g. 500m or 2Gi).
+#[derive(Serialize, Deserialize, Debug, Clone, Default, JsonSchema)]
+pub struct ResourcesSpec {
+    #[serde(default)]
+    pub requests: ResourceQuantities,
+    #[serde(default)]
+    pub limits: ResourceQuantities,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default, JsonSchema)]
+pub struct ResourceQuantities {
+    pub cpu: Option<String>,
+    pub memory: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ProbeSpec {
+    #[serde(rename = "type")]
+    pub kind: ProbeKind,
+    /// the probed port, the game port if not set
+    pub port: Option<i32>,
+    /// the path of http probes
+    #[serde(default)]
+    pub path: String,
+    /// the command of exec probes
+    #[serde(default)]
+    pub command: Vec<String>,
+    #[serde(default)]
+    pub initial_delay_seconds: i32,
+    #[serde(default)]
+    pub period_seconds: i32,
+    #[serde(default)]
+    pub timeout_seconds: i32,
+    #[serde(default)]
+    pub failure_threshold: i32,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum ProbeKind {
+    /// a status ping of the game server, the image has to contain mc-monitor
+    Minecraft,
+    Tcp,
+    Http,
+    Exec,
+}
+
+impl From<&ContainerSpec> for Container {
+    fn from(spec: &ContainerSpec) -> Self {
+        Self {
+            command: spec.command.clone(),
+            args: spec.args.clone(),
+            ports: spec.ports.iter().map(Into::into).collect(),
+            env: spec.env.iter().map(Into::into).collect(),
+            resources: spec.resources.as_ref().map(Into::into),
+            readiness_probe: spec.readiness_probe.as_ref().map(Into::into),
+            liveness_probe: spec.liveness_probe.as_ref().map(Into::into),
+        }
+    }
+}
+
+impl From<&PortSpec> for ContainerPort {
+    fn from(spec: &PortSpec) -> Self {
+        let protocol = match spec.protocol {
+            ProtocolSpec::Tcp => Protocol::Tcp,
+            ProtocolSpec::Udp => Protocol::Udp,
+        };
+
+        Self {
+            name: spec.name.clone(),
+            port: spec.port,
+            protocol: protocol as i32,
+        }
+    }
+}
+
+impl From<&EnvVarSpec> for EnvVar {
+    fn from(spec: &EnvVarSpec) -> Self {
+        Self {
+            name: spec.name.clone(),
+            value: spec.value.clone(),
+            secret: spec
+                .secret_key_ref
+                .as_ref()
+                .map(|secret| SecretKeySelector {
+                    name: secret.name.clone(),
+                    key: secret.key.clone(),
+                }),
+        }
+    }
+}
+
+impl From<&ResourcesSpec> for Resources {
+    fn from(spec: &ResourcesSpec) -> Self {
+        Self {
+            cpu_request: spec.requests.cpu.clone(),
+            cpu_limit: spec.limits.cpu.clone(),
+            memory_request: spec.requests.memory.clone(),
+            memory_limit: spec.limits.memory.clone(),
+        }
+    }
+}
+
+impl From<&ProbeSpec> for Probe {
+    fn from(spec: &ProbeSpec) -> Self {
+        let kind = match spec.kind {
+            ProbeKind::Minecraft => ProbeType::Minecraft,
+            ProbeKind::Tcp => ProbeType::Tcp,
+            ProbeKind::Http => ProbeType::Http,
+            ProbeKind::Exec => ProbeType::Exec,
+        };
+
+        Self {
+            r#type: kind as i32,
+            port: spec.port,
+            path: spec.path.clone(),
+            command: spec.command.clone(),
+            initial_delay_seconds: spec.initial_delay_seconds,
+            period_seconds: spec.period_seconds,
+            timeout_seconds: spec.timeout_seconds,
+            failure_threshold: spec.failure_threshold,
+        }
+    }
 }
 
 pub async fn init(context: Arc<ControllerContext>) -> Result<(), Box<dyn std::error::Error>> {
@@ -81,6 +344,8 @@ pub async fn reconcile(
             let request = Request::new(CreateTemplateRequest {
                 name: name.to_string(),
                 image: template.spec.image.clone(),
+                storage: template.spec.storage.as_ref().map(Into::into),
+                container: template.spec.container.as_ref().map(Into::into),
             });
             // append authorization header
             let request = context.authorize_request(request).await?;
@@ -0,0 +1,134 @@
+/*
+ *    Copyright  2023.  Fritz Ochsmann
+ *
+ *    Licensed under the Apache License, Version 2.0 (the "License");
+ *    you may not use this file except in compliance with the License.
+ *    You may obtain a copy of the License at
+ *
+ *        http://www.apache.org/licenses/LICENSE-2.0
+ *
+ *    Unless required by applicable law or agreed to in writing, software
+ *    distributed under the License is distributed on an "AS IS" BASIS,
+ *    WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ *    See the License for the specific language governing permissions and
+ *    limitations under the License.
+ */
+
+use crate::controller::ControlPlaneError;
+use chrono::{DateTime, Utc};
+use k8s_openapi::api::coordination::v1::{Lease as LeaseResource, LeaseSpec};
+use k8s_openapi::apimachinery::pkg::apis::meta::v1::MicroTime;
+use kube::api::{ObjectMeta, PostParams};
+use kube::{Api, Client};
+use std::time::Duration;
+
+// the namespace of the control plane, set through the downward api
+const POD_NAMESPACE: &str = "POD_NAMESPACE";
+
+/// A `coordination.k8s.io` lease electing a single replica of the control plane for work which
+/// must not run concurrently. The holder has to renew the lease within its duration, otherwise
+/// another replica takes it over.
+pub struct Lease {
+    api: Api<LeaseResource>,
+    name: &'static str,
+    identity: String,
+    duration: Duration,
+}
+
+impl Lease {
+    pub fn new(client: Client, name: &'static str, duration: Duration) -> Self {
+        let namespace = std::env::var(POD_NAMESPACE).unwrap_or_else(|_| "default".to_owned());
+        let identity = std::env::var("HOSTNAME").unwrap_or_else(|_| nanoid::nanoid!());
+
+        Self {
+            api: Api::namespaced(client, namespace.as_str()),
+            name,
+            identity,
+            duration,
+        }
+    }
+
+    /// Acquire or renew the lease. Returns whether this replica holds the lease.
+    pub async fn acquire(&self) -> Result<bool, ControlPlaneError> {
+        let now = Utc::now();
+        let renewed = LeaseSpec {
+            holder_identity: Some(self.identity.clone()),
+            lease_duration_seconds: Some(self.duration.as_secs() as i32),
+            acquire_time: Some(MicroTime(now)),
+            renew_time: Some(MicroTime(now)),
+            lease_transitions: Some(0),
+        };
+
+        let result = match self.api.get_opt(self.name).await? {
+            Some(mut lease) => {
+                let current = lease.spec.take().unwrap_or_default();
+                let held = current.holder_identity.as_deref() == Some(self.identity.as_str());
+                if !held && !expired(&current, now) {
+                    return Ok(false);
+                }
+                lease.spec = Some(match held {
+                    true => LeaseSpec {
+                        acquire_time: current.acquire_time,
+                        lease_transitions: current.lease_transitions,
+                        ..renewed
+                    },
+                    false => LeaseSpec {
+                        lease_transitions: Some(current.lease_transitions.unwrap_or_default() + 1),
+                        ..renewed
+                    },
+                });
+                // the resource version of the lease lets concurrent takeovers conflict
+                self.api
+                    .replace(self.name, &PostParams::default(), &lease)
+                    .await
+            }
+            None => {
+                let lease = LeaseResource {
+                    metadata: ObjectMeta {
+                        name: Some(self.name.to_owned()),
+                        ..ObjectMeta::default()
+                    },
+                    spec: Some(renewed),
+                };
+                self.api.create(&PostParams::default(), &lease).await
+            }
+        };
+
+        match result {
+            Ok(_) => Ok(true),
+            // another replica acquired the lease in the meantime
+            Err(kube::Error::Api(response)) if response.code == 409 => Ok(false),
+            Err(error) => Err(error.into()),
+        }
+    }
+}
+
+fn expired(spec: &LeaseSpec, now: DateTime<Utc>) -> bool {
+    match (spec.renew_time.as_ref(), spec.lease_duration_seconds) {
+        (Some(renewed), Some(seconds)) => {
+            renewed.0 + chrono::Duration::seconds(seconds as i64) < now
+        }
+        _ => true,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_expired() {
+        let now = Utc::now();
+        let lease = |renewed: DateTime<Utc>| LeaseSpec {
+            holder_identity: Some("control-plane-0".to_owned()),
+            lease_duration_seconds: Some(90),
+            renew_time: Some(MicroTime(renewed)),
+            ..LeaseSpec::default()
+        };
+
+        assert!(!expired(&lease(now), now));
+        assert!(!expired(&lease(now - chrono::Duration::seconds(60)), now));
+        assert!(expired(&lease(now - chrono::Duration::seconds(120)), now));
+        assert!(expired(&LeaseSpec::default(), now));
+    }
+}
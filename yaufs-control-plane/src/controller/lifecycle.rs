@@ -0,0 +1,121 @@
+/*
+ *    Copyright  2023.  Fritz Ochsmann
+ *
+ *    Licensed under the Apache License, Version 2.0 (the "License");
+ *    you may not use this file except in compliance with the License.
+ *    You may obtain a copy of the License at
+ *
+ *        http://www.apache.org/licenses/LICENSE-2.0
+ *
+ *    Unless required by applicable law or agreed to in writing, software
+ *    distributed under the License is distributed on an "AS IS" BASIS,
+ *    WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ *    See the License for the specific language governing permissions and
+ *    limitations under the License.
+ */
+
+//! Starting and stopping instances, shared by the api and the autoscaler.
+
+use crate::controller::crd::instance::create_crd;
+use crate::prelude::*;
+use chrono::Utc;
+use futures::{StreamExt, TryStreamExt};
+use kube::api::DeleteParams;
+use kube::{Api, Client};
+use yaufs_common::error::{Result, YaufsError};
+use yaufs_common::map_internal_error;
+use yaufs_common::skytable::actions::AsyncActions;
+use yaufs_common::skytable::ddl::AsyncDdl;
+use yaufs_common::skytable::pool::AsyncPool;
+
+/// Build the record of a new instance of the template.
+pub fn record(
+    template_id: &str,
+    replicas: i32,
+    overrides: Option<InstanceOverrides>,
+    namespace: String,
+) -> Instance {
+    Instance {
+        id: nanoid::nanoid!(),
+        template_id: template_id.to_owned(),
+        created_at: Utc::now().to_rfc3339(),
+        status: None,
+        replicas,
+        address: None,
+        overrides,
+        namespace,
+    }
+}
+
+/// Store the records of the instances and create their crds, which the controller deploys.
+pub async fn start_instances(
+    skytable: &AsyncPool,
+    client: Client,
+    template_id: &str,
+    instances: &[Instance],
+) -> Result<()> {
+    let mut connection =
+        map_internal_error!(skytable.get().await, "failed to access skytable pool")?;
+    kv_span!(
+        connection.switch(format!("instances:{template_id}")).await,
+        "switch"
+    )?;
+    kv_span!(
+        connection
+            .mset(
+                instances
+                    .iter()
+                    .map(|instance| instance.id.as_str())
+                    .collect::<Vec<&str>>(),
+                instances.iter().collect::<Vec<&Instance>>()
+            )
+            .await,
+        "write instances"
+    )?;
+    drop(connection);
+
+    futures::stream::iter(instances.iter())
+        .then(|instance| async {
+            // apply a custom crd for the controller to manage
+            create_crd(instance, client.clone()).await
+        })
+        .try_collect::<Vec<()>>()
+        .await?;
+    for instance in instances {
+        info!(
+            "Started instance {} of template {}",
+            instance.id, template_id
+        );
+    }
+
+    Ok(())
+}
+
+/// Delete the crd of the instance, whose deployment is stopped by the controller, and its
+/// record.
+pub async fn stop_instance(
+    skytable: &AsyncPool,
+    client: Client,
+    template_id: &str,
+    id: &str,
+    namespace: &str,
+) -> Result<()> {
+    map_internal_error!(
+        Api::<crate::controller::crd::instance::Instance>::namespaced(client, namespace)
+            .delete(id, &DeleteParams::default())
+            .await,
+        "Error while deleting crd"
+    )?;
+    debug!("Deleted instance crd {}", id);
+
+    let mut connection =
+        map_internal_error!(skytable.get().await, "failed to access skytable pool")?;
+    kv_span!(
+        connection.switch(format!("instances:{template_id}")).await,
+        "switch"
+    )?;
+    kv_span!(connection.del(id).await, "delete")?;
+    info!("Stopped instance {} of template {}", id, template_id);
+
+    Ok(())
+}
@@ -15,8 +15,11 @@
  */
 
 use fluvio::TopicProducer;
+use k8s_openapi::NamespaceResourceScope;
+use kube::api::ListParams;
 use kube::runtime::controller::Action;
-use kube::Client;
+use kube::{Api, Client, Resource};
+use serde::de::DeserializeOwned;
 use std::sync::Arc;
 use std::time::Duration;
 use thiserror::Error;
@@ -26,13 +29,22 @@ use tonic::transport::Channel;
 use tonic::{Request, Status};
 use yaufs_common::error::YaufsError;
 use yaufs_common::oidc::OIDCClient;
+use yaufs_common::skytable::pool::AsyncPool;
 use yaufs_common::tonic::inject_tracing_context;
 use yaufs_common::yaufs_proto::template_service_v1::template_service_v1_client::TemplateServiceV1Client;
 
-const INSTANCE: &str = "instance";
 const TEMPLATE_SERVICE_ENDPOINT: &str = "TEMPLATE_SERVICE_ENDPOINT";
+// the namespace of instances started without one
+const INSTANCE_NAMESPACE: &str = "INSTANCE_NAMESPACE";
+// comma separated list of the namespaces the control plane is limited to
+const WATCH_NAMESPACES: &str = "WATCH_NAMESPACES";
+const DEFAULT_INSTANCE_NAMESPACE: &str = "instance";
 
+mod autoscaler;
 pub mod crd;
+mod lease;
+pub mod lifecycle;
+mod sweeper;
 
 #[derive(Error, Debug)]
 pub enum ControlPlaneError {
@@ -54,11 +66,120 @@ impl From<Status> for ControlPlaneError {
     }
 }
 
+/// The namespaces the instances are placed into. All namespaces are watched unless the control
+/// plane is limited to some of them, since its rbac may only grant access to these.
+#[derive(Debug, Clone)]
+pub struct Namespaces {
+    default: String,
+    watched: Vec<String>,
+}
+
+impl Namespaces {
+    /// Read the namespaces from `INSTANCE_NAMESPACE` and `WATCH_NAMESPACES`. A default namespace
+    /// outside of the watched ones will panic, since its instances would never be deployed.
+    pub fn from_env() -> Self {
+        let default = std::env::var(INSTANCE_NAMESPACE)
+            .ok()
+            .filter(|namespace| !namespace.is_empty())
+            .unwrap_or_else(|| DEFAULT_INSTANCE_NAMESPACE.to_owned());
+        let watched = std::env::var(WATCH_NAMESPACES)
+            .map(|namespaces| parse_namespaces(namespaces.as_str()))
+            .unwrap_or_default();
+
+        let namespaces = Self { default, watched };
+        if !namespaces.watches(namespaces.default.as_str()) {
+            panic!(
+                "The default namespace {} is not watched",
+                namespaces.default
+            );
+        }
+        namespaces
+    }
+
+    pub fn watches(&self, namespace: &str) -> bool {
+        self.watched.is_empty() || self.watched.iter().any(|watched| watched == namespace)
+    }
+
+    /// Determine the namespace of a new instance. The namespace of the request is preferred over
+    /// the one of the template.
+    pub fn resolve(
+        &self,
+        requested: Option<&str>,
+        template: Option<&str>,
+    ) -> Result<String, YaufsError> {
+        let namespace = requested
+            .or(template)
+            .filter(|namespace| !namespace.is_empty())
+            .unwrap_or(self.default.as_str());
+        if !self.watches(namespace) {
+            return Err(YaufsError::InvalidArgument(
+                "the namespace is not watched by the control plane",
+            ));
+        }
+
+        Ok(namespace.to_owned())
+    }
+
+    /// The namespace of an existing instance, instances stored without one were started in the
+    /// default namespace.
+    pub fn of<'a>(&'a self, namespace: &'a str) -> &'a str {
+        match namespace.is_empty() {
+            true => self.default.as_str(),
+            false => namespace,
+        }
+    }
+
+    /// The apis of the watched namespaces, which is a single cluster wide api if all namespaces
+    /// are watched.
+    pub fn apis<K>(&self, client: &Client) -> Vec<Api<K>>
+    where
+        K: Resource<Scope = NamespaceResourceScope>,
+        K::DynamicType: Default,
+    {
+        match self.watched.is_empty() {
+            true => vec![Api::all(client.clone())],
+            false => self
+                .watched
+                .iter()
+                .map(|namespace| Api::namespaced(client.clone(), namespace))
+                .collect(),
+        }
+    }
+
+    /// List the resources of all watched namespaces.
+    pub async fn list<K>(&self, client: &Client, params: &ListParams) -> Result<Vec<K>, kube::Error>
+    where
+        K: Resource<Scope = NamespaceResourceScope>,
+        K::DynamicType: Default,
+        K: DeserializeOwned,
+        K: Clone,
+        K: std::fmt::Debug,
+    {
+        let mut resources = Vec::new();
+        for api in self.apis::<K>(client) {
+            resources.extend(api.list(params).await?.items);
+        }
+
+        Ok(resources)
+    }
+}
+
+fn parse_namespaces(namespaces: &str) -> Vec<String> {
+    namespaces
+        .split(',')
+        .map(str::trim)
+        .filter(|namespace| !namespace.is_empty())
+        .map(ToOwned::to_owned)
+        .collect()
+}
+
 pub struct ControllerContext {
     kube_client: Client,
+    namespaces: Namespaces,
     producer: TopicProducer,
     template_client: Arc<Mutex<TemplateServiceV1Client<Channel>>>,
     oidc_client: OIDCClient,
+    skytable: AsyncPool,
 }
 
 impl ControllerContext {
@@ -81,11 +202,11 @@ pub fn default_error_policy<T>(
     _context: Arc<ControllerContext>,
 ) -> Action {
     error!("Error occurred during reconcile: {:?}", error);
-    
+
     Action::requeue(Duration::from_secs(30))
 }
 
-pub async fn init(client: Client) -> Result<(), Box<dyn std::error::Error>> {
+pub async fn init(client: Client, skytable: AsyncPool) -> Result<(), Box<dyn std::error::Error>> {
     // connect to the template service
     let template_client = TemplateServiceV1Client::connect(
         std::env::var(TEMPLATE_SERVICE_ENDPOINT)
@@ -96,6 +217,7 @@ pub async fn init(client: Client) -> Result<(), Box<dyn std::error::Error>> {
 
     let context = Arc::new(ControllerContext {
         kube_client: client,
+        namespaces: Namespaces::from_env(),
         // establish connection to the event streaming spu gorup
         producer: yaufs_common::fluvio_util::producer().await?,
         template_client: Arc::new(Mutex::new(template_client)),
@@ -104,11 +226,41 @@ pub async fn init(client: Client) -> Result<(), Box<dyn std::error::Error>> {
             String::from("control-plane"),
         ])
         .await?,
+        skytable,
     });
 
     // start the controllers
     crd::instance::init(context.clone()).await?;
-    crd::template::init(context).await?;
+    crd::template::init(context.clone()).await?;
+    // remove the resources left behind by deleted instances
+    sweeper::start(context.clone());
+    autoscaler::start(context);
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_namespaces() {
+        assert_eq!(
+            parse_namespaces(" games, ,events "),
+            vec!["games", "events"]
+        );
+
+        let namespaces = Namespaces {
+            default: "games".to_owned(),
+            watched: vec!["games".to_owned(), "events".to_owned()],
+        };
+        assert_eq!(namespaces.resolve(None, None).unwrap(), "games");
+        assert_eq!(namespaces.resolve(None, Some("events")).unwrap(), "events");
+        assert_eq!(
+            namespaces.resolve(Some("games"), Some("events")).unwrap(),
+            "games"
+        );
+        assert!(namespaces.resolve(Some("kube-system"), None).is_err());
+        assert_eq!(namespaces.of(""), "games");
+    }
+}
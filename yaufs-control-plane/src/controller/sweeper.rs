@@ -0,0 +1,179 @@
+/*
+ *    Copyright  2023.  Fritz Ochsmann
+ *
+ *    Licensed under the Apache License, Version 2.0 (the "License");
+ *    you may not use this file except in compliance with the License.
+ *    You may obtain a copy of the License at
+ *
+ *        http://www.apache.org/licenses/LICENSE-2.0
+ *
+ *    Unless required by applicable law or agreed to in writing, software
+ *    distributed under the License is distributed on an "AS IS" BASIS,
+ *    WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ *    See the License for the specific language governing permissions and
+ *    limitations under the License.
+ */
+
+use crate::controller::crd::instance::{Instance, INSTANCE_LABEL, INSTANCE_NAMESPACE_LABEL};
+use crate::controller::crd::template::Template;
+use crate::controller::{ControlPlaneError, ControllerContext};
+use chrono::{DateTime, Utc};
+use k8s_openapi::api::apps::v1::Deployment;
+use kube::api::{DeleteParams, ListParams};
+use kube::Api;
+use std::collections::HashSet;
+use std::sync::Arc;
+use std::time::Duration;
+use yaufs_common::error::YaufsError;
+use yaufs_common::skytable::actions::AsyncActions;
+use yaufs_common::skytable::ddl::AsyncDdl;
+
+const SWEEP_INTERVAL: Duration = Duration::from_secs(300);
+// deployments created before instances were labelled live in the former fixed namespace and
+// only carry the `app` label
+const LEGACY_NAMESPACE: &str = "instance";
+
+/// Periodically remove the deployments and skytable records of instances whose crd no longer
+/// exists. Deployments owned by a crd are collected by kubernetes itself, this covers the ones
+/// created without an owner reference (including the unlabelled ones of the legacy `instance`
+/// namespace) and records of failed or interrupted requests.
+pub fn start(context: Arc<ControllerContext>) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(SWEEP_INTERVAL);
+        loop {
+            interval.tick().await;
+            if let Err(error) = sweep(context.as_ref()).await {
+                warn!("Error while sweeping orphaned instances: {:?}", error);
+            }
+        }
+    });
+}
+
+#[tracing::instrument(skip_all)]
+async fn sweep(context: &ControllerContext) -> Result<(), ControlPlaneError> {
+    let client = &context.kube_client;
+    let instances = context
+        .namespaces
+        .list::<Instance>(client, &ListParams::default())
+        .await?
+        .into_iter()
+        .filter_map(|instance| Some((instance.metadata.namespace?, instance.metadata.name?)))
+        .collect::<HashSet<(String, String)>>();
+
+    // the deployments without an instance, mapped to the instance they belong to
+    let mut orphans = Vec::new();
+    let deployments = context
+        .namespaces
+        .list::<Deployment>(client, &ListParams::default().labels(INSTANCE_LABEL))
+        .await?;
+    for deployment in deployments {
+        let labels = deployment.metadata.labels.unwrap_or_default();
+        let (name, namespace) = match (deployment.metadata.name, deployment.metadata.namespace) {
+            (Some(name), Some(namespace)) => (name, namespace),
+            _ => continue,
+        };
+        let owner = (
+            labels
+                .get(INSTANCE_NAMESPACE_LABEL)
+                .cloned()
+                .unwrap_or_else(|| namespace.clone()),
+            labels.get(INSTANCE_LABEL).cloned().unwrap_or_default(),
+        );
+        if !owner.1.is_empty() {
+            orphans.push((namespace, name, owner));
+        }
+    }
+    if context.namespaces.watches(LEGACY_NAMESPACE) {
+        let legacy = Api::<Deployment>::namespaced(client.clone(), LEGACY_NAMESPACE)
+            .list(&ListParams::default().labels(format!("app,!{INSTANCE_LABEL}").as_str()))
+            .await?;
+        for deployment in legacy.items {
+            let name = match deployment.metadata.name {
+                Some(name) => name,
+                None => continue,
+            };
+            let app = match deployment
+                .metadata
+                .labels
+                .and_then(|mut labels| labels.remove("app"))
+                .filter(|app| !app.is_empty())
+            {
+                Some(app) => app,
+                None => continue,
+            };
+            orphans.push((
+                LEGACY_NAMESPACE.to_owned(),
+                name,
+                (LEGACY_NAMESPACE.to_owned(), app),
+            ));
+        }
+    }
+
+    for (namespace, name, owner) in orphans {
+        if instances.contains(&owner) {
+            continue;
+        }
+        // the instance may have been created after the instances were listed
+        let instance = Api::<Instance>::namespaced(client.clone(), owner.0.as_str())
+            .get_opt(owner.1.as_str())
+            .await?;
+        if instance.is_some() {
+            continue;
+        }
+
+        info!("Deleting orphaned deployment {}/{}", namespace, name);
+        Api::<Deployment>::namespaced(client.clone(), namespace.as_str())
+            .delete(name.as_str(), &DeleteParams::background())
+            .await?;
+    }
+
+    // delete the records without an instance. Records are written before their crd is created,
+    // so only records older than the interval are considered.
+    let names = instances
+        .into_iter()
+        .map(|(_, name)| name)
+        .collect::<HashSet<String>>();
+    let templates = Api::<Template>::all(client.clone())
+        .list(&ListParams::default())
+        .await?;
+    let mut connection = context.skytable.get().await.map_err(YaufsError::from)?;
+    for template in templates
+        .items
+        .iter()
+        .filter_map(|template| template.spec.id())
+    {
+        connection
+            .switch(format!("instances:{template}"))
+            .await
+            .map_err(YaufsError::from)?;
+        let keys = connection
+            .lskeys::<Vec<String>>(10000)
+            .await
+            .map_err(YaufsError::from)?;
+
+        for key in keys.into_iter().filter(|key| !names.contains(key)) {
+            let instance = connection
+                .get::<crate::prelude::Instance>(key.as_str())
+                .await
+                .map_err(YaufsError::from)?;
+            if !expired(instance.created_at.as_str()) {
+                continue;
+            }
+
+            info!("Deleting orphaned instance record {}", key);
+            connection
+                .del(key.as_str())
+                .await
+                .map_err(YaufsError::from)?;
+        }
+    }
+
+    Ok(())
+}
+
+fn expired(created_at: &str) -> bool {
+    DateTime::parse_from_rfc3339(created_at).map_or(true, |created_at| {
+        Utc::now().signed_duration_since(created_at)
+            > chrono::Duration::from_std(SWEEP_INTERVAL).unwrap()
+    })
+}
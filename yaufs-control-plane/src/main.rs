@@ -31,6 +31,7 @@ use tokio::net::TcpListener;
 use tokio::task::JoinHandle;
 use tonic::transport::server::TcpIncoming;
 use tonic::transport::Server;
+use yaufs_common::skytable::pool::AsyncPool;
 
 mod controller;
 mod v1;
@@ -51,16 +52,16 @@ cfg_if::cfg_if! {
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let (_, client, join) = init().await?;
+    let (_, client, skytable, join) = init().await?;
     // start the kubernetes operator
-    controller::init(client).await.unwrap();
+    controller::init(client, skytable).await.unwrap();
     // join the grpc server into the process lifetime
     join.await?;
 
     Ok(())
 }
 
-async fn init() -> Result<(String, Client, JoinHandle<()>), Box<dyn std::error::Error>> {
+async fn init() -> Result<(String, Client, AsyncPool, JoinHandle<()>), Box<dyn std::error::Error>> {
     yaufs_common::init_telemetry!();
     // connect to the skytable kv server
     let skytable = yaufs_common::database::skytable::connect().await;
@@ -87,7 +88,7 @@ async fn init() -> Result<(String, Client, JoinHandle<()>), Box<dyn std::error::
         }
     }
 
-    let service = v1::new(skytable, client.clone()).await?;
+    let service = v1::new(skytable.clone(), client.clone()).await?;
     let join = tokio::spawn(async move {
         // expose the health check and in future version the metrics
         #[cfg(not(test))]
@@ -107,7 +108,7 @@ async fn init() -> Result<(String, Client, JoinHandle<()>), Box<dyn std::error::
             .unwrap()
     });
 
-    Ok((format!("ws://{local_addr}"), client, join))
+    Ok((format!("ws://{local_addr}"), client, skytable, join))
 }
 
 pub mod prelude {
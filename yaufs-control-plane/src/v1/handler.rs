@@ -14,11 +14,10 @@
  *    limitations under the License.
  */
 
+use crate::controller::lifecycle;
 use crate::prelude::*;
 use crate::v1::ControlPlaneV1Context;
-use chrono::Utc;
-use futures::{StreamExt, TryStreamExt};
-use kube::api::DeleteParams;
+use kube::api::{ListParams, Patch, PatchParams};
 use kube::Api;
 use tonic::{Request, Response};
 use yaufs_common::error::{Result, YaufsError};
@@ -34,52 +33,87 @@ pub async fn start_instance(
 ) -> Result<Response<StartInstanceResponse>> {
     // convert the request into the inner data
     let data = request.into_inner();
-    let mut connection = map_internal_error!(
-        context.skytable.get().await,
-        "failed to access skytable pool"
-    )?;
     let count = data.count;
-
-    // create the instances
-    let mut instances: Vec<Instance> = Vec::new();
-    let mut keys: Vec<String> = Vec::new();
-    for _ in 0..count {
-        let id = nanoid::nanoid!();
-
-        keys.push(id.clone());
-        instances.push(Instance {
-            id,
-            template_id: data.template_id.clone(),
-            created_at: Utc::now().to_rfc3339(),
-        });
+    let replicas = data.replicas.unwrap_or(1);
+    if let Some(overrides) = data.overrides.as_ref() {
+        validate_overrides(overrides)?;
     }
-    // save them into the skytable
-    kv_span!(
-        connection
-            .switch(format!("instances:{}", data.template_id))
-            .await,
-        "switch"
+    // the namespace of the request is preferred over the one of the template
+    let template = map_internal_error!(
+        crate::controller::crd::template::find(
+            context.kube_client.clone(),
+            data.template_id.as_str()
+        )
+        .await,
+        "Error while fetching template crd"
     )?;
-    kv_span!(
-        connection
-            .mset(keys, instances.iter().collect::<Vec<&Instance>>())
-            .await,
-        "write instances"
+    validate_replicas(replicas, template.as_ref())?;
+    let namespace = context.namespaces.resolve(
+        data.namespace.as_deref(),
+        template
+            .as_ref()
+            .and_then(|template| template.spec.namespace()),
     )?;
 
-    // start all the instances
-    futures::stream::iter(instances.iter())
-        .then(|instance| async {
-            // apply a custom crd for the controller to manage
-            crate::controller::crd::instance::create_crd(instance, context.kube_client.clone())
-                .await
+    let instances = (0..count)
+        .map(|_| {
+            lifecycle::record(
+                data.template_id.as_str(),
+                replicas,
+                data.overrides.clone(),
+                namespace.clone(),
+            )
         })
-        .try_collect::<Vec<()>>()
-        .await?;
+        .collect::<Vec<Instance>>();
+    lifecycle::start_instances(
+        &context.skytable,
+        context.kube_client.clone(),
+        data.template_id.as_str(),
+        instances.as_slice(),
+    )
+    .await?;
 
     Ok(Response::new(StartInstanceResponse { instances }))
 }
 
+fn validate_replicas(
+    replicas: i32,
+    template: Option<&crate::controller::crd::template::Template>,
+) -> Result<()> {
+    if replicas < 0 {
+        return Err(YaufsError::InvalidArgument("replicas must not be negative"));
+    }
+    // the claim of the world can only be attached to a single pod
+    let storage = template.map_or(false, |template| template.spec.storage().is_some());
+    if storage && replicas > 1 {
+        return Err(YaufsError::InvalidArgument(
+            "instances with storage can not have more than one replica",
+        ));
+    }
+
+    Ok(())
+}
+
+fn validate_overrides(overrides: &InstanceOverrides) -> Result<()> {
+    // the pods of the deployment are selected by their app label
+    if overrides.labels.contains_key("app") {
+        return Err(YaufsError::InvalidArgument(
+            "the app label can not be overridden",
+        ));
+    }
+    if overrides
+        .config_files
+        .keys()
+        .any(|path| !path.starts_with('/'))
+    {
+        return Err(YaufsError::InvalidArgument(
+            "config files require an absolute path",
+        ));
+    }
+
+    Ok(())
+}
+
 pub async fn list_instances(
     context: &ControlPlaneV1Context,
     request: Request<ListInstancesRequest>,
@@ -105,12 +139,25 @@ pub async fn list_instances(
         .for_each(|key| pipeline.push(query!("MGET", key)));
 
     // fetch all instances
-    let instances = kv_span!(connection.run_pipeline(pipeline).await, "fetch values")?
+    let mut instances = kv_span!(connection.run_pipeline(pipeline).await, "fetch values")?
         .into_iter()
         // parse from strbin
         .map(Instance::from_element)
         .try_collect::<Vec<Instance>>()?;
 
+    // the status is only kept on the crds
+    let crds = crate::controller::crd::instance::crds(
+        &context.namespaces,
+        context.kube_client.clone(),
+        &ListParams::default(),
+    )
+    .await?;
+    instances.iter_mut().for_each(|instance| {
+        if let Some(crd) = crds.get(&instance.id) {
+            crd.describe(instance);
+        }
+    });
+
     Ok(Response::new(ListInstancesResponse { instances }))
 }
 
@@ -130,39 +177,111 @@ pub async fn get_instance(
             .await,
         "switch"
     )?;
-    let instance = kv_span!(connection.get::<Instance>(data.id).await)?;
+    let mut instance = kv_span!(connection.get::<Instance>(data.id.as_str()).await)?;
+
+    // the status is only kept on the crd
+    let crds = crate::controller::crd::instance::crds(
+        &context.namespaces,
+        context.kube_client.clone(),
+        &ListParams::default().fields(format!("metadata.name={}", data.id).as_str()),
+    )
+    .await?;
+    if let Some(crd) = crds.get(&data.id) {
+        crd.describe(&mut instance);
+    }
 
     Ok(Response::new(instance))
 }
 
-pub async fn stop_instance(
+pub async fn scale_instance(
     context: &ControlPlaneV1Context,
-    request: Request<InstanceId>,
-) -> Result<Response<Empty>> {
+    request: Request<ScaleInstanceRequest>,
+) -> Result<Response<Instance>> {
     let data = request.into_inner();
+    let template = map_internal_error!(
+        crate::controller::crd::template::find(
+            context.kube_client.clone(),
+            data.template_id.as_str()
+        )
+        .await,
+        "Error while fetching template crd"
+    )?;
+    validate_replicas(data.replicas, template.as_ref())?;
     let mut connection = map_internal_error!(
         context.skytable.get().await,
         "failed to access skytable pool"
     )?;
 
-    // delete the crd
-    map_internal_error!(
-        Api::<crate::controller::crd::instance::Instance>::all(context.kube_client.clone())
-            .delete(data.id.as_str(), &DeleteParams::default())
+    // the instance has to belong to the template
+    kv_span!(
+        connection
+            .switch(format!("instances:{}", data.template_id))
             .await,
-        "Error while deleting crd"
+        "switch"
+    )?;
+    let mut instance = kv_span!(connection.get::<Instance>(data.id.as_str()).await)?;
+    let namespace = context
+        .namespaces
+        .of(instance.namespace.as_str())
+        .to_owned();
+
+    // scale the crd, the controller applies the replicas to the deployment
+    let patch = serde_json::json!({
+        "spec": {
+            "replicas": data.replicas,
+        }
+    });
+    let crd = map_internal_error!(
+        Api::<crate::controller::crd::instance::Instance>::namespaced(
+            context.kube_client.clone(),
+            namespace.as_str()
+        )
+        .patch(
+            data.id.as_str(),
+            &PatchParams::default(),
+            &Patch::Merge(&patch)
+        )
+        .await,
+        "Error while scaling crd"
+    )?;
+    crd.describe(&mut instance);
+    kv_span!(
+        connection.update(data.id.as_str(), &instance).await,
+        "update instance"
+    )?;
+    info!("Scaled instance {} to {} replicas", data.id, data.replicas);
+
+    Ok(Response::new(instance))
+}
+
+pub async fn stop_instance(
+    context: &ControlPlaneV1Context,
+    request: Request<InstanceId>,
+) -> Result<Response<Empty>> {
+    let data = request.into_inner();
+    let mut connection = map_internal_error!(
+        context.skytable.get().await,
+        "failed to access skytable pool"
     )?;
-    debug!("Deleted instance crd {}", data.id.as_str());
 
-    // remove the instance from the kv
+    // the record holds the namespace of the crd
     kv_span!(
         connection
             .switch(format!("instances:{}", data.template_id))
             .await,
         "switch"
     )?;
-    kv_span!(connection.del(data.id.as_str()).await, "delete")?;
-    info!("Deleted instance {}", data.id.as_str());
+    let instance = kv_span!(connection.get::<Instance>(data.id.as_str()).await)?;
+    drop(connection);
+
+    lifecycle::stop_instance(
+        &context.skytable,
+        context.kube_client.clone(),
+        data.template_id.as_str(),
+        data.id.as_str(),
+        context.namespaces.of(instance.namespace.as_str()),
+    )
+    .await?;
 
     Ok(Response::new(Empty {}))
 }
@@ -14,6 +14,7 @@
  *    limitations under the License.
  */
 
+use crate::controller::Namespaces;
 use crate::prelude::*;
 use control_plane_v1_server::{ControlPlaneV1, ControlPlaneV1Server};
 use fluvio::dataplane::record::ConsumerRecord;
@@ -31,6 +32,7 @@ mod handler;
 pub struct ControlPlaneV1Context {
     pub skytable: AsyncPool,
     pub kube_client: Client,
+    pub namespaces: Namespaces,
 }
 
 pub type Server = ControlPlaneV1Server<ControlPlaneV1Context>;
@@ -90,6 +92,7 @@ pub async fn new(skytable: AsyncPool, kube_client: Client) -> yaufs_common::erro
     Ok(ControlPlaneV1Server::new(ControlPlaneV1Context {
         skytable,
         kube_client,
+        namespaces: Namespaces::from_env(),
     }))
 }
 
@@ -131,4 +134,14 @@ impl ControlPlaneV1 for ControlPlaneV1Context {
 
         Ok(response)
     }
+
+    #[instrument(skip_all)]
+    async fn scale_instance(
+        &self,
+        request: Request<ScaleInstanceRequest>,
+    ) -> Result<Response<Instance>, Status> {
+        let response = handler::scale_instance(self, request).await?;
+
+        Ok(response)
+    }
 }
@@ -0,0 +1,359 @@
+/*
+ *    Copyright  2023.  Fritz Ochsmann
+ *
+ *    Licensed under the Apache License, Version 2.0 (the "License");
+ *    you may not use this file except in compliance with the License.
+ *    You may obtain a copy of the License at
+ *
+ *        http://www.apache.org/licenses/LICENSE-2.0
+ *
+ *    Unless required by applicable law or agreed to in writing, software
+ *    distributed under the License is distributed on an "AS IS" BASIS,
+ *    WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ *    See the License for the specific language governing permissions and
+ *    limitations under the License.
+ */
+
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::time::Duration;
+
+const PROXY_CONFIG_PATH: &str = "PROXY_CONFIG_PATH";
+
+// the configuration is read once on the first access and is immutable afterwards
+lazy_static::lazy_static! {
+    pub static ref CONFIG: ProxyConfig = ProxyConfig::from_env();
+}
+
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct ProxyConfig {
+    /// address the proxy listens on, defaults to `0.0.0.0:25565`
+    pub address: Option<String>,
+    pub timeouts: TimeoutConfig,
+    pub compression: CompressionConfig,
+    /// display the measured ping of the client in the footer of the tab list
+    pub tab_list_ping: bool,
+    /// directory to write packet captures of every connection into, disabled if not set
+    pub capture: Option<PathBuf>,
+    /// base url of the session server used to authenticate the players, defaults to mojang
+    pub session_server: Option<String>,
+    pub network: NetworkConfig,
+    pub routing: RoutingConfig,
+    pub queue: QueueConfig,
+    pub limbo: LimboConfig,
+    pub secure_chat: SecureChat,
+    pub permissions: PermissionConfig,
+    pub moderation: ModerationConfig,
+}
+
+impl ProxyConfig {
+    /// Load the configuration from the json file specified by `PROXY_CONFIG_PATH`. If the variable
+    /// is not set the default configuration is used. An invalid file will panic, since the proxy
+    /// can not run in a well defined matter with it.
+    pub fn from_env() -> Self {
+        match std::env::var(PROXY_CONFIG_PATH) {
+            Ok(path) => {
+                let raw = std::fs::read_to_string(path.as_str())
+                    .unwrap_or_else(|error| panic!("Error while reading {path}: {error}"));
+                serde_json::from_str(raw.as_str())
+                    .unwrap_or_else(|error| panic!("Invalid proxy configuration: {error}"))
+            }
+            Err(_) => Self::default(),
+        }
+    }
+}
+
+/// All values are given in seconds.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct TimeoutConfig {
+    /// the time a client has to complete the handshake after connecting
+    pub handshake: u64,
+    /// the time a client has to complete the whole login (including authentication)
+    pub login: u64,
+    /// the maximum time without any packet received from a peer
+    pub read: u64,
+    /// the time a client has to answer a keep alive sent by the backend
+    pub keep_alive: u64,
+}
+
+impl Default for TimeoutConfig {
+    fn default() -> Self {
+        Self {
+            handshake: 5,
+            login: 30,
+            read: 30,
+            keep_alive: 20,
+        }
+    }
+}
+
+impl TimeoutConfig {
+    pub fn handshake(&self) -> Duration {
+        Duration::from_secs(self.handshake)
+    }
+
+    pub fn login(&self) -> Duration {
+        Duration::from_secs(self.login)
+    }
+
+    pub fn read(&self) -> Duration {
+        Duration::from_secs(self.read)
+    }
+
+    pub fn keep_alive(&self) -> Duration {
+        Duration::from_secs(self.keep_alive)
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct CompressionConfig {
+    /// packets with a size above the threshold are compressed on the connection between the
+    /// client and the proxy, a negative value disables the compression
+    pub threshold: i32,
+}
+
+impl Default for CompressionConfig {
+    fn default() -> Self {
+        Self { threshold: 256 }
+    }
+}
+
+impl CompressionConfig {
+    pub fn threshold(&self) -> Option<i32> {
+        Some(self.threshold).filter(|threshold| *threshold >= 0)
+    }
+}
+
+/// Sharing of the tab list and the chat between the backends of the network. The state is
+/// shared between all proxy replicas over the session store and the event stream.
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct NetworkConfig {
+    pub enabled: bool,
+    /// list the players of all backends in the tab list
+    pub tab_list: bool,
+    /// relay chat messages between the backends of the same group
+    pub chat: bool,
+    /// chat groups mapped to the backends belonging to them
+    pub groups: HashMap<String, Vec<String>>,
+}
+
+/// Handling of the signed chat of the clients. Messages of the proxy itself are always sent as
+/// unsigned system messages.
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum SecureChat {
+    /// forward the chat sessions and signatures of the clients untouched
+    #[default]
+    Passthrough,
+    /// disconnect clients sending unsigned chat messages
+    Enforce,
+    /// remove the chat sessions and signatures, the backends must not enforce secure profiles
+    Strip,
+}
+
+/// Routing of clients to the backends based on the hostname of the handshake (forced hosts).
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct RoutingConfig {
+    /// named groups of backend addresses (e.g. the instances of a template)
+    pub backends: HashMap<String, Vec<String>>,
+    /// hostnames mapped to their route, a leading `*.` matches all subdomains of the hostname
+    pub hosts: HashMap<String, RouteConfig>,
+    /// route of clients connecting with an unknown hostname
+    pub default: RouteConfig,
+    /// backend groups mapped to their resource pack, which is preferred over the one of the route
+    pub resource_packs: HashMap<String, ResourcePackConfig>,
+}
+
+impl Default for RoutingConfig {
+    fn default() -> Self {
+        Self {
+            backends: HashMap::from([("default".to_owned(), vec!["127.0.0.1:25566".to_owned()])]),
+            hosts: HashMap::new(),
+            default: RouteConfig {
+                group: "default".to_owned(),
+                motd: Some("Hey folks".to_owned()),
+                favicon: None,
+                resource_pack: None,
+            },
+            resource_packs: HashMap::new(),
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct RouteConfig {
+    /// the backend group the clients are sent to
+    pub group: String,
+    /// the description shown in the server list, falls back to the one of the default route
+    #[serde(default)]
+    pub motd: Option<String>,
+    /// path to a 64x64 png shown in the server list, falls back to the one of the default route
+    #[serde(default)]
+    pub favicon: Option<PathBuf>,
+    /// resource pack sent to the clients of the route
+    #[serde(default)]
+    pub resource_pack: Option<ResourcePackConfig>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct ResourcePackConfig {
+    pub url: String,
+    /// sha1 of the pack as hex string, which identifies the pack on the client
+    pub hash: String,
+    /// disconnect players declining the pack or failing to download it
+    #[serde(default)]
+    pub required: bool,
+    /// message shown in the prompt of the client
+    #[serde(default)]
+    pub prompt: Option<String>,
+}
+
+/// Queueing of players while all backends of a group are full.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct QueueConfig {
+    pub enabled: bool,
+    /// the maximum number of players per backend
+    pub max_players: usize,
+    /// players with one of the permissions are moved in front of players with lower priorities
+    pub tiers: Vec<QueueTier>,
+    /// the time a transfer to a backend may take before it is retried (in seconds)
+    pub transfer_timeout: u64,
+    /// endpoint of the control plane used to start more instances while players are queued
+    pub control_plane: Option<String>,
+    /// backend groups mapped to the template of their instances
+    pub templates: HashMap<String, String>,
+    /// the minimum time between two instance starts of the same template (in seconds)
+    pub scale_cooldown: u64,
+}
+
+impl Default for QueueConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            max_players: 100,
+            tiers: Vec::new(),
+            transfer_timeout: 10,
+            control_plane: None,
+            templates: HashMap::new(),
+            scale_cooldown: 120,
+        }
+    }
+}
+
+impl QueueConfig {
+    pub fn transfer_timeout(&self) -> Duration {
+        Duration::from_secs(self.transfer_timeout)
+    }
+
+    pub fn scale_cooldown(&self) -> Duration {
+        Duration::from_secs(self.scale_cooldown)
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct QueueTier {
+    pub permission: String,
+    pub priority: u32,
+}
+
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct LimboConfig {
+    /// file containing the registry codec (as sent in the join game) used by the limbo. If not
+    /// set, the codec of the last joined backend is used.
+    pub registry: Option<PathBuf>,
+    /// chat message shown to players joining the limbo
+    pub message: Option<String>,
+    /// title shown to players joining the limbo
+    pub title: Option<String>,
+    pub subtitle: Option<String>,
+    /// move players into the limbo if their backend stopped instead of disconnecting them. They
+    /// are queued for their backend group again, which requires the queue to be enabled.
+    pub fallback: bool,
+}
+
+/// Permissions of the players, granted directly or through groups. Groups inherit the
+/// permissions of their parents.
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct PermissionConfig {
+    /// load groups and players from skytable, which are preferred over the configured ones
+    pub store: bool,
+    pub groups: HashMap<String, PermissionGroup>,
+    /// players identified by their uuid
+    pub players: HashMap<String, PlayerPermissions>,
+    /// group every player is a member of
+    pub default_group: Option<String>,
+    /// roles of the zitadel project mapped to the group granted to players linked to a user
+    pub roles: HashMap<String, String>,
+}
+
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct PermissionGroup {
+    /// permission nodes, a trailing `*` grants all nodes below (e.g. `proxy.*`)
+    pub permissions: Vec<String>,
+    /// groups whose permissions are inherited
+    pub parents: Vec<String>,
+}
+
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct PlayerPermissions {
+    pub permissions: Vec<String>,
+    pub groups: Vec<String>,
+    /// id of the zitadel user of the player, whose project roles grant further groups
+    pub zitadel_user: Option<String>,
+}
+
+/// Filtering of the chat messages of all players. Blocked messages are published as moderation
+/// events.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct ModerationConfig {
+    pub enabled: bool,
+    /// regular expressions matching blocked messages
+    pub patterns: Vec<String>,
+    /// blocked words, which are matched case insensitive as whole words
+    pub words: Vec<String>,
+    /// the maximum share of capital letters of messages with at least `caps_min_length` letters
+    pub max_caps: f32,
+    pub caps_min_length: usize,
+    /// the maximum number of messages of a player within the rate window (in seconds)
+    pub rate_limit: usize,
+    pub rate_window: u64,
+    /// players exceeding the rate limit are muted for this time (in seconds), 0 disables it
+    pub spam_mute: u64,
+}
+
+impl Default for ModerationConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            patterns: Vec::new(),
+            words: Vec::new(),
+            max_caps: 0.7,
+            caps_min_length: 8,
+            rate_limit: 5,
+            rate_window: 5,
+            spam_mute: 60,
+        }
+    }
+}
+
+impl ModerationConfig {
+    pub fn rate_window(&self) -> Duration {
+        Duration::from_secs(self.rate_window)
+    }
+
+    pub fn spam_mute(&self) -> Option<Duration> {
+        Some(Duration::from_secs(self.spam_mute)).filter(|duration| !duration.is_zero())
+    }
+}
@@ -22,6 +22,7 @@ extern crate async_trait;
 extern crate getset;
 extern crate core;
 
+mod config;
 mod proxy;
 
 const ADDRESS: &str = "0.0.0.0:25565";
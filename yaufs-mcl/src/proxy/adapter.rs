@@ -14,17 +14,38 @@
  *    limitations under the License.
  */
 
+use crate::config::{ResourcePackConfig, SecureChat, CONFIG};
+use crate::proxy::capture::PacketCapture;
+use crate::proxy::connection::ConnectionCommand;
 use crate::proxy::interceptor::PacketInterceptor;
+use crate::proxy::limbo::{self, Limbo};
+use crate::proxy::network::NetworkEvent;
+use crate::proxy::queue::QUEUE;
+use crate::proxy::routing;
 use crate::proxy::PeerMap;
 use kanal::{AsyncReceiver, AsyncSender};
+use std::collections::HashSet;
 use std::net::SocketAddr;
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use tokio::io::BufReader;
 use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
+use tokio::sync::broadcast;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::time::Instant;
 use yaufs_common::craftio_rs::{
     CraftAsyncReader, CraftAsyncWriter, CraftConnection, CraftIo, CraftReader,
     CraftTokioConnection, CraftWriter,
 };
-use yaufs_common::net::packet::{Packet762, RawPacket762};
+use yaufs_common::mcproto_rs::protocol::Packet;
+use yaufs_common::net::packet::{
+    HandshakeNextState, HandshakeSpec, LoginDisconnectSpec, LoginPluginResponseSpec, Packet762,
+    PlayDisconnectSpec, PlayPlayerSessionSpec, RawPacket762,
+};
+use yaufs_common::net::play;
+use yaufs_common::protocol::State;
+use yaufs_common::types::{Chat, RemainingBytes, VarInt};
+use yaufs_common::uuid::UUID4;
 
 pub struct Adapter<
     W: CraftAsyncWriter + CraftIo + Send + 'static,
@@ -35,6 +56,39 @@ pub struct Adapter<
     pub writer: W,
     pub reader: R,
     client: bool,
+    state: State,
+    connected_at: Instant,
+    last_read: Instant,
+    // the keep alive id sent by the backend which has not been answered by the client yet
+    keep_alive: Option<(i64, Instant)>,
+    closed: bool,
+    capture: Option<PacketCapture>,
+    // events of the network, subscribed once the client joined a backend
+    pub network: Option<broadcast::Receiver<NetworkEvent>>,
+    // commands issued to the connection, only used by the client adapter
+    pub commands: Option<AsyncReceiver<ConnectionCommand>>,
+    // the backend the client is moved to, which replaces the current one after the join game
+    pending: Option<PendingBackend>,
+    // whether the client received a join game already
+    pub joined: bool,
+    // players added to the tab list by the current backend
+    pub listed: HashSet<UUID4>,
+    // the chat session of the client, which every backend it is moved to has to know
+    pub session: Option<PlayPlayerSessionSpec>,
+    // the resource pack sent by the proxy, which has not been loaded by the client yet
+    pub pending_pack: Option<&'static ResourcePackConfig>,
+    // the last header and footer of the tab list sent by the current backend
+    pub tab_list: Option<(Chat, Chat)>,
+    // the ping displayed in the footer of the tab list, in milliseconds
+    pub shown_ping: Option<u128>,
+    // the ping of the session in the session store of the network
+    pub stored_ping: Option<Duration>,
+}
+
+struct PendingBackend {
+    backend: String,
+    receiver: AsyncReceiver<Packet762>,
+    sender: AsyncSender<Packet762>,
 }
 
 pub type ServerAdapter =
@@ -57,6 +111,22 @@ impl TryFrom<(CraftTokioConnection, PeerMap, SocketAddr)>
             writer,
             reader,
             client: false,
+            state: State::Handshaking,
+            connected_at: Instant::now(),
+            last_read: Instant::now(),
+            keep_alive: None,
+            closed: false,
+            capture: None,
+            network: None,
+            commands: None,
+            pending: None,
+            joined: false,
+            listed: HashSet::new(),
+            session: None,
+            pending_pack: None,
+            tab_list: None,
+            shown_ping: None,
+            stored_ping: None,
         })
     }
 }
@@ -85,6 +155,22 @@ impl
             peers,
             writer,
             reader,
+            state: State::Handshaking,
+            connected_at: Instant::now(),
+            last_read: Instant::now(),
+            keep_alive: None,
+            closed: false,
+            capture: None,
+            network: None,
+            commands: None,
+            pending: None,
+            joined: false,
+            listed: HashSet::new(),
+            session: None,
+            pending_pack: None,
+            tab_list: None,
+            shown_ping: None,
+            stored_ping: None,
         })
     }
 }
@@ -96,6 +182,8 @@ impl ServerAdapter {
         sender: AsyncSender<Packet762>,
     ) -> anyhow::Result<()> {
         loop {
+            let deadline = self.deadline();
+
             tokio::select! {
                 message = receiver.recv() => {
                     match message {
@@ -109,8 +197,11 @@ impl ServerAdapter {
                     }
                 },
                 message = self.reader.read_packet_async::<RawPacket762>() => {
+                    self.last_read = Instant::now();
+
                     match message {
                         Ok(Some(packet)) => {
+                            self.capture(&packet).await;
                             self.on_receive(packet, sender.clone()).await?;
                         },
                         Ok(None) => {
@@ -123,6 +214,15 @@ impl ServerAdapter {
                             break;
                         }
                     }
+                },
+                _ = tokio::time::sleep_until(deadline) => {
+                    warn!("Backend of {} stopped responding", self.client_address);
+                    // inform the client about the lost backend, the client adapter closes itself afterwards
+                    if let Some(packet) = disconnect_packet(self.state, "The server stopped responding") {
+                        sender.send(packet).await?;
+                    }
+                    receiver.close();
+                    break;
                 }
             }
         }
@@ -134,10 +234,12 @@ impl ServerAdapter {
 impl ClientAdapter {
     pub async fn run(
         mut self,
-        receiver: AsyncReceiver<Packet762>,
-        sender: AsyncSender<Packet762>,
+        mut receiver: AsyncReceiver<Packet762>,
+        mut sender: AsyncSender<Packet762>,
     ) -> anyhow::Result<()> {
-        loop {
+        while !self.closed {
+            let deadline = self.deadline();
+
             tokio::select! {
                 message = receiver.recv() => {
                     match message {
@@ -146,13 +248,23 @@ impl ClientAdapter {
                         },
                         Err(_) => {
                             receiver.close();
-                            break;
+                            match self.fallback().await? {
+                                Some((limbo_receiver, limbo_sender)) => {
+                                    sender.close();
+                                    receiver = limbo_receiver;
+                                    sender = limbo_sender;
+                                }
+                                None => break,
+                            }
                         }
                     }
                 },
                 message = self.reader.read_packet_async::<RawPacket762>() => {
+                    self.last_read = Instant::now();
+
                     match message {
                         Ok(Some(packet)) => {
+                            self.capture(&packet).await;
                             self.on_receive(packet, sender.clone()).await?;
                         },
                         Ok(None) => {
@@ -165,12 +277,263 @@ impl ClientAdapter {
                             break;
                         }
                     }
+                },
+                event = next_network_event(&mut self.network) => {
+                    self.on_network_event(event).await?;
+                },
+                command = next_command(&self.commands) => {
+                    match command {
+                        ConnectionCommand::Connect(backend) => self.connect(backend).await?,
+                        ConnectionCommand::Send(packet) => self.send_packet(packet).await?,
+                        ConnectionCommand::Disconnect(reason) => self.disconnect(reason.as_str()).await?,
+                    }
+                },
+                message = next_pending(&self.pending) => {
+                    match message {
+                        Some(Packet762::PlayLogin(login)) => {
+                            // the new backend accepted the player, the previous one is closed
+                            let pending = self.pending.take().unwrap();
+                            receiver.close();
+                            sender.close();
+                            receiver = pending.receiver;
+                            sender = pending.sender;
+                            info!("Moved {} to {}", self.client_address, pending.backend);
+
+                            self.peers
+                                .lock()
+                                .await
+                                .get_mut(&self.client_address)
+                                .unwrap()
+                                .set_backend(Some(pending.backend));
+                            QUEUE.remove(&self.client_address).await;
+                            self.on_send(Packet762::PlayLogin(login)).await?;
+                            // the new backend has to know the settings of the client
+                            let settings = self
+                                .peers
+                                .lock()
+                                .await
+                                .get(&self.client_address)
+                                .unwrap()
+                                .settings()
+                                .clone();
+                            if let Some(settings) = settings {
+                                sender.send(Packet762::PlayClientSettings(settings)).await?;
+                            }
+                            // the client only sends its chat session once after joining
+                            if let Some(session) = self.session.clone() {
+                                if CONFIG.secure_chat != SecureChat::Strip {
+                                    sender.send(Packet762::PlayPlayerSession(session)).await?;
+                                }
+                            }
+                        },
+                        Some(packet) => self.on_pending(packet).await?,
+                        None => self.abort_pending(Chat::from_text("The server is not reachable")).await?,
+                    }
+                },
+                _ = tokio::time::sleep_until(deadline) => {
+                    info!("Client {} timed out in state {:?}", self.client_address, self.state);
+                    self.disconnect("Timed out").await?;
+                }
+            }
+        }
+        // the backend connection is useless without the client
+        sender.close();
+
+        Ok(())
+    }
+
+    /// Start moving the client to another backend. The current backend stays connected until
+    /// the new one sent the join game.
+    async fn connect(&mut self, backend: String) -> anyhow::Result<()> {
+        let peers = self.peers.lock().await;
+        let connection = peers.get(&self.client_address).unwrap();
+        if self.pending.is_some() || Some(&backend).eq(&connection.backend().as_ref()) {
+            return Ok(());
+        }
+        // the mods of the client were negotiated with the current backend during the login
+        if connection
+            .forge()
+            .as_ref()
+            .map(|marker| marker.requires_login_handshake())
+            .unwrap_or_default()
+        {
+            drop(peers);
+            return self
+                .send_packet(limbo::message("Modded clients can not switch the server"))
+                .await;
+        }
+        let login = connection
+            .login()
+            .clone()
+            .ok_or(anyhow::anyhow!("Client is not logged in"))?;
+        let hostname = connection.hostname().clone().unwrap_or_default();
+        drop(peers);
+
+        let (client_sender, client_receiver) = kanal::unbounded_async::<Packet762>();
+        let (server_sender, server_receiver) = kanal::unbounded_async::<Packet762>();
+        let peers = self.peers.clone();
+        let address = self.client_address;
+        let target = backend.clone();
+        tokio::spawn(async move {
+            let connection = CraftTokioConnection::connect_server_tokio(target.as_str()).await?;
+            let mut adapter = ServerAdapter::try_from((connection, peers, address))?;
+            if let Some(directory) = CONFIG.capture.as_ref() {
+                adapter.enable_capture(directory.as_path()).await?;
+            }
+
+            adapter.run(server_receiver, client_sender).await
+        });
+
+        // the backend login is performed like the initial one, a failed send is detected by
+        // the closed receiver
+        let _ = server_sender
+            .send(Packet762::Handshake(HandshakeSpec {
+                version: VarInt(762),
+                server_address: hostname,
+                server_port: 25565,
+                next_state: HandshakeNextState::Login,
+            }))
+            .await;
+        let _ = server_sender.send(Packet762::LoginStart(login)).await;
+
+        debug!("Moving {} to {}", self.client_address, backend);
+        self.pending = Some(PendingBackend {
+            backend,
+            receiver: client_receiver,
+            sender: server_sender,
+        });
+
+        Ok(())
+    }
+
+    // move a playing client into the limbo after its backend stopped, where it waits in the
+    // queue for the group of its route
+    async fn fallback(
+        &mut self,
+    ) -> anyhow::Result<Option<(AsyncReceiver<Packet762>, AsyncSender<Packet762>)>> {
+        if !CONFIG.limbo.fallback || !CONFIG.queue.enabled || !self.joined || self.closed {
+            return Ok(None);
+        }
+
+        let mut peers = self.peers.lock().await;
+        let connection = peers.get_mut(&self.client_address).unwrap();
+        let login = match connection.login().clone() {
+            Some(login) => login,
+            None => return Ok(None),
+        };
+        let route = routing::resolve(connection.hostname().as_deref().unwrap_or_default());
+        let previous = connection.backend().clone();
+        connection.set_backend(None);
+        drop(peers);
+
+        // a pending move would replace the limbo anyway
+        if let Some(pending) = self.pending.take() {
+            pending.receiver.close();
+            pending.sender.close();
+        }
+        info!(
+            "Backend {:?} of {} stopped, moving it to the limbo",
+            previous, self.client_address
+        );
+        let channels = Limbo::spawn(login).await?;
+        QUEUE
+            .enqueue(route.group.as_str(), self.client_address)
+            .await;
+        self.send_packet(limbo::message(
+            "The server you were playing on stopped, you have been queued for another one",
+        ))
+        .await?;
+
+        Ok(Some(channels))
+    }
+
+    // handle the packets of the pending backend before its join game
+    async fn on_pending(&mut self, packet: Packet762) -> anyhow::Result<()> {
+        match packet {
+            // the client already left the login, so the proxy declines all login requests
+            Packet762::LoginPluginRequest(request) => {
+                if let Some(pending) = self.pending.as_ref() {
+                    let _ = pending
+                        .sender
+                        .send(Packet762::LoginPluginResponse(LoginPluginResponseSpec {
+                            message_id: request.message_id,
+                            successful: false,
+                            data: RemainingBytes { data: vec![] },
+                        }))
+                        .await;
                 }
             }
+            Packet762::LoginDisconnect(disconnect) => {
+                self.abort_pending(disconnect.message).await?;
+            }
+            Packet762::PlayDisconnect(disconnect) => {
+                self.abort_pending(disconnect.reason).await?;
+            }
+            // the login success is already known to the client
+            _ => {}
         }
 
         Ok(())
     }
+
+    // drop the pending backend and keep the client on the current one
+    async fn abort_pending(&mut self, reason: Chat) -> anyhow::Result<()> {
+        if let Some(pending) = self.pending.take() {
+            info!(
+                "Moving {} to {} failed: {:?}",
+                self.client_address, pending.backend, reason
+            );
+            pending.receiver.close();
+            pending.sender.close();
+
+            self.send_packet(Packet762::PlaySystemChatMessage(play::system_message(
+                reason,
+            )))
+            .await?;
+        }
+
+        Ok(())
+    }
+
+    /// Wait for the handshake of the client, which is required in order to choose the backend.
+    pub async fn read_handshake(&mut self) -> anyhow::Result<HandshakeSpec> {
+        let packet = tokio::time::timeout(
+            CONFIG.timeouts.handshake(),
+            self.reader.read_packet_async::<RawPacket762>(),
+        )
+        .await
+        .map_err(|_| anyhow::anyhow!("Client did not send a handshake in time"))??
+        .ok_or(anyhow::anyhow!("Client disconnected before the handshake"))?;
+        self.last_read = Instant::now();
+        self.capture(&packet).await;
+
+        match packet {
+            Packet762::Handshake(handshake) => Ok(handshake),
+            packet => anyhow::bail!("Expected a handshake, received {:?}", packet.id()),
+        }
+    }
+
+    /// Remember the keep alive sent by the backend in order to measure the round trip time of the
+    /// client once it answers.
+    pub fn track_keep_alive(&mut self, id: i64) {
+        // a keep alive which was not answered in time is detected by the deadline, so we
+        // only track the oldest one here
+        if self.keep_alive.is_none() {
+            self.keep_alive = Some((id, Instant::now()));
+        }
+    }
+
+    /// Resolve a keep alive answered by the client. Returns the measured ping if the id matches
+    /// the tracked one.
+    pub fn resolve_keep_alive(&mut self, id: i64) -> Option<std::time::Duration> {
+        match self.keep_alive {
+            Some((expected, sent_at)) if expected == id => {
+                self.keep_alive = None;
+                Some(sent_at.elapsed())
+            }
+            _ => None,
+        }
+    }
 }
 
 impl<
@@ -179,8 +542,134 @@ impl<
     > Adapter<W, R>
 {
     pub async fn send_packet(&mut self, packet: Packet762) -> anyhow::Result<()> {
+        self.capture(&packet).await;
         self.writer.write_packet_async(packet).await?;
 
         Ok(())
     }
+
+    /// Start recording all packets passing this adapter into a new file in the given directory.
+    pub async fn enable_capture(&mut self, directory: &Path) -> anyhow::Result<()> {
+        let timestamp = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+        let name = format!(
+            "{}-{}-{}.ycap",
+            self.client_address.to_string().replace(':', "_"),
+            if self.client { "client" } else { "server" },
+            timestamp
+        );
+        self.capture = Some(PacketCapture::create(directory.join(name).as_path()).await?);
+        debug!(
+            "Capturing packets of {} into {:?}",
+            self.client_address, directory
+        );
+
+        Ok(())
+    }
+
+    async fn capture(&mut self, packet: &Packet762) {
+        if let Some(capture) = self.capture.as_mut() {
+            // a broken capture must never affect the connection itself
+            if let Err(error) = capture.record(packet).await {
+                warn!("Stopping capture of {}: {:?}", self.client_address, error);
+                self.capture = None;
+            }
+        }
+    }
+
+    pub fn state(&self) -> State {
+        self.state
+    }
+
+    /// Switch the protocol state of the reader and the writer.
+    pub fn set_state(&mut self, state: State) {
+        self.reader.set_state(state);
+        self.writer.set_state(state);
+        self.state = state;
+    }
+
+    /// Send a disconnect with the given reason (if the current state supports it) and stop
+    /// the adapter afterwards.
+    pub async fn disconnect(&mut self, reason: &str) -> anyhow::Result<()> {
+        if let Some(packet) = disconnect_packet(self.state, reason) {
+            self.send_packet(packet).await?;
+        }
+        self.close();
+
+        Ok(())
+    }
+
+    /// Stop the adapter after the currently processed packet.
+    pub fn close(&mut self) {
+        self.closed = true;
+    }
+
+    /// Determine the point in time at which the peer is considered dead.
+    fn deadline(&self) -> Instant {
+        let timeouts = &CONFIG.timeouts;
+
+        match self.state {
+            State::Handshaking if self.client => self.connected_at + timeouts.handshake(),
+            State::Login if self.client => self.connected_at + timeouts.login(),
+            _ => {
+                let deadline = self.last_read + timeouts.read();
+                // a client which does not answer keep alive packets is dead, even if it keeps
+                // sending other packets
+                match self.keep_alive {
+                    Some((_, sent_at)) => deadline.min(sent_at + timeouts.keep_alive()),
+                    None => deadline,
+                }
+            }
+        }
+    }
+}
+
+// wait for the next command issued to the connection
+async fn next_command(commands: &Option<AsyncReceiver<ConnectionCommand>>) -> ConnectionCommand {
+    match commands.as_ref() {
+        Some(commands) => match commands.recv().await {
+            Ok(command) => command,
+            Err(_) => std::future::pending().await,
+        },
+        None => std::future::pending().await,
+    }
+}
+
+// wait for the next packet of the pending backend, `None` if it closed the connection
+async fn next_pending(pending: &Option<PendingBackend>) -> Option<Packet762> {
+    match pending.as_ref() {
+        Some(pending) => pending.receiver.recv().await.ok(),
+        None => std::future::pending().await,
+    }
+}
+
+// wait for the next event of the network, never resolves without a subscription
+async fn next_network_event(
+    receiver: &mut Option<broadcast::Receiver<NetworkEvent>>,
+) -> NetworkEvent {
+    let receiver = match receiver.as_mut() {
+        Some(receiver) => receiver,
+        None => return std::future::pending().await,
+    };
+
+    loop {
+        match receiver.recv().await {
+            Ok(event) => return event,
+            // missed events only affect the tab list until the next join
+            Err(RecvError::Lagged(skipped)) => warn!("Skipped {skipped} network events"),
+            Err(RecvError::Closed) => return std::future::pending().await,
+        }
+    }
+}
+
+/// Build the disconnect packet appropriate for the given state.
+pub fn disconnect_packet(state: State, reason: &str) -> Option<Packet762> {
+    match state {
+        State::Login => Some(Packet762::LoginDisconnect(LoginDisconnectSpec {
+            message: Chat::from_text(reason),
+        })),
+        State::Play => Some(Packet762::PlayDisconnect(PlayDisconnectSpec {
+            reason: Chat::from_text(reason),
+        })),
+        _ => None,
+    }
 }
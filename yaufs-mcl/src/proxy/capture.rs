@@ -0,0 +1,292 @@
+/*
+ *    Copyright  2023.  Fritz Ochsmann
+ *
+ *    Licensed under the Apache License, Version 2.0 (the "License");
+ *    you may not use this file except in compliance with the License.
+ *    You may obtain a copy of the License at
+ *
+ *        http://www.apache.org/licenses/LICENSE-2.0
+ *
+ *    Unless required by applicable law or agreed to in writing, software
+ *    distributed under the License is distributed on an "AS IS" BASIS,
+ *    WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ *    See the License for the specific language governing permissions and
+ *    limitations under the License.
+ */
+
+use crate::proxy::interceptor::PacketInterceptor;
+use kanal::AsyncSender;
+use std::path::Path;
+use tokio::fs::File;
+use tokio::io::{AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::time::Instant;
+use yaufs_common::mcproto_rs::protocol::{Id, Packet, PacketDirection, RawPacket};
+use yaufs_common::net::packet::{Packet762, RawPacket762};
+use yaufs_common::protocol::State;
+use yaufs_common::BytesSerializer;
+
+// every capture file starts with the magic followed by the format version
+const MAGIC: &[u8; 4] = b"YCAP";
+const VERSION: u8 = 1;
+
+/// A single packet of a capture. The body is stored decoded, which means before the encryption
+/// and the compression of the connection were applied.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CapturedPacket {
+    /// milliseconds since the start of the capture
+    pub elapsed: u64,
+    pub direction: PacketDirection,
+    pub state: State,
+    pub id: i32,
+    pub body: Vec<u8>,
+}
+
+impl CapturedPacket {
+    pub fn from_packet(packet: &Packet762, elapsed: u64) -> anyhow::Result<Self> {
+        let id = packet.id();
+        let mut serializer = BytesSerializer::default();
+        packet
+            .mc_serialize_body(&mut serializer)
+            .map_err(|error| anyhow::anyhow!("Error while serializing packet: {:?}", error))?;
+
+        Ok(Self {
+            elapsed,
+            direction: id.direction,
+            state: id.state,
+            id: id.id,
+            body: serializer.into_bytes(),
+        })
+    }
+
+    /// Decode the captured body into the packet again.
+    pub fn decode(&self) -> anyhow::Result<Packet762> {
+        let id = Id {
+            id: self.id,
+            state: self.state,
+            direction: self.direction,
+        };
+
+        RawPacket762::create(id, self.body.as_slice())
+            .and_then(|raw| raw.deserialize())
+            .map_err(|error| anyhow::anyhow!("Error while decoding captured packet: {:?}", error))
+    }
+
+    fn encode(&self) -> Vec<u8> {
+        let mut buffer = Vec::with_capacity(18 + self.body.len());
+        buffer.extend_from_slice(&self.elapsed.to_be_bytes());
+        buffer.push(match self.direction {
+            PacketDirection::ServerBound => 0,
+            PacketDirection::ClientBound => 1,
+        });
+        buffer.push(match self.state {
+            State::Handshaking => 0,
+            State::Status => 1,
+            State::Login => 2,
+            State::Play => 3,
+        });
+        buffer.extend_from_slice(&self.id.to_be_bytes());
+        buffer.extend_from_slice(&(self.body.len() as u32).to_be_bytes());
+        buffer.extend_from_slice(self.body.as_slice());
+
+        buffer
+    }
+}
+
+/// Records all packets passing an adapter into a file.
+pub struct PacketCapture {
+    file: File,
+    started: Instant,
+}
+
+impl PacketCapture {
+    pub async fn create(path: &Path) -> anyhow::Result<Self> {
+        let mut file = File::create(path).await?;
+        file.write_all(MAGIC).await?;
+        file.write_u8(VERSION).await?;
+
+        Ok(Self {
+            file,
+            started: Instant::now(),
+        })
+    }
+
+    pub async fn record(&mut self, packet: &Packet762) -> anyhow::Result<()> {
+        let elapsed = self.started.elapsed().as_millis() as u64;
+        let captured = CapturedPacket::from_packet(packet, elapsed)?;
+        // write the record at once, so an aborted connection leaves no partial records behind
+        self.file.write_all(captured.encode().as_slice()).await?;
+
+        Ok(())
+    }
+}
+
+/// Read all packets of a capture file.
+pub async fn read_capture(path: &Path) -> anyhow::Result<Vec<CapturedPacket>> {
+    let mut reader = BufReader::new(File::open(path).await?);
+
+    let mut magic = [0u8; 4];
+    reader.read_exact(&mut magic).await?;
+    if !magic.eq(MAGIC) {
+        anyhow::bail!("Invalid capture file: missing magic");
+    }
+    let version = reader.read_u8().await?;
+    if version != VERSION {
+        anyhow::bail!("Unsupported capture version {version}");
+    }
+
+    let mut packets = Vec::new();
+    loop {
+        // the end of the file can only be reached at the start of a record
+        let elapsed = match reader.read_u64().await {
+            Ok(elapsed) => elapsed,
+            Err(error) if error.kind() == std::io::ErrorKind::UnexpectedEof => break,
+            Err(error) => return Err(error.into()),
+        };
+        let direction = match reader.read_u8().await? {
+            0 => PacketDirection::ServerBound,
+            1 => PacketDirection::ClientBound,
+            other => anyhow::bail!("Invalid packet direction {other}"),
+        };
+        let state = match reader.read_u8().await? {
+            0 => State::Handshaking,
+            1 => State::Status,
+            2 => State::Login,
+            3 => State::Play,
+            other => anyhow::bail!("Invalid protocol state {other}"),
+        };
+        let id = reader.read_i32().await?;
+        let length = reader.read_u32().await? as usize;
+        let mut body = vec![0u8; length];
+        reader.read_exact(&mut body).await?;
+
+        packets.push(CapturedPacket {
+            elapsed,
+            direction,
+            state,
+            id,
+            body,
+        });
+    }
+
+    Ok(packets)
+}
+
+/// Feed a capture into an interceptor. Packets which were received by the captured adapter are
+/// passed to `on_receive`, while packets sent by it are passed to `on_send`.
+pub async fn replay<I>(
+    interceptor: &mut I,
+    received: PacketDirection,
+    packets: &[CapturedPacket],
+    sender: AsyncSender<Packet762>,
+) -> anyhow::Result<()>
+where
+    I: PacketInterceptor + Send,
+{
+    for captured in packets {
+        let packet = captured.decode()?;
+
+        if captured.direction == received {
+            interceptor.on_receive(packet, sender.clone()).await?;
+        } else {
+            interceptor.on_send(packet).await?;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::proxy::adapter::{Adapter, ServerAdapter};
+    use crate::proxy::connection::ProxyConnection;
+    use std::collections::HashMap;
+    use std::net::SocketAddr;
+    use std::sync::Arc;
+    use tokio::net::TcpListener;
+    use tokio::sync::Mutex;
+    use yaufs_common::craftio_rs::CraftTokioConnection;
+    use yaufs_common::net::packet::{LoginSuccessSpec, PlayServerKeepAliveSpec};
+    use yaufs_common::types::CountedArray;
+    use yaufs_common::uuid::UUID4;
+
+    async fn server_adapter() -> anyhow::Result<(ServerAdapter, TcpListener)> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let address = listener.local_addr()?;
+        let connection = CraftTokioConnection::connect_server_tokio(address).await?;
+
+        let client_address: SocketAddr = "127.0.0.1:1".parse()?;
+        let mut peers = HashMap::new();
+        peers.insert(client_address, ProxyConnection::default());
+        let adapter = Adapter::try_from((connection, Arc::new(Mutex::new(peers)), client_address))?;
+
+        Ok((adapter, listener))
+    }
+
+    #[tokio::test]
+    async fn test_capture_roundtrip() -> anyhow::Result<()> {
+        let path = std::env::temp_dir().join(format!("yaufs-{}.ycap", std::process::id()));
+
+        let packets = vec![
+            Packet762::LoginSuccess(LoginSuccessSpec {
+                uuid: UUID4::random(),
+                username: "yaufs".to_owned(),
+                properties: CountedArray::from(vec![]),
+            }),
+            Packet762::PlayServerKeepAlive(PlayServerKeepAliveSpec { id: 42 }),
+        ];
+        let mut capture = PacketCapture::create(path.as_path()).await?;
+        for packet in packets.iter() {
+            capture.record(packet).await?;
+        }
+        drop(capture);
+
+        let captured = read_capture(path.as_path()).await?;
+        assert_eq!(captured.len(), 2);
+        assert_eq!(captured[0].state, State::Login);
+        assert_eq!(captured[1].state, State::Play);
+        assert_eq!(captured[1].direction, PacketDirection::ClientBound);
+        assert!(matches!(
+            captured[1].decode()?,
+            Packet762::PlayServerKeepAlive(PlayServerKeepAliveSpec { id: 42 })
+        ));
+
+        tokio::fs::remove_file(path).await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_replay_server_login() -> anyhow::Result<()> {
+        let (mut adapter, _listener) = server_adapter().await?;
+        adapter.set_state(State::Login);
+        let (sender, receiver) = kanal::unbounded_async::<Packet762>();
+
+        let packets = vec![
+            Packet762::LoginSuccess(LoginSuccessSpec {
+                uuid: UUID4::random(),
+                username: "yaufs".to_owned(),
+                properties: CountedArray::from(vec![]),
+            }),
+            Packet762::PlayServerKeepAlive(PlayServerKeepAliveSpec { id: 7 }),
+        ]
+        .iter()
+        .map(|packet| CapturedPacket::from_packet(packet, 0))
+        .collect::<anyhow::Result<Vec<CapturedPacket>>>()?;
+        replay(
+            &mut adapter,
+            PacketDirection::ClientBound,
+            packets.as_slice(),
+            sender,
+        )
+        .await?;
+
+        // the login success is handled by the proxy, everything afterwards reaches the client
+        assert_eq!(adapter.state(), State::Play);
+        assert!(matches!(
+            receiver.recv().await?,
+            Packet762::PlayServerKeepAlive(PlayServerKeepAliveSpec { id: 7 })
+        ));
+
+        Ok(())
+    }
+}
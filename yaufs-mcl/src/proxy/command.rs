@@ -0,0 +1,147 @@
+/*
+ *    Copyright  2023.  Fritz Ochsmann
+ *
+ *    Licensed under the Apache License, Version 2.0 (the "License");
+ *    you may not use this file except in compliance with the License.
+ *    You may obtain a copy of the License at
+ *
+ *        http://www.apache.org/licenses/LICENSE-2.0
+ *
+ *    Unless required by applicable law or agreed to in writing, software
+ *    distributed under the License is distributed on an "AS IS" BASIS,
+ *    WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ *    See the License for the specific language governing permissions and
+ *    limitations under the License.
+ */
+
+use crate::proxy::moderation::{self, PunishmentKind};
+use std::time::Duration;
+
+/// Commands handled by the proxy itself, which never reach the backend.
+#[derive(Debug, PartialEq)]
+pub enum ProxyCommand {
+    /// `/mute` and `/ban <player> [duration] [reason]`, permanent without a duration
+    Punish {
+        kind: PunishmentKind,
+        player: String,
+        duration: Option<Duration>,
+        reason: String,
+    },
+    /// `/unmute` and `/unban <player>`
+    Pardon {
+        kind: PunishmentKind,
+        player: String,
+    },
+}
+
+impl ProxyCommand {
+    /// Parse a command sent by the client. Commands unknown to the proxy are `None` and belong
+    /// to the backend, invalid arguments result in the usage of the command.
+    pub fn parse(command: &str) -> Option<Result<Self, &'static str>> {
+        let mut arguments = command.split_whitespace();
+
+        match arguments.next()? {
+            "mute" => Some(parse_punish(PunishmentKind::Mute, arguments)),
+            "ban" => Some(parse_punish(PunishmentKind::Ban, arguments)),
+            "unmute" => Some(parse_pardon(PunishmentKind::Mute, arguments)),
+            "unban" => Some(parse_pardon(PunishmentKind::Ban, arguments)),
+            _ => None,
+        }
+    }
+
+    /// The permission node required to execute the command.
+    pub fn permission(&self) -> &'static str {
+        match self {
+            ProxyCommand::Punish { kind, .. } => match kind {
+                PunishmentKind::Mute => "proxy.command.mute",
+                PunishmentKind::Ban => "proxy.command.ban",
+            },
+            ProxyCommand::Pardon { kind, .. } => match kind {
+                PunishmentKind::Mute => "proxy.command.unmute",
+                PunishmentKind::Ban => "proxy.command.unban",
+            },
+        }
+    }
+}
+
+fn parse_punish<'a>(
+    kind: PunishmentKind,
+    mut arguments: impl Iterator<Item = &'a str>,
+) -> Result<ProxyCommand, &'static str> {
+    let usage = match kind {
+        PunishmentKind::Mute => "Usage: /mute <player> [duration] [reason]",
+        PunishmentKind::Ban => "Usage: /ban <player> [duration] [reason]",
+    };
+    let player = arguments.next().ok_or(usage)?.to_owned();
+    let mut arguments = arguments.peekable();
+    // the duration is optional, so anything else is the start of the reason
+    let duration = arguments
+        .peek()
+        .and_then(|duration| moderation::parse_duration(duration));
+    if duration.is_some() {
+        arguments.next();
+    }
+    let reason = arguments.collect::<Vec<&str>>().join(" ");
+
+    Ok(ProxyCommand::Punish {
+        kind,
+        player,
+        duration,
+        reason,
+    })
+}
+
+fn parse_pardon<'a>(
+    kind: PunishmentKind,
+    mut arguments: impl Iterator<Item = &'a str>,
+) -> Result<ProxyCommand, &'static str> {
+    let usage = match kind {
+        PunishmentKind::Mute => "Usage: /unmute <player>",
+        PunishmentKind::Ban => "Usage: /unban <player>",
+    };
+    let player = arguments.next().ok_or(usage)?.to_owned();
+    if arguments.next().is_some() {
+        return Err(usage);
+    }
+
+    Ok(ProxyCommand::Pardon { kind, player })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_punishments() {
+        assert_eq!(
+            ProxyCommand::parse("mute steve 10m spamming the chat"),
+            Some(Ok(ProxyCommand::Punish {
+                kind: PunishmentKind::Mute,
+                player: "steve".to_owned(),
+                duration: Some(Duration::from_secs(600)),
+                reason: "spamming the chat".to_owned(),
+            }))
+        );
+        assert_eq!(
+            ProxyCommand::parse("ban steve griefing"),
+            Some(Ok(ProxyCommand::Punish {
+                kind: PunishmentKind::Ban,
+                player: "steve".to_owned(),
+                duration: None,
+                reason: "griefing".to_owned(),
+            }))
+        );
+        assert_eq!(
+            ProxyCommand::parse("unban steve"),
+            Some(Ok(ProxyCommand::Pardon {
+                kind: PunishmentKind::Ban,
+                player: "steve".to_owned(),
+            }))
+        );
+        assert!(matches!(ProxyCommand::parse("mute"), Some(Err(_))));
+        assert!(matches!(
+            ProxyCommand::parse("unmute steve alex"),
+            Some(Err(_))
+        ));
+    }
+}
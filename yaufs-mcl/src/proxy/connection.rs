@@ -14,25 +14,193 @@
  *    limitations under the License.
  */
 
-use yaufs_common::net::packet::LoginStartSpec;
+use crate::proxy::forge::ForgeMarker;
+use crate::proxy::network;
+use crate::proxy::permission;
+use kanal::AsyncSender;
+use std::any::{Any, TypeId};
+use std::collections::{HashMap, HashSet};
+use std::fmt::{Debug, Formatter};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use yaufs_common::net::packet::{
+    LoginStartSpec, LoginSuccessSpec, Packet762, PlayClientSettingsSpec,
+};
 use yaufs_common::protocol::State;
 use yaufs_common::types::{CountedArray, VarInt};
+use yaufs_common::yaufs_proto::fluvio::{PlayerJoined, PlayerSession};
 
+/// The session of a client, which is shared by its adapters and the rest of the proxy.
 #[derive(Debug, Getters, Setters)]
 #[get = "pub"]
 #[set = "pub"]
 pub struct ProxyConnection {
     state: State,
+    // protocol version of the handshake
+    protocol_version: i32,
+    connected_at: SystemTime,
     client_verify_token: Option<CountedArray<u8, VarInt>>,
     login: Option<LoginStartSpec>,
+    // the profile verified by the session server
+    profile: Option<LoginSuccessSpec>,
+    // hostname of the handshake without any forge marker
+    hostname: Option<String>,
+    forge: Option<ForgeMarker>,
+    // round trip time measured by the last answered keep alive
+    ping: Option<Duration>,
+    // address of the backend the client is connected to
+    backend: Option<String>,
+    // permission nodes granted to the player
+    permissions: HashSet<String>,
+    // commands executed by the client adapter of the connection
+    commands: Option<AsyncSender<ConnectionCommand>>,
+    // brand of the client (e.g. vanilla or fabric)
+    brand: Option<String>,
+    // the settings of the client, which every backend has to know
+    settings: Option<PlayClientSettingsSpec>,
+    #[getset(skip)]
+    metadata: Metadata,
+}
+
+/// Typed values attached to a connection, at most one per type.
+#[derive(Default)]
+pub struct Metadata(HashMap<TypeId, Box<dyn Any + Send + Sync>>);
+
+impl Debug for Metadata {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Metadata")
+            .field("entries", &self.0.len())
+            .finish()
+    }
+}
+
+/// Commands issued to a connection from outside of its adapters (e.g. by the queue).
+#[derive(Debug)]
+pub enum ConnectionCommand {
+    /// move the player to the given backend
+    Connect(String),
+    /// send a packet to the client
+    Send(Packet762),
+    /// disconnect the player with the given reason
+    Disconnect(String),
 }
 
 impl Default for ProxyConnection {
     fn default() -> Self {
         Self {
             state: State::Handshaking,
+            protocol_version: 0,
+            connected_at: SystemTime::now(),
             client_verify_token: None,
             login: None,
+            profile: None,
+            hostname: None,
+            forge: None,
+            ping: None,
+            backend: None,
+            permissions: HashSet::new(),
+            commands: None,
+            brand: None,
+            settings: None,
+            metadata: Metadata::default(),
         }
     }
 }
+
+impl ProxyConnection {
+    pub fn has_permission(&self, permission: &str) -> bool {
+        permission::matches(&self.permissions, permission)
+    }
+
+    pub fn metadata<T: Any + Send + Sync>(&self) -> Option<&T> {
+        self.metadata
+            .0
+            .get(&TypeId::of::<T>())
+            .and_then(|value| value.downcast_ref::<T>())
+    }
+
+    pub fn metadata_mut<T: Any + Send + Sync>(&mut self) -> Option<&mut T> {
+        self.metadata
+            .0
+            .get_mut(&TypeId::of::<T>())
+            .and_then(|value| value.downcast_mut::<T>())
+    }
+
+    /// Attach a value to the connection, replacing the previous value of the same type.
+    pub fn insert_metadata<T: Any + Send + Sync>(&mut self, value: T) {
+        self.metadata.0.insert(TypeId::of::<T>(), Box::new(value));
+    }
+
+    pub fn remove_metadata<T: Any + Send + Sync>(&mut self) -> Option<T> {
+        self.metadata
+            .0
+            .remove(&TypeId::of::<T>())
+            .and_then(|value| value.downcast::<T>().ok())
+            .map(|value| *value)
+    }
+
+    // TODO: the sessions are not queryable from the admin api of the control plane yet, which
+    //  has to read them from the session store of the network
+    /// Describe the player for the session store of the network. Players are only known to
+    /// the network once they are authenticated and connected to a backend.
+    pub fn player(&self, proxy: &str) -> Option<PlayerJoined> {
+        let (profile, backend) = match (self.profile.as_ref(), self.backend.as_ref()) {
+            (Some(profile), Some(backend)) => (profile, backend),
+            _ => return None,
+        };
+
+        Some(PlayerJoined {
+            proxy: proxy.to_owned(),
+            uuid: profile.uuid.to_string(),
+            name: profile.username.clone(),
+            properties: network::to_properties(profile.properties.as_slice()),
+            backend: backend.clone(),
+            session: Some(PlayerSession {
+                protocol_version: self.protocol_version,
+                connected_at: self
+                    .connected_at
+                    .duration_since(UNIX_EPOCH)
+                    .unwrap_or_default()
+                    .as_secs(),
+                brand: self.brand.clone(),
+                locale: self
+                    .settings
+                    .as_ref()
+                    .map(|settings| settings.locale.clone()),
+                view_distance: self
+                    .settings
+                    .as_ref()
+                    .map(|settings| settings.view_distance),
+                ping: self.ping.map(|ping| ping.as_millis() as u64),
+            }),
+        })
+    }
+
+    /// Issue a command to the connection. Commands to closed connections are dropped.
+    pub async fn command(&self, command: ConnectionCommand) {
+        if let Some(commands) = self.commands.as_ref() {
+            let _ = commands.send(command).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, PartialEq)]
+    struct Marker(u32);
+
+    #[test]
+    fn test_typed_metadata() {
+        let mut connection = ProxyConnection::default();
+        assert_eq!(connection.metadata::<Marker>(), None);
+
+        connection.insert_metadata(Marker(1));
+        connection.insert_metadata(Marker(2));
+        connection.insert_metadata(String::from("other"));
+        assert_eq!(connection.metadata::<Marker>(), Some(&Marker(2)));
+        assert_eq!(connection.remove_metadata::<Marker>(), Some(Marker(2)));
+        assert_eq!(connection.metadata::<Marker>(), None);
+        assert_eq!(connection.metadata::<String>().unwrap(), "other");
+    }
+}
@@ -0,0 +1,52 @@
+/*
+ *    Copyright  2023.  Fritz Ochsmann
+ *
+ *    Licensed under the Apache License, Version 2.0 (the "License");
+ *    you may not use this file except in compliance with the License.
+ *    You may obtain a copy of the License at
+ *
+ *        http://www.apache.org/licenses/LICENSE-2.0
+ *
+ *    Unless required by applicable law or agreed to in writing, software
+ *    distributed under the License is distributed on an "AS IS" BASIS,
+ *    WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ *    See the License for the specific language governing permissions and
+ *    limitations under the License.
+ */
+
+/// The login plugin channel used by FML2 and FML3 to wrap the mod handshake.
+pub const LOGIN_WRAPPER_CHANNEL: &str = "fml:loginwrapper";
+
+/// Marker appended by forge clients to the server address of the handshake.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ForgeMarker {
+    // legacy forge (1.12 and older)
+    Fml,
+    Fml2,
+    Fml3,
+}
+
+impl ForgeMarker {
+    /// Mod lists of FML2 and FML3 are negotiated during the login via `fml:loginwrapper`. Since
+    /// the client can not enter the login state again, it stays on the backend it logged in to
+    /// and every switch of the server is refused.
+    pub fn requires_login_handshake(&self) -> bool {
+        !matches!(self, Self::Fml)
+    }
+}
+
+/// Split the server address of a handshake into the hostname and the forge marker. The address
+/// forwarded to the backend is not changed, so the marker always reaches it.
+pub fn split_server_address(address: &str) -> (&str, Option<ForgeMarker>) {
+    let mut parts = address.split('\0');
+    let hostname = parts.next().unwrap_or_default();
+
+    let marker = parts.find_map(|part| match part {
+        "FML" => Some(ForgeMarker::Fml),
+        "FML2" => Some(ForgeMarker::Fml2),
+        "FML3" => Some(ForgeMarker::Fml3),
+        _ => None,
+    });
+
+    (hostname, marker)
+}
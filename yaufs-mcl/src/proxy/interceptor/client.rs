@@ -14,19 +14,47 @@
  *    limitations under the License.
  */
 
+use crate::config::{SecureChat, CONFIG};
 use crate::proxy::adapter::ClientAdapter;
+use crate::proxy::command::ProxyCommand;
+use crate::proxy::connection::ConnectionCommand;
+use crate::proxy::forge;
 use crate::proxy::interceptor::PacketInterceptor;
+use crate::proxy::limbo;
+use crate::proxy::moderation::{self, Punishment, PunishmentKind, MODERATION};
+use crate::proxy::network::{self, NetworkEvent, NETWORK};
+use crate::proxy::permission::PERMISSIONS;
+use crate::proxy::routing;
 use crate::proxy::{ENCRYPTION_PRIVATE_KEY, ENCRYPTION_PUBLIC_KEY_BYTES};
 use kanal::AsyncSender;
 use rsa::Pkcs1v15Encrypt;
+use std::time::Duration;
 use yaufs_common::craftio_rs::CraftIo;
 use yaufs_common::net::packet::{
-    HandshakeNextState, LoginEncryptionRequestSpec, LoginStartSpec, LoginSuccessPropertiesSpec,
-    LoginSuccessSpec, Packet762, StatusPongSpec, StatusResponseSpec,
+    HandshakeNextState, LoginEncryptionRequestSpec, LoginSetCompressionSpec, LoginStartSpec,
+    LoginSuccessPropertiesSpec, LoginSuccessSpec, Packet762, PlayChatCommandSpec,
+    PlayClientChatMessageSpec, PlayPlayerInfoRemoveSpec, PlayResourcePackSpec, PlayServerDataSpec,
+    PlaySetTabListHeaderAndFooter, StatusPongSpec, StatusResponseSpec,
 };
+use yaufs_common::net::play::{self, PlayerInfoEntry};
 use yaufs_common::protocol::State;
-use yaufs_common::status::{StatusPlayersSpec, StatusSpec};
-use yaufs_common::types::{BaseComponent, Chat, CountedArray, TextComponent};
+use yaufs_common::status::{StatusPlayerSampleSpec, StatusPlayersSpec, StatusSpec};
+use yaufs_common::types::{
+    BaseComponent, Chat, CountedArray, RemainingBytes, TextComponent, VarInt,
+};
+use yaufs_common::uuid::UUID4;
+use yaufs_common::yaufs_proto::fluvio::{ChatModerated, PlayerChat};
+use yaufs_common::Deserialize;
+
+const SESSION_SERVER: &str = "https://sessionserver.mojang.com";
+const BRAND_CHANNEL: &str = "minecraft:brand";
+// the amount of players listed in the status response, as many as the vanilla server lists
+const STATUS_SAMPLE: usize = 12;
+// the stored session is only refreshed once the ping deviates by this much
+const PING_TOLERANCE: Duration = Duration::from_millis(50);
+
+/// The hash of the resource pack the client applied during its session.
+pub struct AppliedResourcePack(pub String);
 
 #[async_trait]
 impl PacketInterceptor for ClientAdapter {
@@ -44,19 +72,50 @@ impl PacketInterceptor for ClientAdapter {
                 self.send_packet(packet).await?;
             }
             Packet762::StatusRequest(_) => {
+                let peers = self.peers.lock().await;
+                let playing = peers
+                    .values()
+                    .filter(|connection| State::Play.eq(connection.state()));
+                let online = playing.clone().count();
+                // the hover of the player count lists the players with their measured ping
+                let sample = playing
+                    .filter_map(|connection| {
+                        let profile = connection.profile().as_ref()?;
+                        let name = match connection.ping() {
+                            Some(ping) => format!("{} ({}ms)", profile.username, ping.as_millis()),
+                            None => profile.username.clone(),
+                        };
+
+                        Some(StatusPlayerSampleSpec {
+                            name,
+                            id: profile.uuid,
+                        })
+                    })
+                    .take(STATUS_SAMPLE)
+                    .collect();
+                // every forced host has its own description and favicon
+                let route = routing::resolve(
+                    peers
+                        .get(&self.client_address)
+                        .and_then(|connection| connection.hostname().as_ref())
+                        .map(String::as_str)
+                        .unwrap_or_default(),
+                );
+                drop(peers);
+
                 let packet = Packet762::StatusResponse(StatusResponseSpec {
                     response: StatusSpec {
                         version: None,
                         players: StatusPlayersSpec {
                             max: 0,
-                            online: 1,
-                            sample: vec![],
+                            online: online as i32,
+                            sample,
                         },
                         description: Chat::Text(TextComponent {
-                            text: "Hey folks".to_owned(),
+                            text: routing::motd(route).to_owned(),
                             base: BaseComponent::default(),
                         }),
-                        favicon: None,
+                        favicon: routing::favicon(route),
                     },
                 });
 
@@ -68,13 +127,21 @@ impl PacketInterceptor for ClientAdapter {
                     HandshakeNextState::Login => State::Login,
                 };
 
-                self.reader.set_state(state);
-                self.writer.set_state(state);
+                self.set_state(state);
 
+                let (hostname, forge) =
+                    forge::split_server_address(handshake.server_address.as_str());
                 let mut peers = self.peers.lock().await;
                 let connection = peers.get_mut(&self.client_address).unwrap();
                 connection.set_state(state);
+                connection.set_protocol_version(handshake.version.0);
+                connection.set_hostname(Some(hostname.to_owned()));
+                if let Some(marker) = forge.as_ref() {
+                    debug!("Client {} uses forge ({:?})", self.client_address, marker);
+                }
+                connection.set_forge(forge);
 
+                // the handshake is forwarded untouched, which keeps the forge marker intact
                 match state {
                     State::Login => {
                         sender.send(packet).await?;
@@ -117,9 +184,15 @@ impl PacketInterceptor for ClientAdapter {
                 self.writer.enable_encryption(&secret, &secret)?;
 
                 // authorize the login request
-                let mut peers = self.peers.lock().await;
-                let connection = peers.get_mut(&self.client_address).unwrap();
-                let login_request = connection.login().as_ref().unwrap();
+                let login_request = self
+                    .peers
+                    .lock()
+                    .await
+                    .get(&self.client_address)
+                    .unwrap()
+                    .login()
+                    .clone()
+                    .unwrap();
 
                 // generate the hash
                 let server_hash = mojang_api::server_hash(
@@ -128,24 +201,33 @@ impl PacketInterceptor for ClientAdapter {
                     &ENCRYPTION_PUBLIC_KEY_BYTES,
                 );
                 // verify the session
-                let authentication_response = reqwest::get(
-                    format!(
-                        "https://sessionserver.mojang.com/session/minecraft/hasJoined?username={}&serverId={}",
-                        login_request.name.as_str(),
-                        server_hash.as_str())
-                    )
-                    .await?.json::<mojang_api::ServerAuthResponse>().await?;
-                sender
-                    .send(Packet762::LoginStart(LoginStartSpec {
-                        name: login_request.name.to_string(),
-                        has_uuid: login_request.has_uuid.clone(),
-                        uuid: login_request.uuid.clone(),
-                    }))
-                    .await?;
-                let uuid = login_request.uuid.clone();
-                drop(peers);
-
-                self.send_packet(Packet762::LoginSuccess(LoginSuccessSpec {
+                let authentication_response = reqwest::get(format!(
+                    "{}/session/minecraft/hasJoined?username={}&serverId={}",
+                    CONFIG.session_server.as_deref().unwrap_or(SESSION_SERVER),
+                    login_request.name.as_str(),
+                    server_hash.as_str()
+                ))
+                .await?
+                .json::<mojang_api::ServerAuthResponse>()
+                .await?;
+                // the login success is sent to the client once the backend finished the login,
+                // which allows the backend to send login plugin requests (e.g. forge) before
+                // the uuid of the login start is chosen by the client, only the one of the
+                // session server can be trusted
+                let uuid = UUID4::parse(authentication_response.id.to_string().as_str())
+                    .ok_or(anyhow::anyhow!("Session server returned an invalid uuid"))?;
+                if CONFIG.moderation.enabled {
+                    let ban = MODERATION
+                        .active(PunishmentKind::Ban, uuid.to_string().as_str())
+                        .await;
+                    if let Some(ban) = ban {
+                        self.disconnect(ban.message(PunishmentKind::Ban).as_str())
+                            .await?;
+                        return Ok(());
+                    }
+                }
+                let permissions = PERMISSIONS.resolve(uuid.to_string().as_str()).await;
+                let profile = LoginSuccessSpec {
                     uuid,
                     username: authentication_response.name.clone(),
                     properties: CountedArray::from(
@@ -160,10 +242,190 @@ impl PacketInterceptor for ClientAdapter {
                             })
                             .collect::<Vec<LoginSuccessPropertiesSpec>>(),
                     ),
-                }))
-                .await?;
-                self.reader.set_state(State::Play);
-                self.writer.set_state(State::Play);
+                };
+                let mut peers = self.peers.lock().await;
+                let connection = peers.get_mut(&self.client_address).unwrap();
+                connection.set_profile(Some(profile));
+                connection.set_permissions(permissions);
+                drop(peers);
+
+                // the backend is logged in after the profile is known, which replaces its
+                // login success
+                sender
+                    .send(Packet762::LoginStart(LoginStartSpec {
+                        name: login_request.name,
+                        has_uuid: login_request.has_uuid,
+                        uuid: login_request.uuid,
+                    }))
+                    .await?;
+
+                // the compression towards the client is negotiated by the proxy itself, the
+                // threshold of the backend only applies to the backend connection
+                if let Some(threshold) = CONFIG.compression.threshold() {
+                    self.send_packet(Packet762::LoginSetCompression(LoginSetCompressionSpec {
+                        threshold: VarInt(threshold),
+                    }))
+                    .await?;
+                    self.writer.set_compression_threshold(Some(threshold));
+                    self.reader.set_compression_threshold(Some(threshold));
+                }
+            }
+            Packet762::PlayClientKeepAlive(keep_alive) => {
+                if let Some(ping) = self.resolve_keep_alive(keep_alive.id) {
+                    self.peers
+                        .lock()
+                        .await
+                        .get_mut(&self.client_address)
+                        .unwrap()
+                        .set_ping(Some(ping));
+                    if ping_changed(self.stored_ping, ping) {
+                        self.stored_ping = Some(ping);
+                        self.update_session().await;
+                    }
+
+                    // the footer is only refreshed if the displayed value changed
+                    if CONFIG.tab_list_ping && self.shown_ping != Some(ping.as_millis()) {
+                        self.send_tab_list(Some(ping)).await?;
+                    }
+                }
+
+                sender.send(packet).await?;
+            }
+            Packet762::PlayPlayerSession(session) => {
+                self.session = Some(session.clone());
+                // without the session the backend treats all messages of the client as unsigned
+                if CONFIG.secure_chat != SecureChat::Strip {
+                    sender.send(packet).await?;
+                }
+            }
+            Packet762::PlayClientChatMessage(message) => {
+                let data = message.data.data.as_slice();
+                // blocked messages never reach the backend nor the network
+                if CONFIG.moderation.enabled {
+                    let message = match play::chat_message(data) {
+                        Ok(message) => message,
+                        Err(error) => {
+                            warn!(
+                                "Dropping invalid chat message of {}: {:?}",
+                                self.client_address, error
+                            );
+                            return Ok(());
+                        }
+                    };
+                    if !self.moderate(message).await? {
+                        return Ok(());
+                    }
+                }
+                let packet = match CONFIG.secure_chat {
+                    SecureChat::Passthrough => packet.clone(),
+                    SecureChat::Enforce => {
+                        if self.session.is_none() || !play::chat_signed(data).unwrap_or_default() {
+                            self.disconnect("Secure chat is required on this server")
+                                .await?;
+                            return Ok(());
+                        }
+                        packet.clone()
+                    }
+                    SecureChat::Strip => {
+                        let data = play::strip_chat_signature(data).map_err(|error| {
+                            anyhow::anyhow!("Invalid chat message: {:?}", error)
+                        })?;
+                        Packet762::PlayClientChatMessage(PlayClientChatMessageSpec {
+                            data: RemainingBytes { data },
+                        })
+                    }
+                };
+
+                if CONFIG.network.chat {
+                    self.relay_chat(data).await;
+                }
+                sender.send(packet).await?;
+            }
+            Packet762::PlayClientSettings(settings) => {
+                self.peers
+                    .lock()
+                    .await
+                    .get_mut(&self.client_address)
+                    .unwrap()
+                    .set_settings(Some(settings.clone()));
+                self.update_session().await;
+                sender.send(packet).await?;
+            }
+            Packet762::PlayClientPluginMessage(message) if BRAND_CHANNEL.eq(&message.channel) => {
+                match String::mc_deserialize(message.data.data.as_slice()) {
+                    Ok(brand) => {
+                        self.peers
+                            .lock()
+                            .await
+                            .get_mut(&self.client_address)
+                            .unwrap()
+                            .set_brand(Some(brand.value));
+                        self.update_session().await;
+                    }
+                    Err(error) => debug!("Invalid brand of {}: {:?}", self.client_address, error),
+                }
+                sender.send(packet).await?;
+            }
+            // the status of a resource pack sent by the proxy is not forwarded to the backend
+            Packet762::PlayResourcePackStatus(status) if self.pending_pack.is_some() => {
+                let pack = self.pending_pack.unwrap();
+                match status.result.0 {
+                    play::RESOURCE_PACK_ACCEPTED => {}
+                    play::RESOURCE_PACK_LOADED => {
+                        self.peers
+                            .lock()
+                            .await
+                            .get_mut(&self.client_address)
+                            .unwrap()
+                            .insert_metadata(AppliedResourcePack(pack.hash.clone()));
+                        self.pending_pack = None;
+                    }
+                    result => {
+                        self.pending_pack = None;
+                        if pack.required {
+                            debug!(
+                                "Client {} rejected resource pack {} ({})",
+                                self.client_address, pack.hash, result
+                            );
+                            self.disconnect("This server requires its resource pack")
+                                .await?;
+                        }
+                    }
+                }
+            }
+            Packet762::PlayChatCommand(command) => {
+                let data = command.data.data.as_slice();
+                let command = match play::chat_command(data) {
+                    Ok(command) => command,
+                    Err(error) => {
+                        warn!(
+                            "Dropping invalid chat command of {}: {:?}",
+                            self.client_address, error
+                        );
+                        return Ok(());
+                    }
+                };
+                // commands of the proxy never reach the backend
+                if self.proxy_command(command.as_str()).await? {
+                    return Ok(());
+                }
+                // messages to other players are blocked like the ones of the chat
+                if let Some(message) = moderation::command_message(command.as_str()) {
+                    if CONFIG.moderation.enabled && !self.moderate(message.to_owned()).await? {
+                        return Ok(());
+                    }
+                }
+                if CONFIG.secure_chat == SecureChat::Strip {
+                    let data = play::strip_command_signatures(data)
+                        .map_err(|error| anyhow::anyhow!("Invalid chat command: {:?}", error))?;
+                    sender
+                        .send(Packet762::PlayChatCommand(PlayChatCommandSpec {
+                            data: RemainingBytes { data },
+                        }))
+                        .await?;
+                } else {
+                    sender.send(packet).await?;
+                }
             }
             _ => {
                 sender.send(packet).await?;
@@ -175,12 +437,113 @@ impl PacketInterceptor for ClientAdapter {
 
     async fn on_send(&mut self, packet: Packet762) -> anyhow::Result<()> {
         match &packet {
-            Packet762::LoginSetCompression(request) => {
-                let threshold = Some(request.threshold.0);
+            // the client connection has its own threshold, which is set during the login
+            Packet762::LoginSetCompression(_) => {}
+            // the limbo a playing client falls back to performs a login as well
+            Packet762::LoginSuccess(_) if State::Play.eq(&self.state()) => {}
+            Packet762::LoginSuccess(_) => {
+                // replace the success of the (offline) backend with the authenticated profile
+                let mut peers = self.peers.lock().await;
+                let connection = peers.get_mut(&self.client_address).unwrap();
+                let profile = connection.profile().clone().ok_or(anyhow::anyhow!(
+                    "Backend finished login before authentication"
+                ))?;
+                connection.set_state(State::Play);
+                drop(peers);
+
+                self.send_packet(Packet762::LoginSuccess(profile)).await?;
+                self.set_state(State::Play);
+            }
+            Packet762::LoginPluginRequest(request) => {
+                if forge::LOGIN_WRAPPER_CHANNEL.eq(request.channel.as_str()) {
+                    debug!(
+                        "Forwarding forge login handshake to {}",
+                        self.client_address
+                    );
+                }
                 self.send_packet(packet).await?;
+            }
+            Packet762::PlayLogin(login) => {
+                limbo::remember(login).await;
+
+                if self.joined {
+                    // the client was moved from another backend, so everything it knows about
+                    // the previous one has to be removed
+                    self.tab_list = None;
+                    if !self.listed.is_empty() {
+                        let players = self.listed.drain().collect::<Vec<UUID4>>();
+                        self.send_packet(Packet762::PlayPlayerInfoRemove(
+                            PlayPlayerInfoRemoveSpec {
+                                players: CountedArray::from(players),
+                            },
+                        ))
+                        .await?;
+                    }
+                    let respawn = play::respawn_of(login);
+                    self.send_packet(packet).await?;
+                    self.send_packet(Packet762::PlayRespawn(respawn)).await?;
+                } else {
+                    self.send_packet(packet).await?;
+                    self.joined = true;
+                }
 
-                self.writer.set_compression_threshold(threshold);
-                self.reader.set_compression_threshold(threshold);
+                if CONFIG.network.enabled {
+                    self.join_network().await?;
+                }
+                self.send_resource_pack().await?;
+            }
+            // the pack of the backend replaces the one applied by the proxy
+            Packet762::PlayResourcePack(_) => {
+                self.peers
+                    .lock()
+                    .await
+                    .get_mut(&self.client_address)
+                    .unwrap()
+                    .remove_metadata::<AppliedResourcePack>();
+                self.send_packet(packet).await?;
+            }
+            Packet762::PlayPlayerInfoUpdate(update) => {
+                match play::player_info_added(update.data.data.as_slice()) {
+                    Ok(added) => self.listed.extend(added),
+                    Err(error) => debug!("Invalid player info update: {:?}", error),
+                }
+                self.send_packet(packet).await?;
+            }
+            Packet762::PlayPlayerInfoRemove(remove) => {
+                for uuid in remove.players.iter() {
+                    self.listed.remove(uuid);
+                }
+                self.send_packet(packet).await?;
+            }
+            // the client warns about unverifiable messages unless the server enforces secure chat
+            Packet762::PlayServerData(data) if CONFIG.secure_chat != SecureChat::Passthrough => {
+                let enforces = CONFIG.secure_chat == SecureChat::Enforce;
+                self.send_packet(Packet762::PlayServerData(PlayServerDataSpec {
+                    data: RemainingBytes {
+                        data: play::set_enforces_secure_chat(data.data.data.as_slice(), enforces),
+                    },
+                }))
+                .await?;
+            }
+            // the ping is appended to the footer of the backend instead of replacing it
+            Packet762::PlaySetTabListHeaderAndFooter(tab_list) if CONFIG.tab_list_ping => {
+                self.tab_list = Some((tab_list.header.clone(), tab_list.footer.clone()));
+                let ping = *self
+                    .peers
+                    .lock()
+                    .await
+                    .get(&self.client_address)
+                    .unwrap()
+                    .ping();
+                self.send_tab_list(ping).await?;
+            }
+            Packet762::PlayServerKeepAlive(keep_alive) => {
+                self.track_keep_alive(keep_alive.id);
+                self.send_packet(packet).await?;
+            }
+            Packet762::LoginDisconnect(_) | Packet762::PlayDisconnect(_) => {
+                self.send_packet(packet).await?;
+                self.close();
             }
             _ => {
                 self.send_packet(packet).await?;
@@ -190,3 +553,414 @@ impl PacketInterceptor for ClientAdapter {
         Ok(())
     }
 }
+
+impl ClientAdapter {
+    // send the header and footer of the backend with the ping of the client appended
+    async fn send_tab_list(&mut self, ping: Option<Duration>) -> anyhow::Result<()> {
+        let (header, footer) = self
+            .tab_list
+            .clone()
+            .unwrap_or_else(|| (Chat::from_text(""), Chat::from_text("")));
+        let footer = match ping {
+            Some(ping) => footer_with_ping(footer, ping),
+            None => footer,
+        };
+        self.shown_ping = ping.map(|ping| ping.as_millis());
+
+        self.send_packet(Packet762::PlaySetTabListHeaderAndFooter(
+            PlaySetTabListHeaderAndFooter { header, footer },
+        ))
+        .await
+    }
+
+    // refresh the session of the player in the session store of the network
+    async fn update_session(&self) {
+        if !CONFIG.network.enabled || !self.joined {
+            return;
+        }
+
+        let player = self
+            .peers
+            .lock()
+            .await
+            .get(&self.client_address)
+            .unwrap()
+            .player(NETWORK.proxy());
+        if let Some(player) = player {
+            NETWORK.update(player).await;
+        }
+    }
+
+    /// Send the resource pack of the current backend, unless the client applied it already.
+    async fn send_resource_pack(&mut self) -> anyhow::Result<()> {
+        let peers = self.peers.lock().await;
+        let connection = peers.get(&self.client_address).unwrap();
+        let route = routing::resolve(connection.hostname().as_deref().unwrap_or_default());
+        let pack = match routing::resource_pack(route, connection.backend().as_deref()) {
+            Some(pack) => pack,
+            None => return Ok(()),
+        };
+        let applied = connection
+            .metadata::<AppliedResourcePack>()
+            .map(|applied| pack.hash.eq(&applied.0))
+            .unwrap_or_default();
+        drop(peers);
+        if applied {
+            return Ok(());
+        }
+
+        self.pending_pack = Some(pack);
+        self.send_packet(Packet762::PlayResourcePack(PlayResourcePackSpec {
+            url: pack.url.clone(),
+            hash: pack.hash.clone(),
+            forced: pack.required,
+            prompt_message: pack.prompt.as_deref().map(Chat::from_text),
+        }))
+        .await
+    }
+
+    /// Announce the player to the network and add the players of the other backends to the
+    /// tab list.
+    async fn join_network(&mut self) -> anyhow::Result<()> {
+        let player = match self
+            .peers
+            .lock()
+            .await
+            .get(&self.client_address)
+            .unwrap()
+            .player(NETWORK.proxy())
+        {
+            Some(player) => player,
+            None => return Ok(()),
+        };
+        let backend = player.backend.clone();
+
+        NETWORK.join(player).await;
+        if self.network.is_none() {
+            self.network = Some(NETWORK.subscribe());
+        }
+
+        if CONFIG.network.tab_list {
+            let entries = NETWORK
+                .players_outside(backend.as_str())
+                .await
+                .iter()
+                .map(PlayerInfoEntry::from)
+                .collect::<Vec<PlayerInfoEntry>>();
+            if !entries.is_empty() {
+                let update = play::player_info_add(entries.as_slice()).map_err(|error| {
+                    anyhow::anyhow!("Error while building tab list: {:?}", error)
+                })?;
+                self.send_packet(Packet762::PlayPlayerInfoUpdate(update))
+                    .await?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Apply an event of the network to the client. Players and messages of the own backend are
+    /// already handled by the backend itself.
+    pub async fn on_network_event(&mut self, event: NetworkEvent) -> anyhow::Result<()> {
+        let backend = match self.backend().await {
+            Some(backend) => backend,
+            None => return Ok(()),
+        };
+
+        match event {
+            NetworkEvent::Joined(player)
+                if CONFIG.network.tab_list && !backend.eq(&player.backend) =>
+            {
+                let update =
+                    play::player_info_add(&[PlayerInfoEntry::from(&player)]).map_err(|error| {
+                        anyhow::anyhow!("Error while building tab list: {:?}", error)
+                    })?;
+                self.send_packet(Packet762::PlayPlayerInfoUpdate(update))
+                    .await?;
+            }
+            NetworkEvent::Left(player)
+                if CONFIG.network.tab_list && !backend.eq(&player.backend) =>
+            {
+                if let Some(uuid) = UUID4::parse(player.uuid.as_str()) {
+                    self.send_packet(Packet762::PlayPlayerInfoRemove(PlayPlayerInfoRemoveSpec {
+                        players: CountedArray::from(vec![uuid]),
+                    }))
+                    .await?;
+                }
+            }
+            NetworkEvent::Chat(chat)
+                if CONFIG.network.chat
+                    && !backend.eq(&chat.backend)
+                    && network::group_of(backend.as_str()).eq(&Some(&chat.group)) =>
+            {
+                // relayed messages can not be verified by the client, so they are sent unsigned
+                self.send_packet(Packet762::PlaySystemChatMessage(play::system_message(
+                    Chat::from_text(format!("<{}> {}", chat.name, chat.message).as_str()),
+                )))
+                .await?;
+            }
+            _ => {}
+        }
+
+        Ok(())
+    }
+
+    // relay a chat message of the client to the other backends of its group
+    async fn relay_chat(&self, data: &[u8]) {
+        let peers = self.peers.lock().await;
+        let connection = peers.get(&self.client_address).unwrap();
+        let (profile, backend) = match (connection.profile(), connection.backend()) {
+            (Some(profile), Some(backend)) => (profile.clone(), backend.clone()),
+            _ => return,
+        };
+        drop(peers);
+
+        let group = match network::group_of(backend.as_str()) {
+            Some(group) => group.clone(),
+            None => return,
+        };
+        let message = match play::chat_message(data) {
+            Ok(message) => message,
+            Err(error) => {
+                debug!(
+                    "Invalid chat message of {}: {:?}",
+                    self.client_address, error
+                );
+                return;
+            }
+        };
+
+        NETWORK
+            .chat(PlayerChat {
+                proxy: NETWORK.proxy().to_owned(),
+                uuid: profile.uuid.to_string(),
+                name: profile.username,
+                group,
+                backend,
+                message,
+            })
+            .await;
+    }
+
+    /// Check the chat message against the moderation and notify the player if it is blocked.
+    /// Returns whether the message may be sent.
+    async fn moderate(&mut self, message: String) -> anyhow::Result<bool> {
+        let peers = self.peers.lock().await;
+        let connection = peers.get(&self.client_address).unwrap();
+        let (profile, backend) = match connection.profile() {
+            Some(profile) => (profile.clone(), connection.backend().clone()),
+            None => return Ok(true),
+        };
+        drop(peers);
+
+        let uuid = profile.uuid.to_string();
+        let violation = match MODERATION
+            .check(
+                &self.peers,
+                &self.client_address,
+                uuid.as_str(),
+                message.as_str(),
+            )
+            .await
+        {
+            Some(violation) => violation,
+            None => return Ok(true),
+        };
+
+        debug!(
+            "Blocked chat message of {} ({})",
+            profile.username,
+            violation.as_str()
+        );
+        self.send_packet(limbo::message(violation.message()))
+            .await?;
+        MODERATION
+            .publish(ChatModerated {
+                proxy: NETWORK.proxy().to_owned(),
+                uuid,
+                name: profile.username,
+                backend,
+                message,
+                violation: violation.as_str().to_owned(),
+            })
+            .await;
+
+        Ok(false)
+    }
+
+    // execute a command of the proxy, returns whether the command belonged to the proxy
+    async fn proxy_command(&mut self, command: &str) -> anyhow::Result<bool> {
+        let command = match ProxyCommand::parse(command) {
+            Some(Ok(command)) => command,
+            Some(Err(usage)) => {
+                self.send_packet(limbo::message(usage)).await?;
+                return Ok(true);
+            }
+            None => return Ok(false),
+        };
+
+        let peers = self.peers.lock().await;
+        if !peers
+            .get(&self.client_address)
+            .unwrap()
+            .has_permission(command.permission())
+        {
+            drop(peers);
+            self.send_packet(limbo::message("You are not allowed to use this command"))
+                .await?;
+            return Ok(true);
+        }
+
+        drop(peers);
+
+        let reply = match command {
+            ProxyCommand::Punish { .. } | ProxyCommand::Pardon { .. }
+                if !CONFIG.moderation.enabled =>
+            {
+                "The moderation is not enabled on this proxy".to_owned()
+            }
+            ProxyCommand::Punish {
+                kind,
+                player,
+                duration,
+                reason,
+            } => match self.find_player(player.as_str()).await {
+                Some((uuid, name)) => {
+                    let punishment = Punishment::new(duration, reason);
+                    let message = punishment.message(kind);
+                    MODERATION.punish(kind, uuid.as_str(), punishment).await;
+                    // players of other proxies notice the ban once they reconnect
+                    if let Some(connection) = self.peers.lock().await.values().find(|connection| {
+                        connection
+                            .profile()
+                            .as_ref()
+                            .map_or(false, |profile| uuid.eq(&profile.uuid.to_string()))
+                    }) {
+                        let command = match kind {
+                            PunishmentKind::Mute => {
+                                ConnectionCommand::Send(limbo::message(message.as_str()))
+                            }
+                            PunishmentKind::Ban => ConnectionCommand::Disconnect(message),
+                        };
+                        connection.command(command).await;
+                    }
+
+                    match duration {
+                        Some(duration) => format!(
+                            "{name} is {} for {} minutes",
+                            kind.past_tense(),
+                            (duration.as_secs() + 59) / 60
+                        ),
+                        None => format!("{name} is {} permanently", kind.past_tense()),
+                    }
+                }
+                None => "The player is not known to the network".to_owned(),
+            },
+            ProxyCommand::Pardon { kind, player } => {
+                match self.find_player(player.as_str()).await {
+                    Some((uuid, name)) if MODERATION.pardon(kind, uuid.as_str()).await => {
+                        format!("{name} is no longer {}", kind.past_tense())
+                    }
+                    Some((_, name)) => format!("{name} is not {}", kind.past_tense()),
+                    None => "The player is not known to the network".to_owned(),
+                }
+            }
+        };
+
+        self.send_packet(limbo::message(reply.as_str())).await?;
+        Ok(true)
+    }
+
+    // resolve the uuid and the name of a player of the network, players who are offline can only
+    // be found by their uuid
+    async fn find_player(&self, player: &str) -> Option<(String, String)> {
+        if let Some(uuid) = UUID4::parse(player) {
+            return Some((uuid.to_string(), player.to_owned()));
+        }
+        let local = self
+            .peers
+            .lock()
+            .await
+            .values()
+            .filter_map(|connection| connection.profile().as_ref())
+            .find(|profile| profile.username.eq_ignore_ascii_case(player))
+            .map(|profile| (profile.uuid.to_string(), profile.username.clone()));
+        match local {
+            Some(local) => Some(local),
+            None => NETWORK
+                .find(player)
+                .await
+                .map(|joined| (joined.uuid, joined.name)),
+        }
+    }
+
+    async fn backend(&self) -> Option<String> {
+        self.peers
+            .lock()
+            .await
+            .get(&self.client_address)
+            .and_then(|connection| connection.backend().clone())
+    }
+}
+
+fn ping_changed(stored: Option<Duration>, ping: Duration) -> bool {
+    stored.map_or(true, |stored| {
+        let deviation = match stored > ping {
+            true => stored - ping,
+            false => ping - stored,
+        };
+        deviation >= PING_TOLERANCE
+    })
+}
+
+// append the ping as a new line to the footer, an empty footer is replaced by the ping
+fn footer_with_ping(footer: Chat, ping: Duration) -> Chat {
+    let line = Chat::from_text(format!("Ping: {}ms", ping.as_millis()).as_str());
+    match &footer {
+        Chat::Text(text) if text.text.is_empty() && text.base.extra.is_empty() => line,
+        _ => Chat::Text(TextComponent {
+            text: String::new(),
+            base: BaseComponent {
+                extra: vec![
+                    Box::new(footer),
+                    Box::new(Chat::from_text("\n")),
+                    Box::new(line),
+                ],
+                ..BaseComponent::default()
+            },
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ping_changed() {
+        let ping = Duration::from_millis(80);
+
+        assert!(ping_changed(None, ping));
+        assert!(!ping_changed(Some(Duration::from_millis(60)), ping));
+        assert!(ping_changed(Some(Duration::from_millis(20)), ping));
+        assert!(ping_changed(Some(Duration::from_millis(140)), ping));
+    }
+
+    #[test]
+    fn test_footer_with_ping() {
+        let ping = Duration::from_millis(42);
+
+        let footer = footer_with_ping(Chat::from_text(""), ping);
+        assert_eq!(footer, Chat::from_text("Ping: 42ms"));
+
+        let footer = footer_with_ping(Chat::from_text("play.example.com"), ping);
+        match footer {
+            Chat::Text(text) => {
+                assert_eq!(text.base.extra.len(), 3);
+                assert_eq!(*text.base.extra[0], Chat::from_text("play.example.com"));
+                assert_eq!(*text.base.extra[2], Chat::from_text("Ping: 42ms"));
+            }
+            _ => panic!("footer is not a text component"),
+        }
+    }
+}
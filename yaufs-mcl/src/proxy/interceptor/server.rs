@@ -29,15 +29,17 @@ impl PacketInterceptor for ServerAdapter {
         sender: AsyncSender<Packet762>,
     ) -> anyhow::Result<()> {
         match &packet {
+            // the backend threshold only applies to the backend connection and is never
+            // forwarded, a threshold of -1 (e.g. on the internal network) disables it
             Packet762::LoginSetCompression(compression) => {
-                self.reader
-                    .set_compression_threshold(Some(compression.threshold.0));
-                self.writer
-                    .set_compression_threshold(Some(compression.threshold.0));
+                let threshold = Some(compression.threshold.0).filter(|threshold| *threshold >= 0);
+                self.reader.set_compression_threshold(threshold);
+                self.writer.set_compression_threshold(threshold);
             }
             Packet762::LoginSuccess(_) => {
-                self.reader.set_state(State::Play);
-                self.writer.set_state(State::Play);
+                self.set_state(State::Play);
+                // the client adapter answers with the authenticated profile
+                sender.send(packet).await?;
             }
             _ => {
                 sender.send(packet).await?;
@@ -50,8 +52,7 @@ impl PacketInterceptor for ServerAdapter {
     async fn on_send(&mut self, packet: Packet762) -> anyhow::Result<()> {
         match &packet {
             Packet762::LoginStart(_) => {
-                self.reader.set_state(State::Login);
-                self.writer.set_state(State::Login);
+                self.set_state(State::Login);
             }
             _ => {}
         }
@@ -0,0 +1,306 @@
+/*
+ *    Copyright  2023.  Fritz Ochsmann
+ *
+ *    Licensed under the Apache License, Version 2.0 (the "License");
+ *    you may not use this file except in compliance with the License.
+ *    You may obtain a copy of the License at
+ *
+ *        http://www.apache.org/licenses/LICENSE-2.0
+ *
+ *    Unless required by applicable law or agreed to in writing, software
+ *    distributed under the License is distributed on an "AS IS" BASIS,
+ *    WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ *    See the License for the specific language governing permissions and
+ *    limitations under the License.
+ */
+
+use crate::config::CONFIG;
+use kanal::{AsyncReceiver, AsyncSender};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::sync::RwLock;
+use yaufs_common::net::packet::{
+    HandshakeNextState, HandshakeSpec, LoginDisconnectSpec, LoginStartSpec, LoginSuccessSpec,
+    Packet762, PlayLoginSpec, PlayServerKeepAliveSpec, PlaySetDefaultSpawnLocationSpec,
+    PlaySetSubtitleTextSpec, PlaySetTitleAnimationTimesSpec, PlaySetTitleTextSpec,
+    PlaySynchronizePlayerPositionSpec,
+};
+use yaufs_common::net::play;
+use yaufs_common::types::{Chat, CountedArray, IntPosition, NamedNbtTag, VarInt};
+use yaufs_common::Deserialize;
+
+const OVERWORLD: &str = "minecraft:overworld";
+// spectators can not interact with the (empty) world
+const SPECTATOR: u8 = 3;
+// the chunks around the spawn sent to the client
+const CHUNK_RADIUS: i32 = 1;
+// the height of the overworld, used if the dimension type is not part of the registry
+const DEFAULT_HEIGHT: i32 = 384;
+
+lazy_static::lazy_static! {
+    // the limbo needs a registry codec the client accepts, which is either read from the
+    // configured file or taken from the join game of the first backend
+    static ref WORLD: RwLock<Option<PlayLoginSpec>> = RwLock::new(load_registry());
+}
+
+fn load_registry() -> Option<PlayLoginSpec> {
+    let path = CONFIG.limbo.registry.as_ref()?;
+    let raw = std::fs::read(path.as_path())
+        .unwrap_or_else(|error| panic!("Error while reading {:?}: {error}", path));
+    let registry_codec = NamedNbtTag::mc_deserialize(raw.as_slice())
+        .unwrap_or_else(|error| panic!("Invalid registry codec {:?}: {:?}", path, error))
+        .value;
+
+    Some(PlayLoginSpec {
+        entity_id: 0,
+        hardcore: false,
+        game_mode: SPECTATOR,
+        previous_game_mode: -1,
+        dimension_names: CountedArray::from(vec![OVERWORLD.to_owned()]),
+        registry_codec,
+        dimension_type: OVERWORLD.to_owned(),
+        dimension_name: OVERWORLD.to_owned(),
+        hashed_seed: 0,
+        max_players: VarInt(0),
+        view_distance: VarInt(2),
+        simulation_distance: VarInt(2),
+        reduced_debug_info: true,
+        enable_respawn_screen: false,
+        is_debug: false,
+        is_flat: true,
+        death_location: None,
+    })
+}
+
+/// Remember the world of a backend, if the limbo does not know any world yet.
+pub async fn remember(login: &PlayLoginSpec) {
+    if WORLD.read().await.is_some() {
+        return;
+    }
+
+    let mut world = WORLD.write().await;
+    if world.is_none() {
+        *world = Some(PlayLoginSpec {
+            game_mode: SPECTATOR,
+            previous_game_mode: -1,
+            reduced_debug_info: true,
+            enable_respawn_screen: false,
+            death_location: None,
+            ..login.clone()
+        });
+    }
+}
+
+/// Build a chat message sent by the proxy.
+pub fn message(text: &str) -> Packet762 {
+    Packet762::PlaySystemChatMessage(play::system_message(Chat::from_text(text)))
+}
+
+/// Build the packets showing a title with the default timings (in ticks).
+pub fn title(title: &str, subtitle: Option<&str>) -> Vec<Packet762> {
+    let mut packets = vec![Packet762::PlaySetTitleAnimationTimes(
+        PlaySetTitleAnimationTimesSpec {
+            fade_in: 10,
+            stay: 70,
+            fade_out: 20,
+        },
+    )];
+    if let Some(subtitle) = subtitle {
+        packets.push(Packet762::PlaySetSubtitleText(PlaySetSubtitleTextSpec {
+            text: Chat::from_text(subtitle),
+        }));
+    }
+    packets.push(Packet762::PlaySetTitleText(PlaySetTitleTextSpec {
+        text: Chat::from_text(title),
+    }));
+
+    packets
+}
+
+/// A backend without any server, which holds the player in an empty world. It is connected
+/// to the client adapter like a server adapter.
+#[derive(Default)]
+pub struct Limbo {
+    joined: bool,
+}
+
+impl Limbo {
+    /// Start a limbo for a client which is already playing. The returned channels replace the
+    /// ones of the previous backend.
+    pub async fn spawn(
+        login: LoginStartSpec,
+    ) -> anyhow::Result<(AsyncReceiver<Packet762>, AsyncSender<Packet762>)> {
+        let (client_sender, client_receiver) = kanal::unbounded_async::<Packet762>();
+        let (server_sender, server_receiver) = kanal::unbounded_async::<Packet762>();
+        tokio::spawn(async move { Limbo::default().run(server_receiver, client_sender).await });
+
+        // the limbo joins the player like a backend would do
+        server_sender
+            .send(Packet762::Handshake(HandshakeSpec {
+                version: VarInt(762),
+                server_address: String::new(),
+                server_port: 25565,
+                next_state: HandshakeNextState::Login,
+            }))
+            .await?;
+        server_sender.send(Packet762::LoginStart(login)).await?;
+
+        Ok((client_receiver, server_sender))
+    }
+
+    pub async fn run(
+        mut self,
+        receiver: AsyncReceiver<Packet762>,
+        sender: AsyncSender<Packet762>,
+    ) -> anyhow::Result<()> {
+        let mut keep_alive = tokio::time::interval(Duration::from_secs(10));
+
+        loop {
+            tokio::select! {
+                message = receiver.recv() => {
+                    match message {
+                        Ok(Packet762::LoginStart(login)) => {
+                            if !self.login(login, &sender).await? {
+                                break;
+                            }
+                        }
+                        // the player can not do anything in the limbo
+                        Ok(_) => {}
+                        Err(_) => break,
+                    }
+                },
+                _ = keep_alive.tick(), if self.joined => {
+                    let id = SystemTime::now().duration_since(UNIX_EPOCH)?.as_millis() as i64;
+                    sender
+                        .send(Packet762::PlayServerKeepAlive(PlayServerKeepAliveSpec { id }))
+                        .await?;
+                }
+            }
+        }
+        sender.close();
+
+        Ok(())
+    }
+
+    // returns false if the player could not join the limbo
+    async fn login(
+        &mut self,
+        login: LoginStartSpec,
+        sender: &AsyncSender<Packet762>,
+    ) -> anyhow::Result<bool> {
+        let world = match WORLD.read().await.clone() {
+            Some(world) => world,
+            None => {
+                warn!("The limbo does not know any world yet");
+                sender
+                    .send(Packet762::LoginDisconnect(LoginDisconnectSpec {
+                        message: Chat::from_text("The server is full, please try again later"),
+                    }))
+                    .await?;
+                return Ok(false);
+            }
+        };
+
+        // the client adapter replaces the profile with the authenticated one
+        sender
+            .send(Packet762::LoginSuccess(LoginSuccessSpec {
+                uuid: login.uuid,
+                username: login.name,
+                properties: CountedArray::from(vec![]),
+            }))
+            .await?;
+        let height = play::dimension_height(&world.registry_codec, world.dimension_type.as_str())
+            .unwrap_or(DEFAULT_HEIGHT);
+        sender.send(Packet762::PlayLogin(world)).await?;
+
+        // the void around the spawn
+        for x in -CHUNK_RADIUS..=CHUNK_RADIUS {
+            for z in -CHUNK_RADIUS..=CHUNK_RADIUS {
+                let chunk = play::empty_chunk(x, z, (height / 16) as usize)
+                    .map_err(|error| anyhow::anyhow!("Error while building chunk: {:?}", error))?;
+                sender
+                    .send(Packet762::PlayChunkDataAndUpdateLight(chunk))
+                    .await?;
+            }
+        }
+        sender
+            .send(Packet762::PlaySetDefaultSpawnLocation(
+                PlaySetDefaultSpawnLocationSpec {
+                    location: IntPosition { x: 0, y: 64, z: 0 },
+                    angle: 0.0,
+                },
+            ))
+            .await?;
+        sender
+            .send(Packet762::PlaySynchronizePlayerPosition(
+                PlaySynchronizePlayerPositionSpec {
+                    x: 0.5,
+                    y: 64.0,
+                    z: 0.5,
+                    yaw: 0.0,
+                    pitch: 0.0,
+                    flags: 0,
+                    teleport_id: VarInt(0),
+                },
+            ))
+            .await?;
+        self.joined = true;
+
+        let limbo = &CONFIG.limbo;
+        if let Some(text) = limbo.message.as_ref() {
+            sender.send(message(text.as_str())).await?;
+        }
+        if let Some(text) = limbo.title.as_ref() {
+            for packet in title(text.as_str(), limbo.subtitle.as_deref()) {
+                sender.send(packet).await?;
+            }
+        }
+
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use yaufs_common::uuid::UUID4;
+
+    #[test]
+    fn test_title() {
+        let packets = title("Queue", Some("Please wait"));
+        assert_eq!(packets.len(), 3);
+        assert!(matches!(
+            packets[0],
+            Packet762::PlaySetTitleAnimationTimes(_)
+        ));
+        assert!(matches!(packets[1], Packet762::PlaySetSubtitleText(_)));
+        assert!(matches!(packets[2], Packet762::PlaySetTitleText(_)));
+
+        assert_eq!(title("Queue", None).len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_login_without_world() {
+        // without a configured registry the limbo knows no world until a backend was joined
+        let (client_sender, client_receiver) = kanal::unbounded_async::<Packet762>();
+        let (server_sender, server_receiver) = kanal::unbounded_async::<Packet762>();
+        let limbo = tokio::spawn(Limbo::default().run(server_receiver, client_sender));
+
+        server_sender
+            .send(Packet762::LoginStart(LoginStartSpec {
+                name: "steve".to_owned(),
+                has_uuid: false,
+                uuid: UUID4::random(),
+            }))
+            .await
+            .unwrap();
+        match client_receiver.recv().await.unwrap() {
+            Packet762::LoginDisconnect(disconnect) => assert_eq!(
+                disconnect.message,
+                Chat::from_text("The server is full, please try again later")
+            ),
+            _ => panic!("the player was not disconnected"),
+        }
+        // the limbo stops once the player is refused
+        limbo.await.unwrap().unwrap();
+    }
+}
@@ -14,8 +14,15 @@
  *    limitations under the License.
  */
 
+use crate::config::CONFIG;
 use crate::proxy::adapter::Adapter;
-use crate::proxy::connection::ProxyConnection;
+use crate::proxy::connection::{ConnectionCommand, ProxyConnection};
+use crate::proxy::interceptor::PacketInterceptor;
+use crate::proxy::limbo::Limbo;
+use crate::proxy::moderation::MODERATION;
+use crate::proxy::network::NETWORK;
+use crate::proxy::permission::PERMISSIONS;
+use crate::proxy::queue::QUEUE;
 use crate::ADDRESS;
 use rsa::pkcs8::EncodePublicKey;
 use rsa::rand_core::OsRng;
@@ -25,14 +32,24 @@ use std::net::SocketAddr;
 use std::sync::Arc;
 use tokio::net::{TcpListener, TcpStream};
 use tokio::sync::Mutex;
-use yaufs_common::craftio_rs::{CraftConnection, CraftTokioConnection};
+use yaufs_common::craftio_rs::CraftConnection;
 use yaufs_common::mcproto_rs::protocol::PacketDirection;
-use yaufs_common::net::packet::Packet762;
+use yaufs_common::net::packet::{HandshakeNextState, Packet762};
 use yaufs_common::protocol::State;
+use yaufs_common::yaufs_proto::fluvio::PlayerLeft;
 
 mod adapter;
+mod capture;
+mod command;
 mod connection;
+mod forge;
 mod interceptor;
+mod limbo;
+mod moderation;
+mod network;
+mod permission;
+mod queue;
+mod routing;
 
 // TODO: may consider to save the information in skytable in order to be able to run multiple instances
 pub type PeerMap = Arc<Mutex<HashMap<SocketAddr, ProxyConnection>>>;
@@ -70,10 +87,37 @@ impl ProxySocket {
     }
 
     pub async fn start(self) {
-        let socket = TcpListener::bind(ADDRESS)
+        // the network, the permissions and the punishments share the connection pool
+        if CONFIG.network.enabled || CONFIG.permissions.store || CONFIG.moderation.enabled {
+            let skytable = yaufs_common::database::skytable::connect().await;
+            if CONFIG.network.enabled {
+                NETWORK
+                    .init(skytable.clone())
+                    .await
+                    .expect("Error while connecting to the network");
+            }
+            if CONFIG.permissions.store {
+                PERMISSIONS
+                    .init(skytable.clone())
+                    .await
+                    .expect("Error while creating the permission tables");
+            }
+            if CONFIG.moderation.enabled {
+                MODERATION
+                    .init(skytable)
+                    .await
+                    .expect("Error while initializing the chat moderation");
+            }
+        }
+        if CONFIG.queue.enabled {
+            QUEUE.start(self.peers.clone());
+        }
+
+        let listen = CONFIG.address.as_deref().unwrap_or(ADDRESS);
+        let socket = TcpListener::bind(listen)
             .await
             .expect("Error while binding to address");
-        info!("Listening for incoming connections on {}", ADDRESS);
+        info!("Listening for incoming connections on {}", listen);
 
         while let Ok((stream, address)) = socket.accept().await {
             let context = self.clone();
@@ -85,39 +129,139 @@ impl ProxySocket {
 
     async fn handle_connection(self, stream: TcpStream, address: SocketAddr) -> anyhow::Result<()> {
         debug!("Incoming connection from {}", address);
+        self.peers
+            .lock()
+            .await
+            .insert(address.clone(), ProxyConnection::default());
+
+        // the peer has to be removed even if the connection failed
+        let result = self.proxy_connection(stream, address).await;
+
+        debug!("Client disconnected from {}", address);
+        // remove the disconnected client from the peer map
+        let connection = self.peers.lock().await.remove(&address);
+        // only players which reached a backend were announced to the network
+        let connection = connection
+            .filter(|connection| CONFIG.network.enabled && State::Play.eq(connection.state()));
+        if let Some(connection) = connection {
+            if let (Some(profile), Some(backend)) = (connection.profile(), connection.backend()) {
+                NETWORK
+                    .leave(PlayerLeft {
+                        proxy: NETWORK.proxy().to_owned(),
+                        uuid: profile.uuid.to_string(),
+                        backend: backend.clone(),
+                    })
+                    .await;
+            }
+        }
+
+        result
+    }
+
+    async fn proxy_connection(&self, stream: TcpStream, address: SocketAddr) -> anyhow::Result<()> {
         let (read, write) = stream.into_split();
         let craft_stream = CraftConnection::from_async_with_state(
             (read, write),
             PacketDirection::ServerBound,
             State::Handshaking,
         );
-        self.peers
-            .lock()
-            .await
-            .insert(address.clone(), ProxyConnection::default());
 
         let (client_write_sender, client_write_receiver) = kanal::unbounded_async::<Packet762>();
         let (server_write_sender, server_write_receiver) = kanal::unbounded_async::<Packet762>();
 
-        let client_adapter =
+        let mut client_adapter =
             Adapter::try_from((craft_stream, self.peers.clone(), address.clone())).unwrap();
-        let server_address = SocketAddr::new("127.0.0.1".parse()?, 25566);
-        let server_listener = CraftTokioConnection::connect_server_tokio(server_address).await?;
-        let server_adapter =
-            Adapter::try_from((server_listener, self.peers.clone(), address.clone())).unwrap();
+        if let Some(directory) = CONFIG.capture.as_ref() {
+            client_adapter.enable_capture(directory.as_path()).await?;
+        }
+
+        // the backend is chosen by the hostname the client connected with
+        let handshake = client_adapter.read_handshake().await?;
+        let (hostname, _) = forge::split_server_address(handshake.server_address.as_str());
+        let route = routing::resolve(hostname);
+        let hostname = hostname.to_owned();
+        let next_state = handshake.next_state.clone();
+        client_adapter
+            .on_receive(Packet762::Handshake(handshake), server_write_sender.clone())
+            .await?;
+
+        // status requests are answered by the proxy itself
+        if let HandshakeNextState::Status = next_state {
+            let client_connector =
+                connector!(client_adapter, client_write_receiver, server_write_sender);
+            if let Ok(Err(error)) = client_connector.await {
+                debug!("Client connector of {} failed: {:?}", address, error);
+            }
+            drop(client_write_sender);
+            drop(server_write_receiver);
+
+            return Ok(());
+        }
+
+        // the client adapter executes the commands of the queue (e.g. the transfer)
+        let (command_sender, command_receiver) = kanal::unbounded_async::<ConnectionCommand>();
+        client_adapter.commands = Some(command_receiver);
+        self.peers
+            .lock()
+            .await
+            .get_mut(&address)
+            .unwrap()
+            .set_commands(Some(command_sender));
+
+        // with the queue enabled, players are only sent to backends with free slots
+        let backend = if CONFIG.queue.enabled {
+            match QUEUE.admit(route.group.as_str(), &self.peers).await {
+                Some(backend) => Some(routing::connect_to(backend.as_str()).await),
+                None => None,
+            }
+        } else {
+            Some(routing::connect(route).await)
+        };
+
+        let server_connector = match backend {
+            Some(Ok((server_listener, backend))) => {
+                debug!("Routing {} ({}) to {}", address, hostname, backend);
+                self.peers
+                    .lock()
+                    .await
+                    .get_mut(&address)
+                    .unwrap()
+                    .set_backend(Some(backend));
+
+                let mut server_adapter =
+                    Adapter::try_from((server_listener, self.peers.clone(), address.clone()))
+                        .unwrap();
+                if let Some(directory) = CONFIG.capture.as_ref() {
+                    server_adapter.enable_capture(directory.as_path()).await?;
+                }
+
+                connector!(server_adapter, server_write_receiver, client_write_sender)
+            }
+            Some(Err(error)) => {
+                warn!("No backend available for {}: {:?}", address, error);
+                client_adapter
+                    .disconnect("There is no server available at the moment")
+                    .await?;
+                return Ok(());
+            }
+            None => {
+                // all backends are full, the player waits in the limbo
+                QUEUE.enqueue(route.group.as_str(), address).await;
+                connector!(Limbo::default(), server_write_receiver, client_write_sender)
+            }
+        };
 
         // start the process
-        let server_connector =
-            connector!(server_adapter, server_write_receiver, client_write_sender);
         let client_connector =
             connector!(client_adapter, client_write_receiver, server_write_sender);
 
-        client_connector.await.unwrap().unwrap();
-        server_connector.await.unwrap().unwrap();
-
-        debug!("Client disconnected from {}", address);
-        // remove the disconnected client from the peer map
-        self.peers.lock().await.remove(&address);
+        // a failing connector must not prevent the cleanup of the peer
+        if let Ok(Err(error)) = client_connector.await {
+            debug!("Client connector of {} failed: {:?}", address, error);
+        }
+        if let Ok(Err(error)) = server_connector.await {
+            debug!("Server connector of {} failed: {:?}", address, error);
+        }
 
         Ok(())
     }
@@ -0,0 +1,545 @@
+/*
+ *    Copyright  2023.  Fritz Ochsmann
+ *
+ *    Licensed under the Apache License, Version 2.0 (the "License");
+ *    you may not use this file except in compliance with the License.
+ *    You may obtain a copy of the License at
+ *
+ *        http://www.apache.org/licenses/LICENSE-2.0
+ *
+ *    Unless required by applicable law or agreed to in writing, software
+ *    distributed under the License is distributed on an "AS IS" BASIS,
+ *    WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ *    See the License for the specific language governing permissions and
+ *    limitations under the License.
+ */
+
+use crate::config::{ModerationConfig, CONFIG};
+use crate::proxy::PeerMap;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::net::SocketAddr;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::sync::{OnceCell, RwLock};
+use yaufs_common::fluvio::TopicProducer;
+use yaufs_common::skytable::actions::AsyncActions;
+use yaufs_common::skytable::ddl::{AsyncDdl, Keymap, KeymapType};
+use yaufs_common::skytable::pool::AsyncPool;
+use yaufs_common::yaufs_proto::fluvio::{ChatModerated, YaufsEvent};
+
+const PUNISHMENTS: &str = "default:punishments";
+
+lazy_static::lazy_static! {
+    pub static ref MODERATION: Moderation = Moderation::new(&CONFIG.moderation);
+}
+
+/// The kinds of punishments, which are stored in the same table.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PunishmentKind {
+    Mute,
+    Ban,
+}
+
+impl PunishmentKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Mute => "mute",
+            Self::Ban => "ban",
+        }
+    }
+
+    pub fn past_tense(&self) -> &'static str {
+        match self {
+            Self::Mute => "muted",
+            Self::Ban => "banned",
+        }
+    }
+
+    // punishments are stored by their kind and the uuid of the player (e.g. `ban:<uuid>`)
+    fn key(&self, uuid: &str) -> String {
+        format!("{}:{}", self.as_str(), uuid)
+    }
+}
+
+/// A mute or a ban of a player, stored as json.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Punishment {
+    /// unix timestamp (in seconds) the punishment expires at, permanent if not set
+    pub until: Option<u64>,
+    pub reason: String,
+}
+
+impl Punishment {
+    pub fn new(duration: Option<Duration>, reason: String) -> Self {
+        Self {
+            until: duration.map(|duration| unix_time() + duration.as_secs()),
+            reason,
+        }
+    }
+
+    fn active(&self) -> bool {
+        self.until.map_or(true, |until| until > unix_time())
+    }
+
+    /// The message shown to the punished player.
+    pub fn message(&self, kind: PunishmentKind) -> String {
+        let mut message = format!("You are {}", kind.past_tense());
+        if let Some(until) = self.until {
+            let minutes = (until.saturating_sub(unix_time()) + 59) / 60;
+            message.push_str(format!(" for {minutes} more minutes").as_str());
+        }
+        if !self.reason.is_empty() {
+            message.push_str(format!(": {}", self.reason).as_str());
+        }
+
+        message
+    }
+}
+
+/// The reason a chat message was blocked.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Violation {
+    Muted,
+    Spam,
+    Caps,
+    Filter,
+}
+
+impl Violation {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Muted => "muted",
+            Self::Spam => "spam",
+            Self::Caps => "caps",
+            Self::Filter => "filter",
+        }
+    }
+
+    /// The message shown to the player whose chat message was blocked.
+    pub fn message(&self) -> &'static str {
+        match self {
+            Self::Muted => "You are muted",
+            Self::Spam => "You are sending messages too fast",
+            Self::Caps => "Please do not use that many capital letters",
+            Self::Filter => "Your message contains blocked words",
+        }
+    }
+}
+
+/// The times of the last chat messages of the client, kept in the connection metadata.
+#[derive(Default)]
+struct ChatHistory(VecDeque<Instant>);
+
+/// Filters the chat messages of the players. Mutes and bans are stored in skytable if the proxy
+/// is connected to it, otherwise they only last for the lifetime of the proxy.
+pub struct Moderation {
+    filters: Vec<Regex>,
+    punishments: RwLock<HashMap<String, Punishment>>,
+    skytable: OnceCell<AsyncPool>,
+    producer: OnceCell<TopicProducer>,
+}
+
+impl Moderation {
+    fn new(config: &ModerationConfig) -> Self {
+        // an invalid filter would let the messages pass silently, so it has to panic as well
+        let filters =
+            compile_filters(config).unwrap_or_else(|error| panic!("Invalid chat filter: {error}"));
+
+        Self {
+            filters,
+            punishments: RwLock::new(HashMap::new()),
+            skytable: OnceCell::new(),
+            producer: OnceCell::new(),
+        }
+    }
+
+    /// Create the punishment table and connect to the event stream. This has to be called once before
+    /// the proxy accepts connections.
+    pub async fn init(&self, skytable: AsyncPool) -> anyhow::Result<()> {
+        let mut connection = skytable.get().await?;
+        let keymap = Keymap::new(PUNISHMENTS)
+            .set_ktype(KeymapType::Str)
+            .set_vtype(KeymapType::Str);
+        // the table may already exist if another replica created it
+        if connection.create_table(keymap).await.is_err() {
+            debug!("Table {} already exists", PUNISHMENTS);
+        }
+        drop(connection);
+
+        let _ = self.skytable.set(skytable);
+        let _ = self
+            .producer
+            .set(yaufs_common::fluvio_util::producer().await?);
+        Ok(())
+    }
+
+    /// Check the chat message of the player. Returns the violation if the message has to be
+    /// blocked.
+    pub async fn check(
+        &self,
+        peers: &PeerMap,
+        address: &SocketAddr,
+        uuid: &str,
+        message: &str,
+    ) -> Option<Violation> {
+        if self.active(PunishmentKind::Mute, uuid).await.is_some() {
+            return Some(Violation::Muted);
+        }
+
+        let config = &CONFIG.moderation;
+        let spamming = {
+            let mut peers = peers.lock().await;
+            let connection = peers.get_mut(address)?;
+            if connection.metadata::<ChatHistory>().is_none() {
+                connection.insert_metadata(ChatHistory::default());
+            }
+            let history = connection.metadata_mut::<ChatHistory>().unwrap();
+            exceeds_rate(&mut history.0, Instant::now(), config)
+        };
+        if spamming {
+            if let Some(duration) = config.spam_mute() {
+                self.punish(
+                    PunishmentKind::Mute,
+                    uuid,
+                    Punishment::new(Some(duration), Violation::Spam.as_str().to_owned()),
+                )
+                .await;
+            }
+            return Some(Violation::Spam);
+        }
+
+        if exceeds_caps(message, config) {
+            return Some(Violation::Caps);
+        }
+        if self.filters.iter().any(|filter| filter.is_match(message)) {
+            return Some(Violation::Filter);
+        }
+
+        None
+    }
+
+    /// Punish the player, replacing a previous punishment of the same kind.
+    pub async fn punish(&self, kind: PunishmentKind, uuid: &str, punishment: Punishment) {
+        let key = kind.key(uuid);
+        if let Some(skytable) = self.skytable.get() {
+            if let Err(error) = store(skytable, key.as_str(), Some(&punishment)).await {
+                warn!(
+                    "Error while storing {} of {}: {:?}",
+                    kind.as_str(),
+                    uuid,
+                    error
+                );
+            }
+        } else {
+            self.punishments.write().await.insert(key, punishment);
+        }
+    }
+
+    /// Lift the punishment of the player. Returns whether the player was punished.
+    pub async fn pardon(&self, kind: PunishmentKind, uuid: &str) -> bool {
+        let active = self.active(kind, uuid).await.is_some();
+        let key = kind.key(uuid);
+        if let Some(skytable) = self.skytable.get() {
+            if let Err(error) = store(skytable, key.as_str(), None).await {
+                warn!(
+                    "Error while removing {} of {}: {:?}",
+                    kind.as_str(),
+                    uuid,
+                    error
+                );
+            }
+        } else {
+            self.punishments.write().await.remove(key.as_str());
+        }
+
+        active
+    }
+
+    /// The punishment of the player, if it did not expire yet. Expired punishments are removed.
+    pub async fn active(&self, kind: PunishmentKind, uuid: &str) -> Option<Punishment> {
+        let key = kind.key(uuid);
+        let punishment = match self.skytable.get() {
+            Some(skytable) => match load(skytable, key.as_str()).await {
+                Ok(punishment) => punishment,
+                Err(error) => {
+                    warn!(
+                        "Error while loading {} of {}: {:?}",
+                        kind.as_str(),
+                        uuid,
+                        error
+                    );
+                    None
+                }
+            },
+            None => self.punishments.read().await.get(key.as_str()).cloned(),
+        }?;
+
+        if punishment.active() {
+            return Some(punishment);
+        }
+        match self.skytable.get() {
+            Some(skytable) => {
+                if let Err(error) = store(skytable, key.as_str(), None).await {
+                    warn!(
+                        "Error while removing expired {} of {}: {:?}",
+                        kind.as_str(),
+                        uuid,
+                        error
+                    );
+                }
+            }
+            None => {
+                self.punishments.write().await.remove(key.as_str());
+            }
+        }
+
+        None
+    }
+
+    /// Publish the blocked message to the event stream.
+    pub async fn publish(&self, event: ChatModerated) {
+        if let Some(producer) = self.producer.get() {
+            let data = match serde_json::to_vec(&event) {
+                Ok(data) => data,
+                Err(error) => {
+                    warn!("Error while serializing moderation event: {:?}", error);
+                    return;
+                }
+            };
+            if let Err(error) = producer.send(YaufsEvent::CHAT_MODERATED, data).await {
+                warn!(
+                    "Error while publishing {}: {:?}",
+                    YaufsEvent::CHAT_MODERATED,
+                    error
+                );
+            }
+        }
+    }
+}
+
+async fn load(skytable: &AsyncPool, key: &str) -> anyhow::Result<Option<Punishment>> {
+    let mut connection = skytable.get().await?;
+    connection.switch(PUNISHMENTS).await?;
+    if connection.exists(key).await? == 0 {
+        return Ok(None);
+    }
+
+    let raw = connection.get::<String>(key).await?;
+    Ok(Some(serde_json::from_str(raw.as_str())?))
+}
+
+// update or remove the punishment, `set` would fail if the player was punished before
+async fn store(
+    skytable: &AsyncPool,
+    key: &str,
+    punishment: Option<&Punishment>,
+) -> anyhow::Result<()> {
+    let mut connection = skytable.get().await?;
+    connection.switch(PUNISHMENTS).await?;
+    match punishment {
+        Some(punishment) => {
+            connection
+                .uset([key], [serde_json::to_string(punishment)?])
+                .await?;
+        }
+        None => {
+            connection.del(key).await?;
+        }
+    }
+
+    Ok(())
+}
+
+/// The text of a command sending a message to other players (e.g. `/msg` or `/me`), which is
+/// moderated like a chat message. Other commands are `None`.
+pub fn command_message(command: &str) -> Option<&str> {
+    let (name, arguments) = command.split_once(' ')?;
+    // commands may be namespaced, e.g. `minecraft:msg`
+    let name = name.rsplit_once(':').map_or(name, |(_, name)| name);
+    let message = match name.to_ascii_lowercase().as_str() {
+        "msg" | "tell" | "w" => arguments.trim_start().split_once(' ')?.1,
+        "me" | "say" | "teammsg" | "tm" => arguments,
+        _ => return None,
+    };
+
+    Some(message.trim()).filter(|message| !message.is_empty())
+}
+
+/// Parse a duration like `30s`, `10m`, `2h`, `7d` or `1w`.
+pub fn parse_duration(duration: &str) -> Option<Duration> {
+    let unit = duration.chars().last()?;
+    let value = duration[..duration.len() - unit.len_utf8()]
+        .parse::<u64>()
+        .ok()?;
+    let seconds = match unit {
+        's' => 1,
+        'm' => 60,
+        'h' => 60 * 60,
+        'd' => 24 * 60 * 60,
+        'w' => 7 * 24 * 60 * 60,
+        _ => return None,
+    };
+
+    Some(Duration::from_secs(value * seconds)).filter(|duration| !duration.is_zero())
+}
+
+// blocked words are matched as whole words regardless of their case
+fn compile_filters(config: &ModerationConfig) -> Result<Vec<Regex>, regex::Error> {
+    let words = config
+        .words
+        .iter()
+        .map(|word| format!(r"(?i)\b{}\b", regex::escape(word)));
+
+    config
+        .patterns
+        .iter()
+        .cloned()
+        .chain(words)
+        .map(|pattern| Regex::new(pattern.as_str()))
+        .collect()
+}
+
+/// Record the message and check whether more messages than allowed were sent within the
+/// rate window.
+fn exceeds_rate(history: &mut VecDeque<Instant>, now: Instant, config: &ModerationConfig) -> bool {
+    let window = config.rate_window();
+    while let Some(sent) = history.front() {
+        if now.duration_since(*sent) < window {
+            break;
+        }
+        history.pop_front();
+    }
+    history.push_back(now);
+
+    history.len() > config.rate_limit
+}
+
+fn exceeds_caps(message: &str, config: &ModerationConfig) -> bool {
+    let letters = message.chars().filter(|c| c.is_alphabetic()).count();
+    if letters < config.caps_min_length {
+        return false;
+    }
+    let caps = message.chars().filter(|c| c.is_uppercase()).count();
+
+    caps as f32 / letters as f32 > config.max_caps
+}
+
+fn unix_time() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or(Duration::ZERO)
+        .as_secs()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_filters() {
+        let config = ModerationConfig {
+            patterns: vec![r"discord\.gg/\w+".to_owned()],
+            words: vec!["badword".to_owned()],
+            ..Default::default()
+        };
+        let filters = compile_filters(&config).unwrap();
+        let blocked = |message: &str| filters.iter().any(|filter| filter.is_match(message));
+
+        assert!(blocked("join discord.gg/abc"));
+        assert!(blocked("what a BadWord!"));
+        assert!(!blocked("badwords are fine"));
+        assert!(!blocked("hello there"));
+    }
+
+    #[test]
+    fn test_command_message() {
+        assert_eq!(
+            command_message("msg steve hello there"),
+            Some("hello there")
+        );
+        assert_eq!(command_message("minecraft:tell steve hi"), Some("hi"));
+        assert_eq!(command_message("me waves"), Some("waves"));
+        assert_eq!(command_message("say hello"), Some("hello"));
+        assert_eq!(command_message("msg steve"), None);
+        assert_eq!(command_message("gamemode creative"), None);
+        assert_eq!(command_message("spawn"), None);
+    }
+
+    #[test]
+    fn test_parse_duration() {
+        assert_eq!(parse_duration("30s"), Some(Duration::from_secs(30)));
+        assert_eq!(parse_duration("10m"), Some(Duration::from_secs(600)));
+        assert_eq!(parse_duration("2d"), Some(Duration::from_secs(172800)));
+        assert_eq!(parse_duration("0m"), None);
+        assert_eq!(parse_duration("10"), None);
+        assert_eq!(parse_duration("spam"), None);
+    }
+
+    #[tokio::test]
+    async fn test_punishments() {
+        let moderation = Moderation::new(&ModerationConfig::default());
+        let uuid = "069a79f4-44e9-4726-a5be-fca90e38aaf5";
+
+        moderation
+            .punish(
+                PunishmentKind::Mute,
+                uuid,
+                Punishment::new(None, "spam".to_owned()),
+            )
+            .await;
+        assert!(moderation
+            .active(PunishmentKind::Mute, uuid)
+            .await
+            .is_some());
+        assert!(moderation.active(PunishmentKind::Ban, uuid).await.is_none());
+        // a second mute replaces the first one
+        moderation
+            .punish(
+                PunishmentKind::Mute,
+                uuid,
+                Punishment::new(Some(Duration::from_secs(60)), "caps".to_owned()),
+            )
+            .await;
+        let mute = moderation.active(PunishmentKind::Mute, uuid).await.unwrap();
+        assert_eq!(mute.reason, "caps");
+
+        assert!(moderation.pardon(PunishmentKind::Mute, uuid).await);
+        assert!(!moderation.pardon(PunishmentKind::Mute, uuid).await);
+
+        // expired punishments are removed once they are looked up
+        moderation
+            .punish(
+                PunishmentKind::Ban,
+                uuid,
+                Punishment {
+                    until: Some(unix_time() - 1),
+                    reason: "griefing".to_owned(),
+                },
+            )
+            .await;
+        assert!(moderation.active(PunishmentKind::Ban, uuid).await.is_none());
+        assert!(moderation.punishments.read().await.is_empty());
+    }
+
+    #[test]
+    fn test_caps_and_rate() {
+        let config = ModerationConfig {
+            rate_limit: 2,
+            ..Default::default()
+        };
+
+        assert!(exceeds_caps("HELLO EVERYONE", &config));
+        assert!(!exceeds_caps("Hello Everyone", &config));
+        assert!(!exceeds_caps("HI", &config));
+
+        let mut history = VecDeque::new();
+        let now = Instant::now();
+        assert!(!exceeds_rate(&mut history, now, &config));
+        assert!(!exceeds_rate(&mut history, now, &config));
+        assert!(exceeds_rate(&mut history, now, &config));
+        // messages outside of the window are forgotten
+        let later = now + config.rate_window();
+        assert!(!exceeds_rate(&mut history, later, &config));
+    }
+}
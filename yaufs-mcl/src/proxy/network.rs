@@ -0,0 +1,537 @@
+/*
+ *    Copyright  2023.  Fritz Ochsmann
+ *
+ *    Licensed under the Apache License, Version 2.0 (the "License");
+ *    you may not use this file except in compliance with the License.
+ *    You may obtain a copy of the License at
+ *
+ *        http://www.apache.org/licenses/LICENSE-2.0
+ *
+ *    Unless required by applicable law or agreed to in writing, software
+ *    distributed under the License is distributed on an "AS IS" BASIS,
+ *    WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ *    See the License for the specific language governing permissions and
+ *    limitations under the License.
+ */
+
+use crate::config::CONFIG;
+use futures::StreamExt;
+use std::collections::{HashMap, HashSet};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::sync::{broadcast, OnceCell, RwLock};
+use yaufs_common::error::YaufsError;
+use yaufs_common::fluvio::dataplane::record::ConsumerRecord;
+use yaufs_common::fluvio::{Offset, TopicProducer};
+use yaufs_common::fluvio_err;
+use yaufs_common::net::packet::LoginSuccessPropertiesSpec;
+use yaufs_common::net::play::PlayerInfoEntry;
+use yaufs_common::skytable::actions::AsyncActions;
+use yaufs_common::skytable::ddl::{AsyncDdl, Keymap, KeymapType};
+use yaufs_common::skytable::pool::AsyncPool;
+use yaufs_common::uuid::UUID4;
+use yaufs_common::yaufs_proto::fluvio::{
+    PlayerChat, PlayerJoined, PlayerLeft, PlayerProperty, YaufsEvent,
+};
+
+const SESSIONS: &str = "default:sessions";
+// the last heartbeat of every proxy replica, the sessions of replicas which stopped sending
+// heartbeats (e.g. after a crash) are removed by the remaining ones
+const PROXIES: &str = "default:proxies";
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(30);
+const PROXY_TIMEOUT: Duration = Duration::from_secs(90);
+// the delay before the event stream is opened again, doubled on every failed attempt
+const RECONNECT_DELAY: Duration = Duration::from_secs(1);
+const MAX_RECONNECT_DELAY: Duration = Duration::from_secs(60);
+
+// the network state is shared by all connections of this proxy
+lazy_static::lazy_static! {
+    pub static ref NETWORK: Network = Network::new();
+}
+
+#[derive(Debug, Clone)]
+pub enum NetworkEvent {
+    Joined(PlayerJoined),
+    Left(PlayerLeft),
+    Chat(PlayerChat),
+}
+
+/// Keeps track of all players of the network, including the ones connected to other proxy
+/// replicas. Replicas exchange their events over fluvio, while skytable stores the sessions
+/// for replicas joining later on.
+pub struct Network {
+    // identifies this proxy replica
+    proxy: String,
+    players: RwLock<HashMap<String, PlayerJoined>>,
+    events: broadcast::Sender<NetworkEvent>,
+    producer: OnceCell<TopicProducer>,
+    skytable: OnceCell<AsyncPool>,
+}
+
+impl Network {
+    fn new() -> Self {
+        let proxy = std::env::var("HOSTNAME").unwrap_or_else(|_| {
+            let mut buffer = [0; 8];
+            openssl::rand::rand_bytes(&mut buffer).unwrap();
+            buffer.iter().map(|byte| format!("{byte:02x}")).collect()
+        });
+        let (events, _) = broadcast::channel(256);
+
+        Self {
+            proxy,
+            players: RwLock::new(HashMap::new()),
+            events,
+            producer: OnceCell::new(),
+            skytable: OnceCell::new(),
+        }
+    }
+
+    /// Connect to the session store and the event stream. This has to be called once before
+    /// the proxy accepts connections.
+    pub async fn init(&'static self, skytable: AsyncPool) -> anyhow::Result<()> {
+        let mut connection = skytable.get().await?;
+        // the tables may already exist if another replica created them
+        for table in [SESSIONS, PROXIES] {
+            let keymap = Keymap::new(table)
+                .set_ktype(KeymapType::Str)
+                .set_vtype(KeymapType::Str);
+            if connection.create_table(keymap).await.is_err() {
+                debug!("Table {} already exists", table);
+            }
+        }
+        drop(connection);
+        self.beat(&skytable).await?;
+
+        // load the sessions of the other replicas, the ones of this replica were left by a
+        // previous process with the same hostname
+        let (sessions, mut stale) = self.sessions(&skytable).await?;
+        let (own, sessions) = sessions
+            .into_iter()
+            .partition::<Vec<PlayerJoined>, _>(|player| self.proxy.eq(&player.proxy));
+        stale.extend(own);
+        let mut players = self.players.write().await;
+        players.extend(
+            sessions
+                .into_iter()
+                .map(|player| (player.uuid.clone(), player)),
+        );
+        info!("Loaded {} sessions of the network", players.len());
+        drop(players);
+
+        let _ = self.skytable.set(skytable);
+        let _ = self
+            .producer
+            .set(yaufs_common::fluvio_util::producer().await?);
+        self.remove_stale(stale).await;
+
+        // apply the events of the other replicas
+        tokio::spawn(async move {
+            let mut delay = RECONNECT_DELAY;
+            loop {
+                match self.consume(&mut delay).await {
+                    Ok(()) => warn!("The event stream ended, reconnecting in {:?}", delay),
+                    Err(error) => warn!(
+                        "Error while consuming the network events, reconnecting in {:?}: {:?}",
+                        delay, error
+                    ),
+                }
+                tokio::time::sleep(delay).await;
+                delay = (delay * 2).min(MAX_RECONNECT_DELAY);
+
+                // the events published in the meantime are lost, so the players are reloaded
+                if let Some(skytable) = self.skytable.get() {
+                    match self.sessions(skytable).await {
+                        Ok((sessions, _)) => {
+                            *self.players.write().await = sessions
+                                .into_iter()
+                                .map(|player| (player.uuid.clone(), player))
+                                .collect()
+                        }
+                        Err(error) => warn!("Error while reloading the sessions: {:?}", error),
+                    }
+                }
+            }
+        });
+
+        // renew the heartbeat and clean up after the replicas which stopped
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(HEARTBEAT_INTERVAL);
+            loop {
+                interval.tick().await;
+                if let Err(error) = self.heartbeat().await {
+                    warn!("Error while sending the heartbeat: {:?}", error);
+                }
+            }
+        });
+
+        Ok(())
+    }
+
+    async fn consume(&self, delay: &mut Duration) -> Result<(), YaufsError> {
+        let consumer = yaufs_common::fluvio_util::consumer().await?;
+        let mut stream = fluvio_err!(consumer.stream(Offset::end()).await)?;
+
+        while let Some(record) = stream.next().await {
+            let record: ConsumerRecord = fluvio_err!(record)?;
+            // the stream works again, so the next reconnect starts with the initial delay
+            *delay = RECONNECT_DELAY;
+            let key = match record.key() {
+                Some(key) => String::from_utf8_lossy(key).to_string(),
+                None => continue,
+            };
+
+            let event = match key.as_str() {
+                YaufsEvent::PLAYER_JOINED => {
+                    serde_json::from_slice(record.value()).map(NetworkEvent::Joined)
+                }
+                YaufsEvent::PLAYER_LEFT => {
+                    serde_json::from_slice(record.value()).map(NetworkEvent::Left)
+                }
+                YaufsEvent::PLAYER_CHAT => {
+                    serde_json::from_slice(record.value()).map(NetworkEvent::Chat)
+                }
+                // we do not listen for any other events here
+                _ => continue,
+            };
+            let event = match event {
+                Ok(event) => event,
+                Err(error) => {
+                    warn!("Skipping malformed {} event: {:?}", key, error);
+                    continue;
+                }
+            };
+            // the events of this replica were already applied locally
+            if !self.proxy.eq(event.proxy()) {
+                self.apply(event).await;
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn heartbeat(&self) -> anyhow::Result<()> {
+        let skytable = match self.skytable.get() {
+            Some(skytable) => skytable,
+            None => return Ok(()),
+        };
+        self.beat(skytable).await?;
+        let (_, stale) = self.sessions(skytable).await?;
+        self.remove_stale(stale).await;
+
+        Ok(())
+    }
+
+    // store the heartbeat of this replica
+    async fn beat(&self, skytable: &AsyncPool) -> anyhow::Result<()> {
+        let mut connection = skytable.get().await?;
+        connection.switch(PROXIES).await?;
+        connection
+            .uset([self.proxy.as_str()], [now().to_string()])
+            .await?;
+
+        Ok(())
+    }
+
+    /// Read the stored sessions, split into the ones of live replicas and the ones left behind
+    /// by replicas which stopped sending heartbeats.
+    async fn sessions(
+        &self,
+        skytable: &AsyncPool,
+    ) -> anyhow::Result<(Vec<PlayerJoined>, Vec<PlayerJoined>)> {
+        let mut connection = skytable.get().await?;
+        connection.switch(PROXIES).await?;
+        let now = now();
+        let mut proxies = HashSet::new();
+        for proxy in connection.lskeys::<Vec<String>>(10000).await? {
+            let heartbeat = connection.get::<String>(proxy.as_str()).await?;
+            if heartbeat
+                .parse::<u64>()
+                .map_or(false, |heartbeat| alive(heartbeat, now))
+            {
+                proxies.insert(proxy);
+            } else {
+                connection.del(proxy.as_str()).await?;
+            }
+        }
+
+        connection.switch(SESSIONS).await?;
+        let mut sessions = Vec::new();
+        let mut stale = Vec::new();
+        for key in connection.lskeys::<Vec<String>>(10000).await? {
+            let raw = connection.get::<String>(key.as_str()).await?;
+            let player = serde_json::from_str::<PlayerJoined>(raw.as_str())?;
+            match proxies.contains(&player.proxy) {
+                true => sessions.push(player),
+                false => stale.push(player),
+            }
+        }
+
+        Ok((sessions, stale))
+    }
+
+    // remove the sessions of stopped replicas, which could not announce the leave themselves
+    async fn remove_stale(&self, stale: Vec<PlayerJoined>) {
+        for player in stale {
+            info!(
+                "Removing session of {} left by proxy {}",
+                player.name, player.proxy
+            );
+            self.leave(PlayerLeft {
+                proxy: player.proxy,
+                uuid: player.uuid,
+                backend: player.backend,
+            })
+            .await;
+        }
+    }
+
+    pub fn proxy(&self) -> &str {
+        self.proxy.as_str()
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<NetworkEvent> {
+        self.events.subscribe()
+    }
+
+    /// All players of the network which are not connected to the given backend.
+    pub async fn players_outside(&self, backend: &str) -> Vec<PlayerJoined> {
+        self.players
+            .read()
+            .await
+            .values()
+            .filter(|player| !backend.eq(player.backend.as_str()))
+            .cloned()
+            .collect()
+    }
+
+    /// Find a player of the network by its name.
+    pub async fn find(&self, name: &str) -> Option<PlayerJoined> {
+        self.players
+            .read()
+            .await
+            .values()
+            .find(|player| player.name.eq_ignore_ascii_case(name))
+            .cloned()
+    }
+
+    /// The number of players of other replicas per backend.
+    pub async fn remote_players(&self) -> HashMap<String, usize> {
+        let mut backends = HashMap::new();
+        for player in self.players.read().await.values() {
+            if !self.proxy.eq(&player.proxy) {
+                *backends.entry(player.backend.clone()).or_insert(0) += 1;
+            }
+        }
+
+        backends
+    }
+
+    pub async fn join(&self, player: PlayerJoined) {
+        let value = serde_json::to_string(&player).unwrap();
+        if let Err(error) = self.store(player.uuid.as_str(), Some(value)).await {
+            warn!(
+                "Error while storing session of {}: {:?}",
+                player.name, error
+            );
+        }
+
+        self.publish(YaufsEvent::PLAYER_JOINED, player.clone().into())
+            .await;
+        self.apply(NetworkEvent::Joined(player)).await;
+    }
+
+    /// Refresh the stored session of a player without announcing the player again.
+    pub async fn update(&self, player: PlayerJoined) {
+        let value = serde_json::to_string(&player).unwrap();
+        if let Err(error) = self.store(player.uuid.as_str(), Some(value)).await {
+            warn!(
+                "Error while updating session of {}: {:?}",
+                player.name, error
+            );
+        }
+
+        self.players
+            .write()
+            .await
+            .insert(player.uuid.clone(), player);
+    }
+
+    pub async fn leave(&self, player: PlayerLeft) {
+        if let Err(error) = self.store(player.uuid.as_str(), None).await {
+            warn!(
+                "Error while removing session of {}: {:?}",
+                player.uuid, error
+            );
+        }
+
+        self.publish(YaufsEvent::PLAYER_LEFT, player.clone().into())
+            .await;
+        self.apply(NetworkEvent::Left(player)).await;
+    }
+
+    pub async fn chat(&self, chat: PlayerChat) {
+        self.publish(YaufsEvent::PLAYER_CHAT, chat.clone().into())
+            .await;
+        self.apply(NetworkEvent::Chat(chat)).await;
+    }
+
+    // update or remove the session in the session store
+    async fn store(&self, uuid: &str, value: Option<String>) -> anyhow::Result<()> {
+        let skytable = match self.skytable.get() {
+            Some(skytable) => skytable,
+            None => return Ok(()),
+        };
+        let mut connection = skytable.get().await?;
+        connection.switch(SESSIONS).await?;
+
+        match value {
+            // the session is stored again whenever the player changes the backend, which would
+            // fail with `set` since the key exists already
+            Some(value) => {
+                connection.uset([uuid], [value]).await?;
+            }
+            None => {
+                connection.del(uuid).await?;
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn publish(&self, event: &'static str, data: Vec<u8>) {
+        if let Some(producer) = self.producer.get() {
+            if let Err(error) = producer.send(event, data).await {
+                warn!("Error while publishing {event}: {:?}", error);
+            }
+        }
+    }
+
+    async fn apply(&self, event: NetworkEvent) {
+        match &event {
+            NetworkEvent::Joined(player) => {
+                self.players
+                    .write()
+                    .await
+                    .insert(player.uuid.clone(), player.clone());
+            }
+            NetworkEvent::Left(player) => {
+                self.players.write().await.remove(&player.uuid);
+            }
+            NetworkEvent::Chat(_) => {}
+        }
+
+        // there may be no connection subscribed at the moment
+        let _ = self.events.send(event);
+    }
+}
+
+// unix timestamp (in seconds) of the heartbeats
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+// whether the replica with the given heartbeat is still running
+fn alive(heartbeat: u64, now: u64) -> bool {
+    now.saturating_sub(heartbeat) < PROXY_TIMEOUT.as_secs()
+}
+
+impl NetworkEvent {
+    pub fn proxy(&self) -> &str {
+        match self {
+            Self::Joined(player) => player.proxy.as_str(),
+            Self::Left(player) => player.proxy.as_str(),
+            Self::Chat(chat) => chat.proxy.as_str(),
+        }
+    }
+}
+
+/// Resolve the chat group of the given backend.
+pub fn group_of(backend: &str) -> Option<&'static String> {
+    CONFIG
+        .network
+        .groups
+        .iter()
+        .find(|(_, backends)| backends.iter().any(|member| backend.eq(member)))
+        .map(|(group, _)| group)
+}
+
+pub fn to_properties(properties: &[LoginSuccessPropertiesSpec]) -> Vec<PlayerProperty> {
+    properties
+        .iter()
+        .map(|property| PlayerProperty {
+            name: property.name.clone(),
+            value: property.value.clone(),
+            signature: Some(property.signature.clone()).filter(|_| property.signed),
+        })
+        .collect()
+}
+
+impl From<&PlayerJoined> for PlayerInfoEntry {
+    fn from(player: &PlayerJoined) -> Self {
+        Self {
+            uuid: UUID4::parse(player.uuid.as_str()).unwrap_or_else(UUID4::random),
+            name: player.name.clone(),
+            properties: player
+                .properties
+                .iter()
+                .map(|property| LoginSuccessPropertiesSpec {
+                    name: property.name.clone(),
+                    value: property.value.clone(),
+                    signed: property.signature.is_some(),
+                    signature: property.signature.clone().unwrap_or_default(),
+                })
+                .collect(),
+            latency: 0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_alive() {
+        let now = now();
+        assert!(alive(now, now));
+        assert!(alive(now - 60, now));
+        assert!(!alive(now - 120, now));
+        // heartbeats of replicas with a clock ahead of this one
+        assert!(alive(now + 5, now));
+    }
+
+    #[tokio::test]
+    #[ignore = "requires a running skytable instance"]
+    async fn test_join_twice() {
+        let network = Network::new();
+        let skytable = yaufs_common::database::skytable::connect().await;
+        let mut connection = skytable.get().await.unwrap();
+        let keymap = Keymap::new(SESSIONS)
+            .set_ktype(KeymapType::Str)
+            .set_vtype(KeymapType::Str);
+        let _ = connection.create_table(keymap).await;
+        connection.switch(SESSIONS).await.unwrap();
+        let _ = network.skytable.set(skytable.clone());
+
+        let uuid = UUID4::random().to_string();
+        let mut player = PlayerJoined {
+            proxy: network.proxy().to_owned(),
+            uuid: uuid.clone(),
+            name: "test".to_owned(),
+            properties: vec![],
+            backend: "lobby".to_owned(),
+            session: None,
+        };
+        network.join(player.clone()).await;
+        player.backend = "survival".to_owned();
+        network.join(player.clone()).await;
+
+        let stored = connection.get::<String>(uuid.as_str()).await.unwrap();
+        let stored = serde_json::from_str::<PlayerJoined>(stored.as_str()).unwrap();
+        assert_eq!(stored.backend, "survival");
+
+        network.store(uuid.as_str(), None).await.unwrap();
+    }
+}
@@ -0,0 +1,180 @@
+/*
+ *    Copyright  2023.  Fritz Ochsmann
+ *
+ *    Licensed under the Apache License, Version 2.0 (the "License");
+ *    you may not use this file except in compliance with the License.
+ *    You may obtain a copy of the License at
+ *
+ *        http://www.apache.org/licenses/LICENSE-2.0
+ *
+ *    Unless required by applicable law or agreed to in writing, software
+ *    distributed under the License is distributed on an "AS IS" BASIS,
+ *    WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ *    See the License for the specific language governing permissions and
+ *    limitations under the License.
+ */
+
+use crate::config::{PermissionGroup, PlayerPermissions, CONFIG};
+use serde::de::DeserializeOwned;
+use std::collections::HashSet;
+use tokio::sync::OnceCell;
+use yaufs_common::oidc::OIDCClient;
+use yaufs_common::skytable::actions::AsyncActions;
+use yaufs_common::skytable::ddl::{AsyncDdl, Keymap, KeymapType};
+use yaufs_common::skytable::pool::AsyncPool;
+
+const GROUPS: &str = "default:permission_groups";
+const PLAYERS: &str = "default:player_permissions";
+
+lazy_static::lazy_static! {
+    pub static ref PERMISSIONS: Permissions = Permissions::default();
+}
+
+// the oidc client is only required if players are linked to zitadel users
+static OIDC_CLIENT: OnceCell<OIDCClient> = OnceCell::const_new();
+
+/// Resolves the permission nodes of the players. Groups and players are read from the
+/// configuration and, if enabled, from skytable. Both are stored as json.
+#[derive(Default)]
+pub struct Permissions {
+    skytable: OnceCell<AsyncPool>,
+}
+
+impl Permissions {
+    /// Create the permission tables. This has to be called once before the proxy accepts
+    /// connections.
+    pub async fn init(&self, skytable: AsyncPool) -> anyhow::Result<()> {
+        let mut connection = skytable.get().await?;
+        for table in [GROUPS, PLAYERS] {
+            let keymap = Keymap::new(table)
+                .set_ktype(KeymapType::Str)
+                .set_vtype(KeymapType::Str);
+            // the table may already exist if another replica created it
+            if connection.create_table(keymap).await.is_err() {
+                debug!("Table {} already exists", table);
+            }
+        }
+        drop(connection);
+
+        let _ = self.skytable.set(skytable);
+        Ok(())
+    }
+
+    /// Resolve all permission nodes of the player, including the ones of its groups, their
+    /// parents and the groups granted by the roles of its zitadel user.
+    pub async fn resolve(&self, uuid: &str) -> HashSet<String> {
+        let player = match self.load::<PlayerPermissions>(PLAYERS, uuid).await {
+            Ok(Some(player)) => player,
+            Ok(None) => CONFIG
+                .permissions
+                .players
+                .get(uuid)
+                .cloned()
+                .unwrap_or_default(),
+            Err(error) => {
+                warn!("Error while loading permissions of {}: {:?}", uuid, error);
+                CONFIG
+                    .permissions
+                    .players
+                    .get(uuid)
+                    .cloned()
+                    .unwrap_or_default()
+            }
+        };
+
+        let mut groups = player.groups;
+        groups.extend(CONFIG.permissions.default_group.clone());
+        if let Some(user) = player.zitadel_user.as_ref() {
+            match user_roles(user.as_str()).await {
+                Ok(roles) => groups.extend(
+                    roles
+                        .iter()
+                        .filter_map(|role| CONFIG.permissions.roles.get(role))
+                        .cloned(),
+                ),
+                Err(error) => warn!("Error while fetching roles of {}: {:?}", user, error),
+            }
+        }
+
+        let mut permissions = player.permissions.into_iter().collect::<HashSet<String>>();
+        // groups may inherit from each other in cycles
+        let mut visited = HashSet::new();
+        while let Some(name) = groups.pop() {
+            if !visited.insert(name.clone()) {
+                continue;
+            }
+
+            match self.group(name.as_str()).await {
+                Some(group) => {
+                    permissions.extend(group.permissions);
+                    groups.extend(group.parents);
+                }
+                None => debug!("Unknown permission group {}", name),
+            }
+        }
+
+        permissions
+    }
+
+    async fn group(&self, name: &str) -> Option<PermissionGroup> {
+        match self.load::<PermissionGroup>(GROUPS, name).await {
+            Ok(Some(group)) => return Some(group),
+            Ok(None) => {}
+            Err(error) => warn!("Error while loading permission group {}: {:?}", name, error),
+        }
+
+        CONFIG.permissions.groups.get(name).cloned()
+    }
+
+    async fn load<T: DeserializeOwned>(&self, table: &str, key: &str) -> anyhow::Result<Option<T>> {
+        let skytable = match self.skytable.get() {
+            Some(skytable) => skytable,
+            None => return Ok(None),
+        };
+        let mut connection = skytable.get().await?;
+        connection.switch(table).await?;
+        if connection.exists(key).await? == 0 {
+            return Ok(None);
+        }
+
+        let raw = connection.get::<String>(key).await?;
+        Ok(Some(serde_json::from_str(raw.as_str())?))
+    }
+}
+
+async fn user_roles(user: &str) -> anyhow::Result<Vec<String>> {
+    let oidc_client = OIDC_CLIENT
+        .get_or_try_init(|| OIDCClient::new_from_env(Vec::new()))
+        .await?;
+
+    Ok(oidc_client.user_roles(user).await?)
+}
+
+/// Check whether the permission is granted by one of the nodes. A node ending with `*` grants
+/// all permissions below it.
+pub fn matches(permissions: &HashSet<String>, permission: &str) -> bool {
+    if permissions.contains(permission) || permissions.contains("*") {
+        return true;
+    }
+
+    permission
+        .match_indices('.')
+        .any(|(index, _)| permissions.contains(format!("{}.*", &permission[..index]).as_str()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_matches_wildcards() {
+        let permissions = HashSet::from(["proxy.queue.*".to_owned(), "proxy.send".to_owned()]);
+
+        assert!(matches(&permissions, "proxy.send"));
+        assert!(matches(&permissions, "proxy.queue.priority"));
+        assert!(matches(&permissions, "proxy.queue.priority.high"));
+        assert!(!matches(&permissions, "proxy.queue"));
+        assert!(!matches(&permissions, "proxy.kick"));
+        assert!(matches(&HashSet::from(["*".to_owned()]), "proxy.kick"));
+    }
+}
@@ -0,0 +1,443 @@
+/*
+ *    Copyright  2023.  Fritz Ochsmann
+ *
+ *    Licensed under the Apache License, Version 2.0 (the "License");
+ *    you may not use this file except in compliance with the License.
+ *    You may obtain a copy of the License at
+ *
+ *        http://www.apache.org/licenses/LICENSE-2.0
+ *
+ *    Unless required by applicable law or agreed to in writing, software
+ *    distributed under the License is distributed on an "AS IS" BASIS,
+ *    WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ *    See the License for the specific language governing permissions and
+ *    limitations under the License.
+ */
+
+use crate::config::{QueueConfig, RoutingConfig, CONFIG};
+use crate::proxy::connection::{ConnectionCommand, ProxyConnection};
+use crate::proxy::network::NETWORK;
+use crate::proxy::PeerMap;
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::time::Duration;
+use tokio::sync::{Mutex, OnceCell};
+use tokio::time::Instant;
+use tonic::codegen::http::header::AUTHORIZATION;
+use tonic::Request;
+use yaufs_common::net::packet::{Packet762, PlaySetActionBarTextSpec};
+use yaufs_common::oidc::OIDCClient;
+use yaufs_common::protocol::State;
+use yaufs_common::tonic::inject_tracing_context;
+use yaufs_common::types::Chat;
+use yaufs_common::yaufs_proto::control_plane_v1::control_plane_v1_client::ControlPlaneV1Client;
+use yaufs_common::yaufs_proto::control_plane_v1::StartInstanceRequest;
+
+lazy_static::lazy_static! {
+    pub static ref QUEUE: Queue = Queue::default();
+}
+
+// the oidc client is only required if instances are started by the queue
+static OIDC_CLIENT: OnceCell<OIDCClient> = OnceCell::const_new();
+
+struct QueueEntry {
+    address: SocketAddr,
+    enqueued_at: Instant,
+    // the start of the transfer to a backend which is currently in progress
+    transfer: Option<Instant>,
+}
+
+/// Players waiting for a free slot on one of the backends of a group. The players are held in
+/// the limbo meanwhile.
+#[derive(Default)]
+pub struct Queue {
+    groups: Mutex<HashMap<String, Vec<QueueEntry>>>,
+    // the last instance start per template
+    scaled: Mutex<HashMap<String, Instant>>,
+}
+
+impl Queue {
+    pub fn start(&'static self, peers: PeerMap) {
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(Duration::from_secs(1));
+
+            loop {
+                interval.tick().await;
+                self.tick(&peers).await;
+            }
+        });
+    }
+
+    /// Choose a backend of the group for a new player. Returns `None` if the player has to be
+    /// queued, which is also the case if other players are waiting already.
+    pub async fn admit(&self, group: &str, peers: &PeerMap) -> Option<String> {
+        let waiting = self
+            .groups
+            .lock()
+            .await
+            .get(group)
+            .map(|entries| !entries.is_empty())
+            .unwrap_or_default();
+        if waiting {
+            return None;
+        }
+
+        let remote = remote_players().await;
+        let peers = peers.lock().await;
+        free_slots(group, &peers, &remote)
+            .into_iter()
+            .find(|(_, slots)| *slots > 0)
+            .map(|(backend, _)| backend)
+    }
+
+    pub async fn enqueue(&self, group: &str, address: SocketAddr) {
+        debug!("Queueing {} for {}", address, group);
+        self.groups
+            .lock()
+            .await
+            .entry(group.to_owned())
+            .or_default()
+            .push(QueueEntry {
+                address,
+                enqueued_at: Instant::now(),
+                transfer: None,
+            });
+    }
+
+    /// Remove the player from the queue, e.g. after the transfer completed.
+    pub async fn remove(&self, address: &SocketAddr) {
+        for entries in self.groups.lock().await.values_mut() {
+            entries.retain(|entry| !address.eq(&entry.address));
+        }
+    }
+
+    async fn tick(&self, peers: &PeerMap) {
+        let remote = remote_players().await;
+        let mut groups = self.groups.lock().await;
+        let peers = peers.lock().await;
+
+        for (group, entries) in groups.iter_mut() {
+            // disconnected players leave the queue
+            entries.retain(|entry| peers.contains_key(&entry.address));
+            sort_entries(entries, |address| {
+                peers.get(address).map(priority).unwrap_or_default()
+            });
+
+            let mut slots = free_slots(group, &peers, &remote);
+            // transfers in progress already occupy a slot
+            let transferring = entries
+                .iter()
+                .filter(|entry| {
+                    entry
+                        .transfer
+                        .map(|started| started.elapsed() < CONFIG.queue.transfer_timeout())
+                        .unwrap_or_default()
+                })
+                .count();
+            reserve_slots(&mut slots, transferring);
+
+            let length = entries.len();
+            let mut full = false;
+            for (position, entry) in entries.iter_mut().enumerate() {
+                let connection = peers.get(&entry.address).unwrap();
+                // players which are still logging in are not transferred yet
+                if !State::Play.eq(connection.state()) {
+                    continue;
+                }
+                if let Some(started) = entry.transfer {
+                    if started.elapsed() < CONFIG.queue.transfer_timeout() {
+                        continue;
+                    }
+                }
+
+                match slots.iter_mut().find(|(_, free)| *free > 0) {
+                    Some((backend, free)) => {
+                        *free -= 1;
+                        entry.transfer = Some(Instant::now());
+                        connection
+                            .command(ConnectionCommand::Connect(backend.clone()))
+                            .await;
+                    }
+                    None => {
+                        full = true;
+                        connection
+                            .command(ConnectionCommand::Send(position_text(position, length)))
+                            .await;
+                    }
+                }
+            }
+
+            if full {
+                self.scale(group).await;
+            }
+        }
+
+        groups.retain(|_, entries| !entries.is_empty());
+    }
+
+    // ask the control plane for another instance of the template behind the group
+    async fn scale(&self, group: &str) {
+        let (endpoint, template) = match (
+            CONFIG.queue.control_plane.as_ref(),
+            CONFIG.queue.templates.get(group),
+        ) {
+            (Some(endpoint), Some(template)) => (endpoint.clone(), template.clone()),
+            _ => return,
+        };
+
+        let mut scaled = self.scaled.lock().await;
+        if let Some(last) = scaled.get(template.as_str()) {
+            if last.elapsed() < CONFIG.queue.scale_cooldown() {
+                return;
+            }
+        }
+        scaled.insert(template.clone(), Instant::now());
+        drop(scaled);
+
+        info!(
+            "Starting another instance of {} for the queue of {}",
+            template, group
+        );
+        tokio::spawn(async move {
+            if let Err(error) = start_instance(endpoint, template.clone()).await {
+                warn!(
+                    "Error while starting an instance of {}: {:?}",
+                    template, error
+                );
+            }
+        });
+    }
+}
+
+async fn start_instance(endpoint: String, template_id: String) -> anyhow::Result<()> {
+    let oidc_client = OIDC_CLIENT
+        .get_or_try_init(|| OIDCClient::new_from_env(vec![String::from("control-plane")]))
+        .await?;
+    let mut client = ControlPlaneV1Client::connect(endpoint).await?;
+
+    let mut request = Request::new(StartInstanceRequest {
+        template_id,
+        count: 1,
+        replicas: None,
+        overrides: None,
+        namespace: None,
+    });
+    let access_token = oidc_client.obtain_access_token().await?;
+    request
+        .metadata_mut()
+        .insert(AUTHORIZATION.as_str(), access_token.parse()?);
+    client
+        .start_instance(inject_tracing_context(request))
+        .await?;
+
+    Ok(())
+}
+
+async fn remote_players() -> HashMap<String, usize> {
+    if CONFIG.network.enabled {
+        NETWORK.remote_players().await
+    } else {
+        HashMap::new()
+    }
+}
+
+// the free slots of every backend of the group, including the players of other replicas
+fn free_slots(
+    group: &str,
+    peers: &HashMap<SocketAddr, ProxyConnection>,
+    remote: &HashMap<String, usize>,
+) -> Vec<(String, usize)> {
+    free_slots_in(&CONFIG.routing, &CONFIG.queue, group, peers, remote)
+}
+
+fn free_slots_in(
+    routing: &RoutingConfig,
+    queue: &QueueConfig,
+    group: &str,
+    peers: &HashMap<SocketAddr, ProxyConnection>,
+    remote: &HashMap<String, usize>,
+) -> Vec<(String, usize)> {
+    let backends = match routing.backends.get(group) {
+        Some(backends) => backends,
+        None => return Vec::new(),
+    };
+
+    backends
+        .iter()
+        .map(|backend| {
+            let local = peers
+                .values()
+                .filter(|connection| Some(backend).eq(&connection.backend().as_ref()))
+                .count();
+            let players = local + remote.get(backend).copied().unwrap_or_default();
+
+            (backend.clone(), queue.max_players.saturating_sub(players))
+        })
+        .collect()
+}
+
+// the transfers in progress occupy the first free slots
+fn reserve_slots(slots: &mut [(String, usize)], mut transferring: usize) {
+    for (_, free) in slots.iter_mut() {
+        let occupied = transferring.min(*free);
+        *free -= occupied;
+        transferring -= occupied;
+    }
+}
+
+// higher priorities first, players of the same priority in the order they were queued
+fn sort_entries(entries: &mut [QueueEntry], priority: impl Fn(&SocketAddr) -> u32) {
+    entries.sort_by_key(|entry| (Reverse(priority(&entry.address)), entry.enqueued_at));
+}
+
+fn position_text(position: usize, length: usize) -> Packet762 {
+    let text = format!("Position in queue: {}/{}", position + 1, length);
+
+    Packet762::PlaySetActionBarText(PlaySetActionBarTextSpec {
+        text: Chat::from_text(text.as_str()),
+    })
+}
+
+fn priority(connection: &ProxyConnection) -> u32 {
+    priority_in(&CONFIG.queue, connection)
+}
+
+fn priority_in(queue: &QueueConfig, connection: &ProxyConnection) -> u32 {
+    queue
+        .tiers
+        .iter()
+        .filter(|tier| connection.has_permission(tier.permission.as_str()))
+        .map(|tier| tier.priority)
+        .max()
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::QueueTier;
+    use std::collections::HashSet;
+    use std::sync::Arc;
+
+    fn address(port: u16) -> SocketAddr {
+        SocketAddr::from(([127, 0, 0, 1], port))
+    }
+
+    fn entry(port: u16, enqueued_at: Instant) -> QueueEntry {
+        QueueEntry {
+            address: address(port),
+            enqueued_at,
+            transfer: None,
+        }
+    }
+
+    fn connection(backend: Option<&str>, permissions: &[&str]) -> ProxyConnection {
+        let mut connection = ProxyConnection::default();
+        connection.set_backend(backend.map(str::to_owned));
+        connection.set_permissions(
+            permissions
+                .iter()
+                .map(|permission| permission.to_string())
+                .collect::<HashSet<String>>(),
+        );
+        connection
+    }
+
+    #[test]
+    fn test_priority() {
+        let queue = QueueConfig {
+            tiers: vec![
+                QueueTier {
+                    permission: "queue.vip".to_owned(),
+                    priority: 10,
+                },
+                QueueTier {
+                    permission: "queue.staff".to_owned(),
+                    priority: 20,
+                },
+            ],
+            ..QueueConfig::default()
+        };
+
+        assert_eq!(priority_in(&queue, &connection(None, &[])), 0);
+        assert_eq!(priority_in(&queue, &connection(None, &["queue.vip"])), 10);
+        assert_eq!(priority_in(&queue, &connection(None, &["queue.*"])), 20);
+    }
+
+    #[test]
+    fn test_sort_entries() {
+        let now = Instant::now();
+        let mut entries = vec![
+            entry(1, now),
+            entry(2, now + Duration::from_secs(1)),
+            entry(3, now + Duration::from_secs(2)),
+            entry(4, now + Duration::from_secs(3)),
+        ];
+        // the later players 3 and 4 have a higher priority
+        sort_entries(&mut entries, |address| match address.port() {
+            3 | 4 => 10,
+            _ => 0,
+        });
+
+        let order = entries
+            .iter()
+            .map(|entry| entry.address.port())
+            .collect::<Vec<u16>>();
+        assert_eq!(order, vec![3, 4, 1, 2]);
+    }
+
+    #[test]
+    fn test_free_slots() {
+        let mut routing = RoutingConfig::default();
+        routing.backends.insert(
+            "lobby".to_owned(),
+            vec!["lobby-1".to_owned(), "lobby-2".to_owned()],
+        );
+        let queue = QueueConfig {
+            max_players: 2,
+            ..QueueConfig::default()
+        };
+        let peers = HashMap::from([
+            (address(1), connection(Some("lobby-1"), &[])),
+            (address(2), connection(Some("survival"), &[])),
+        ]);
+        let remote = HashMap::from([("lobby-2".to_owned(), 3)]);
+
+        let mut slots = free_slots_in(&routing, &queue, "lobby", &peers, &remote);
+        assert_eq!(
+            slots,
+            vec![("lobby-1".to_owned(), 1), ("lobby-2".to_owned(), 0)]
+        );
+        assert!(free_slots_in(&routing, &queue, "unknown", &peers, &remote).is_empty());
+
+        reserve_slots(&mut slots, 2);
+        assert_eq!(
+            slots,
+            vec![("lobby-1".to_owned(), 0), ("lobby-2".to_owned(), 0)]
+        );
+    }
+
+    #[test]
+    fn test_position_text() {
+        match position_text(0, 3) {
+            Packet762::PlaySetActionBarText(spec) => {
+                assert_eq!(spec.text, Chat::from_text("Position in queue: 1/3"))
+            }
+            _ => panic!("not an action bar text"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_admit_behind_waiting_players() {
+        let queue = Queue::default();
+        let peers: PeerMap = Arc::new(Mutex::new(HashMap::new()));
+
+        queue.enqueue("lobby", address(1)).await;
+        // new players never overtake the ones already waiting
+        assert_eq!(queue.admit("lobby", &peers).await, None);
+
+        queue.remove(&address(1)).await;
+        assert!(queue.groups.lock().await["lobby"].is_empty());
+    }
+}
@@ -0,0 +1,227 @@
+/*
+ *    Copyright  2023.  Fritz Ochsmann
+ *
+ *    Licensed under the Apache License, Version 2.0 (the "License");
+ *    you may not use this file except in compliance with the License.
+ *    You may obtain a copy of the License at
+ *
+ *        http://www.apache.org/licenses/LICENSE-2.0
+ *
+ *    Unless required by applicable law or agreed to in writing, software
+ *    distributed under the License is distributed on an "AS IS" BASIS,
+ *    WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ *    See the License for the specific language governing permissions and
+ *    limitations under the License.
+ */
+
+use crate::config::{ResourcePackConfig, RouteConfig, RoutingConfig, CONFIG};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use yaufs_common::craftio_rs::CraftTokioConnection;
+use yaufs_common::status::StatusFaviconSpec;
+
+lazy_static::lazy_static! {
+    // the favicons are read once, a missing file only removes the favicon
+    static ref FAVICONS: HashMap<PathBuf, Vec<u8>> = {
+        let routing = &CONFIG.routing;
+        routing
+            .hosts
+            .values()
+            .chain(std::iter::once(&routing.default))
+            .filter_map(|route| route.favicon.clone())
+            .filter_map(|path| match std::fs::read(path.as_path()) {
+                Ok(data) => Some((path, data)),
+                Err(error) => {
+                    warn!("Error while reading favicon {:?}: {}", path, error);
+                    None
+                }
+            })
+            .collect()
+    };
+}
+
+// spreads the clients over the backends of a group
+static NEXT_BACKEND: AtomicUsize = AtomicUsize::new(0);
+
+/// Resolve the route of the given hostname. Exact matches are preferred over wildcards, of which
+/// the most specific one wins.
+pub fn resolve(hostname: &str) -> &'static RouteConfig {
+    resolve_in(&CONFIG.routing, hostname)
+}
+
+fn resolve_in<'a>(routing: &'a RoutingConfig, hostname: &str) -> &'a RouteConfig {
+    // clients may send the fully qualified name including the trailing dot
+    let hostname = hostname.trim_end_matches('.').to_lowercase();
+
+    if let Some((_, route)) = routing
+        .hosts
+        .iter()
+        .find(|(pattern, _)| pattern.eq_ignore_ascii_case(hostname.as_str()))
+    {
+        return route;
+    }
+
+    routing
+        .hosts
+        .iter()
+        .filter_map(|(pattern, route)| {
+            let domain = pattern.strip_prefix("*.")?.to_lowercase();
+            hostname
+                .ends_with(format!(".{domain}").as_str())
+                .then_some((domain.len(), route))
+        })
+        .max_by_key(|(length, _)| *length)
+        .map(|(_, route)| route)
+        .unwrap_or(&routing.default)
+}
+
+pub fn motd(route: &RouteConfig) -> &str {
+    route
+        .motd
+        .as_ref()
+        .or(CONFIG.routing.default.motd.as_ref())
+        .map(String::as_str)
+        .unwrap_or_default()
+}
+
+pub fn favicon(route: &RouteConfig) -> Option<StatusFaviconSpec> {
+    let path = route
+        .favicon
+        .as_ref()
+        .or(CONFIG.routing.default.favicon.as_ref())?;
+
+    FAVICONS.get(path).map(|data| StatusFaviconSpec {
+        content_type: "image/png".to_owned(),
+        data: data.clone(),
+    })
+}
+
+/// Resolve the group a backend belongs to.
+pub fn group_of(backend: &str) -> Option<&'static String> {
+    group_in(&CONFIG.routing, backend)
+}
+
+fn group_in<'a>(routing: &'a RoutingConfig, backend: &str) -> Option<&'a String> {
+    routing
+        .backends
+        .iter()
+        .find(|(_, backends)| backends.iter().any(|member| backend.eq(member)))
+        .map(|(group, _)| group)
+}
+
+/// Resolve the resource pack of a client, the pack of the group of its backend is preferred
+/// over the one of its route.
+pub fn resource_pack(
+    route: &'static RouteConfig,
+    backend: Option<&str>,
+) -> Option<&'static ResourcePackConfig> {
+    resource_pack_in(&CONFIG.routing, route, backend)
+}
+
+fn resource_pack_in<'a>(
+    routing: &'a RoutingConfig,
+    route: &'a RouteConfig,
+    backend: Option<&str>,
+) -> Option<&'a ResourcePackConfig> {
+    backend
+        .and_then(|backend| group_in(routing, backend))
+        .and_then(|group| routing.resource_packs.get(group))
+        .or(route.resource_pack.as_ref())
+}
+
+/// Connect to one of the backends of the route. Unreachable backends are skipped.
+pub async fn connect(route: &RouteConfig) -> anyhow::Result<(CraftTokioConnection, String)> {
+    let backends = CONFIG
+        .routing
+        .backends
+        .get(route.group.as_str())
+        .filter(|backends| !backends.is_empty())
+        .ok_or(anyhow::anyhow!("Unknown backend group {}", route.group))?;
+
+    let offset = NEXT_BACKEND.fetch_add(1, Ordering::Relaxed);
+    for index in 0..backends.len() {
+        let backend = &backends[(offset + index) % backends.len()];
+
+        match CraftTokioConnection::connect_server_tokio(backend.as_str()).await {
+            Ok(connection) => return Ok((connection, backend.clone())),
+            Err(error) => warn!("Backend {} is not reachable: {}", backend, error),
+        }
+    }
+
+    anyhow::bail!("No backend of group {} is reachable", route.group)
+}
+
+/// Connect to the given backend.
+pub async fn connect_to(backend: &str) -> anyhow::Result<(CraftTokioConnection, String)> {
+    let connection = CraftTokioConnection::connect_server_tokio(backend).await?;
+
+    Ok((connection, backend.to_owned()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn route(group: &str) -> RouteConfig {
+        RouteConfig {
+            group: group.to_owned(),
+            motd: None,
+            favicon: None,
+            resource_pack: None,
+        }
+    }
+
+    fn resource_pack(hash: &str) -> ResourcePackConfig {
+        ResourcePackConfig {
+            url: format!("https://example.net/{hash}.zip"),
+            hash: hash.to_owned(),
+            required: false,
+            prompt: None,
+        }
+    }
+
+    #[test]
+    fn test_resolve_forced_hosts() {
+        let mut routing = RoutingConfig::default();
+        routing
+            .hosts
+            .insert("skyblock.example.net".to_owned(), route("skyblock"));
+        routing
+            .hosts
+            .insert("*.example.net".to_owned(), route("lobby"));
+        routing
+            .hosts
+            .insert("*.eu.example.net".to_owned(), route("eu"));
+
+        assert_eq!(
+            resolve_in(&routing, "SkyBlock.example.net.").group,
+            "skyblock"
+        );
+        assert_eq!(resolve_in(&routing, "play.example.net").group, "lobby");
+        assert_eq!(resolve_in(&routing, "play.eu.example.net").group, "eu");
+        // the wildcard only matches subdomains
+        assert_eq!(resolve_in(&routing, "example.net").group, "default");
+        assert_eq!(resolve_in(&routing, "localhost").group, "default");
+    }
+
+    #[test]
+    fn test_resource_pack_of_group() {
+        let mut routing = RoutingConfig::default();
+        routing.backends.insert(
+            "skyblock".to_owned(),
+            vec!["skyblock-0:25565".to_owned(), "skyblock-1:25565".to_owned()],
+        );
+        routing
+            .resource_packs
+            .insert("skyblock".to_owned(), resource_pack("skyblock"));
+        let mut route = route("default");
+        route.resource_pack = Some(resource_pack("lobby"));
+
+        let pack = |backend| resource_pack_in(&routing, &route, backend).map(|pack| &pack.hash);
+        assert_eq!(pack(Some("skyblock-1:25565")).unwrap(), "skyblock");
+        assert_eq!(pack(Some("127.0.0.1:25566")).unwrap(), "lobby");
+        // the limbo has no backend
+        assert_eq!(pack(None).unwrap(), "lobby");
+    }
+}
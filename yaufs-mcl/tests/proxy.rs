@@ -0,0 +1,447 @@
+/*
+ *    Copyright  2023.  Fritz Ochsmann
+ *
+ *    Licensed under the Apache License, Version 2.0 (the "License");
+ *    you may not use this file except in compliance with the License.
+ *    You may obtain a copy of the License at
+ *
+ *        http://www.apache.org/licenses/LICENSE-2.0
+ *
+ *    Unless required by applicable law or agreed to in writing, software
+ *    distributed under the License is distributed on an "AS IS" BASIS,
+ *    WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ *    See the License for the specific language governing permissions and
+ *    limitations under the License.
+ */
+
+//! End to end tests of the proxy. A fake backend and a fake session server are started next to
+//! the proxy, so the tests run without any network access. Since the configuration of the proxy
+//! is global, every test starts its own proxy process with its own configuration.
+
+use rsa::pkcs8::DecodePublicKey;
+use rsa::rand_core::OsRng;
+use rsa::{Pkcs1v15Encrypt, RsaPublicKey};
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::process::Stdio;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::process::{Child, Command};
+use yaufs_common::craftio_rs::{
+    CraftAsyncReader, CraftAsyncWriter, CraftConnection, CraftIo, CraftTokioConnection,
+};
+use yaufs_common::mcproto_rs::protocol::{Packet, PacketDirection};
+use yaufs_common::nbt::{NamedTag, Tag};
+use yaufs_common::net::packet::{
+    HandshakeNextState, HandshakeSpec, LoginEncryptionResponseSpec, LoginSetCompressionSpec,
+    LoginStartSpec, LoginSuccessSpec, Packet762, PlayClientChatMessageSpec, PlayDisconnectSpec,
+    PlayLoginSpec, RawPacket762, StatusPingSpec, StatusRequestSpec,
+};
+use yaufs_common::net::play;
+use yaufs_common::protocol::State;
+use yaufs_common::types::{Chat, CountedArray, NamedNbtTag, RemainingBytes, VarInt};
+use yaufs_common::uuid::UUID4;
+use yaufs_common::{BytesSerializer, Serialize};
+
+const MOTD: &str = "Integration";
+// the uuid the fake session server returns for every player
+const PLAYER_UUID: &str = "069a79f4-44e9-4726-a5be-fca90e38aaf5";
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A proxy process, which is killed once the test finished.
+struct Proxy {
+    address: SocketAddr,
+    config: PathBuf,
+    // only held to kill the process on drop
+    _process: Child,
+}
+
+impl Proxy {
+    async fn start() -> Self {
+        let backend = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let session_server = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        // the proxy binds the address itself, so a free port is reserved and released again
+        let address = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+
+        let config = serde_json::json!({
+            "address": address.to_string(),
+            "session_server": format!("http://{}", session_server.local_addr().unwrap()),
+            "routing": {
+                "backends": { "default": [backend.local_addr().unwrap().to_string()] },
+                "default": { "group": "default", "motd": MOTD }
+            }
+        });
+        let path = std::env::temp_dir().join(format!(
+            "yaufs-mcl-{}-{}.json",
+            std::process::id(),
+            address.port()
+        ));
+        std::fs::write(path.as_path(), config.to_string()).unwrap();
+
+        tokio::spawn(fake_session_server(session_server));
+        tokio::spawn(fake_backend(backend));
+        let process = Command::new(env!("CARGO_BIN_EXE_yaufs-mcl"))
+            .env("PROXY_CONFIG_PATH", path.as_path())
+            .stdout(Stdio::null())
+            .kill_on_drop(true)
+            .spawn()
+            .unwrap();
+
+        // wait until the proxy accepts connections
+        tokio::time::timeout(TIMEOUT, async {
+            while TcpStream::connect(address).await.is_err() {
+                tokio::time::sleep(Duration::from_millis(50)).await;
+            }
+        })
+        .await
+        .expect("The proxy did not start in time");
+
+        Self {
+            address,
+            config: path,
+            _process: process,
+        }
+    }
+}
+
+impl Drop for Proxy {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(self.config.as_path());
+    }
+}
+
+// answers every join check with the same profile
+async fn fake_session_server(listener: TcpListener) {
+    while let Ok((mut stream, _)) = listener.accept().await {
+        tokio::spawn(async move {
+            let mut request = Vec::new();
+            let mut buffer = [0; 1024];
+            while !request.windows(4).any(|window| window == b"\r\n\r\n") {
+                let read = stream.read(&mut buffer).await?;
+                if read == 0 {
+                    return Ok(());
+                }
+                request.extend_from_slice(&buffer[..read]);
+            }
+
+            let request = String::from_utf8_lossy(request.as_slice());
+            let name = request
+                .split(|character| character == '?' || character == '&' || character == ' ')
+                .find_map(|parameter| parameter.strip_prefix("username="))
+                .unwrap_or_default()
+                .to_owned();
+            // the session server sends the uuid without hyphens
+            let body = serde_json::json!({
+                "id": PLAYER_UUID.replace('-', ""),
+                "name": name,
+                "properties": []
+            })
+            .to_string();
+            let response = format!(
+                "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                body.len(),
+                body
+            );
+            stream.write_all(response.as_bytes()).await?;
+            stream.shutdown().await
+        });
+    }
+}
+
+// an offline mode backend, which greets the player and echoes its chat messages
+async fn fake_backend(listener: TcpListener) {
+    while let Ok((stream, _)) = listener.accept().await {
+        tokio::spawn(async move {
+            let result = backend_connection(stream).await;
+            if let Err(error) = result.as_ref() {
+                tracing::debug!("Fake backend connection failed: {:?}", error);
+            }
+            result
+        });
+    }
+}
+
+async fn backend_connection(stream: TcpStream) -> anyhow::Result<()> {
+    let mut connection = CraftConnection::from_async_with_state(
+        stream.into_split(),
+        PacketDirection::ServerBound,
+        State::Handshaking,
+    );
+
+    match read(&mut connection).await? {
+        Packet762::Handshake(_) => connection.set_state(State::Login),
+        packet => anyhow::bail!("Expected a handshake, received {:?}", packet.id()),
+    }
+    let login = match read(&mut connection).await? {
+        Packet762::LoginStart(login) => login,
+        packet => anyhow::bail!("Expected a login start, received {:?}", packet.id()),
+    };
+
+    // the backend uses its own threshold towards the proxy
+    connection
+        .write_packet_async(Packet762::LoginSetCompression(LoginSetCompressionSpec {
+            threshold: VarInt(64),
+        }))
+        .await?;
+    connection.set_compression_threshold(Some(64));
+    connection
+        .write_packet_async(Packet762::LoginSuccess(LoginSuccessSpec {
+            uuid: login.uuid,
+            username: login.name.clone(),
+            properties: CountedArray::from(vec![]),
+        }))
+        .await?;
+    connection.set_state(State::Play);
+    connection
+        .write_packet_async(Packet762::PlayLogin(play_login()))
+        .await?;
+    connection
+        .write_packet_async(system_message(format!("welcome {}", login.name).as_str()))
+        .await?;
+
+    loop {
+        if let Packet762::PlayClientChatMessage(message) = read(&mut connection).await? {
+            let text = play::chat_message(message.data.data.as_slice())
+                .map_err(|error| anyhow::anyhow!("Invalid chat message: {:?}", error))?;
+            match text.as_str() {
+                "quit" => {
+                    connection
+                        .write_packet_async(Packet762::PlayDisconnect(PlayDisconnectSpec {
+                            reason: Chat::from_text("bye"),
+                        }))
+                        .await?;
+                    return Ok(());
+                }
+                text => {
+                    connection
+                        .write_packet_async(system_message(format!("echo: {text}").as_str()))
+                        .await?;
+                }
+            }
+        }
+    }
+}
+
+fn play_login() -> PlayLoginSpec {
+    PlayLoginSpec {
+        entity_id: 1,
+        hardcore: false,
+        game_mode: 0,
+        previous_game_mode: -1,
+        dimension_names: CountedArray::from(vec!["minecraft:overworld".to_owned()]),
+        registry_codec: NamedNbtTag {
+            root: NamedTag {
+                name: String::new(),
+                payload: Tag::Compound(vec![]),
+            },
+        },
+        dimension_type: "minecraft:overworld".to_owned(),
+        dimension_name: "minecraft:overworld".to_owned(),
+        hashed_seed: 0,
+        max_players: VarInt(20),
+        view_distance: VarInt(8),
+        simulation_distance: VarInt(8),
+        reduced_debug_info: false,
+        enable_respawn_screen: true,
+        is_debug: false,
+        is_flat: false,
+        death_location: None,
+    }
+}
+
+fn system_message(text: &str) -> Packet762 {
+    Packet762::PlaySystemChatMessage(play::system_message(Chat::from_text(text)))
+}
+
+// an unsigned chat message of the client
+fn chat_message(text: &str) -> Packet762 {
+    let mut serializer = BytesSerializer::default();
+    text.to_owned().mc_serialize(&mut serializer).unwrap();
+    0i64.mc_serialize(&mut serializer).unwrap();
+    0i64.mc_serialize(&mut serializer).unwrap();
+    false.mc_serialize(&mut serializer).unwrap();
+    VarInt(0).mc_serialize(&mut serializer).unwrap();
+    let mut data = serializer.into_bytes();
+    // no acknowledged messages
+    data.extend_from_slice(&[0; 3]);
+
+    Packet762::PlayClientChatMessage(PlayClientChatMessageSpec {
+        data: RemainingBytes { data },
+    })
+}
+
+fn text(chat: &Chat) -> &str {
+    match chat {
+        Chat::Text(component) => component.text.as_str(),
+        _ => "",
+    }
+}
+
+async fn read<C: CraftAsyncReader + Send>(connection: &mut C) -> anyhow::Result<Packet762> {
+    tokio::time::timeout(TIMEOUT, connection.read_packet_async::<RawPacket762>())
+        .await??
+        .ok_or(anyhow::anyhow!("Connection closed"))
+}
+
+async fn handshake(proxy: &Proxy, next_state: HandshakeNextState) -> CraftTokioConnection {
+    let mut connection =
+        CraftTokioConnection::connect_server_tokio(proxy.address.to_string().as_str())
+            .await
+            .unwrap();
+    connection
+        .write_packet_async(Packet762::Handshake(HandshakeSpec {
+            version: VarInt(762),
+            server_address: "localhost".to_owned(),
+            server_port: proxy.address.port(),
+            next_state,
+        }))
+        .await
+        .unwrap();
+
+    connection
+}
+
+// perform an online mode login like the vanilla client does
+async fn login(proxy: &Proxy, name: &str) -> CraftTokioConnection {
+    let mut connection = handshake(proxy, HandshakeNextState::Login).await;
+    connection.set_state(State::Login);
+    connection
+        .write_packet_async(Packet762::LoginStart(LoginStartSpec {
+            name: name.to_owned(),
+            has_uuid: false,
+            uuid: UUID4::random(),
+        }))
+        .await
+        .unwrap();
+
+    let request = match read(&mut connection).await.unwrap() {
+        Packet762::LoginEncryptionRequest(request) => request,
+        packet => panic!("Expected an encryption request, received {:?}", packet.id()),
+    };
+    let public_key = RsaPublicKey::from_public_key_der(request.public_key.as_slice()).unwrap();
+    let mut secret = [0; 16];
+    openssl::rand::rand_bytes(&mut secret).unwrap();
+    connection
+        .write_packet_async(Packet762::LoginEncryptionResponse(
+            LoginEncryptionResponseSpec {
+                shared_secret: CountedArray::from(
+                    public_key
+                        .encrypt(&mut OsRng, Pkcs1v15Encrypt, &secret)
+                        .unwrap(),
+                ),
+                verify_token: CountedArray::from(
+                    public_key
+                        .encrypt(&mut OsRng, Pkcs1v15Encrypt, request.verify_token.as_slice())
+                        .unwrap(),
+                ),
+            },
+        ))
+        .await
+        .unwrap();
+    connection.enable_encryption(&secret, &secret).unwrap();
+
+    // the proxy negotiates its own threshold
+    match read(&mut connection).await.unwrap() {
+        Packet762::LoginSetCompression(compression) => {
+            assert_eq!(compression.threshold.0, 256);
+            connection.set_compression_threshold(Some(compression.threshold.0));
+        }
+        packet => panic!(
+            "Expected a compression threshold, received {:?}",
+            packet.id()
+        ),
+    }
+    match read(&mut connection).await.unwrap() {
+        Packet762::LoginSuccess(success) => {
+            // the profile of the session server replaces the one of the offline backend
+            assert_eq!(success.uuid, UUID4::parse(PLAYER_UUID).unwrap());
+            assert_eq!(success.username, name);
+        }
+        packet => panic!("Expected a login success, received {:?}", packet.id()),
+    }
+    connection.set_state(State::Play);
+
+    connection
+}
+
+// skip all packets until the next system message
+async fn next_message(connection: &mut CraftTokioConnection) -> String {
+    loop {
+        if let Packet762::PlaySystemChatMessage(message) = read(connection).await.unwrap() {
+            return text(&message.content).to_owned();
+        }
+    }
+}
+
+#[tokio::test]
+async fn test_status_ping() {
+    let proxy = Proxy::start().await;
+    let mut connection = handshake(&proxy, HandshakeNextState::Status).await;
+    connection.set_state(State::Status);
+
+    connection
+        .write_packet_async(Packet762::StatusRequest(StatusRequestSpec {}))
+        .await
+        .unwrap();
+    match read(&mut connection).await.unwrap() {
+        Packet762::StatusResponse(response) => {
+            assert_eq!(text(&response.response.description), MOTD);
+        }
+        packet => panic!("Expected a status response, received {:?}", packet.id()),
+    }
+
+    connection
+        .write_packet_async(Packet762::StatusPing(StatusPingSpec { payload: 42 }))
+        .await
+        .unwrap();
+    match read(&mut connection).await.unwrap() {
+        Packet762::StatusPong(pong) => assert_eq!(pong.payload, 42),
+        packet => panic!("Expected a pong, received {:?}", packet.id()),
+    }
+}
+
+#[tokio::test]
+async fn test_login_and_play() {
+    let proxy = Proxy::start().await;
+    let mut connection = login(&proxy, "steve").await;
+
+    match read(&mut connection).await.unwrap() {
+        Packet762::PlayLogin(login) => assert_eq!(login.entity_id, 1),
+        packet => panic!("Expected a join game, received {:?}", packet.id()),
+    }
+    assert_eq!(next_message(&mut connection).await, "welcome steve");
+
+    connection
+        .write_packet_async(chat_message("hello"))
+        .await
+        .unwrap();
+    assert_eq!(next_message(&mut connection).await, "echo: hello");
+}
+
+#[tokio::test]
+async fn test_backend_disconnect() {
+    let proxy = Proxy::start().await;
+    let mut connection = login(&proxy, "alex").await;
+    assert_eq!(next_message(&mut connection).await, "welcome alex");
+
+    connection
+        .write_packet_async(chat_message("quit"))
+        .await
+        .unwrap();
+    loop {
+        match read(&mut connection).await {
+            Ok(Packet762::PlayDisconnect(disconnect)) => {
+                assert_eq!(text(&disconnect.reason), "bye");
+                break;
+            }
+            Ok(_) => {}
+            Err(error) => panic!("Expected a disconnect, received {:?}", error),
+        }
+    }
+    // the proxy closes the connection afterwards
+    assert!(read(&mut connection).await.is_err());
+}
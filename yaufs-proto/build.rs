@@ -21,6 +21,34 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
             "Template",
             "#[derive(serde::Serialize, serde::Deserialize)]",
         )
+        .type_attribute(
+            "Storage",
+            "#[derive(serde::Serialize, serde::Deserialize)]",
+        )
+        .type_attribute(
+            "Container",
+            "#[derive(serde::Serialize, serde::Deserialize)]",
+        )
+        .type_attribute(
+            "ContainerPort",
+            "#[derive(serde::Serialize, serde::Deserialize)]",
+        )
+        .type_attribute(
+            "EnvVar",
+            "#[derive(serde::Serialize, serde::Deserialize)]",
+        )
+        .type_attribute(
+            "SecretKeySelector",
+            "#[derive(serde::Serialize, serde::Deserialize)]",
+        )
+        .type_attribute(
+            "Resources",
+            "#[derive(serde::Serialize, serde::Deserialize)]",
+        )
+        .type_attribute(
+            "Probe",
+            "#[derive(serde::Serialize, serde::Deserialize)]",
+        )
         .compile(&["../proto/template-service-v1.proto"], &["../proto"])?;
 
     tonic_build::configure()
@@ -28,6 +56,18 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
             "Instance",
             "#[derive(serde::Serialize, serde::Deserialize, IntoSkyhashBytes, FromSkyhashBytes)]",
         )
+        // instances stored before the replicas were introduced
+        .field_attribute("Instance.replicas", "#[serde(default)]")
+        // instances stored before the namespace was introduced
+        .field_attribute("Instance.namespace", "#[serde(default)]")
+        .type_attribute(
+            "InstanceStatus",
+            "#[derive(serde::Serialize, serde::Deserialize)]",
+        )
+        .type_attribute(
+            "InstanceOverrides",
+            "#[derive(serde::Serialize, serde::Deserialize)]",
+        )
         .compile(&["../proto/control-plane-v1.proto"], &["../proto"])?;
 
     Ok(())
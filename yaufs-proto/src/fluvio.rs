@@ -25,6 +25,15 @@ impl YaufsEvent {
     pub const INSTANCE_DEPLOYED: &'static str = "INSTANCE_DEPLOYED";
     pub const INSTANCE_STARTED: &'static str = "INSTANCE_STARTED";
     pub const INSTANCE_STOPPED: &'static str = "INSTANCE_STOPPED";
+
+    /// Event issued by a proxy once a player joined a backend
+    pub const PLAYER_JOINED: &'static str = "PLAYER_JOINED";
+    /// Event issued by a proxy once a player left the network
+    pub const PLAYER_LEFT: &'static str = "PLAYER_LEFT";
+    /// Event issued by a proxy for chat messages relayed between backends
+    pub const PLAYER_CHAT: &'static str = "PLAYER_CHAT";
+    /// Event issued by a proxy for chat messages blocked by its moderation
+    pub const CHAT_MODERATED: &'static str = "CHAT_MODERATED";
 }
 
 macro_rules! event {
@@ -81,3 +90,59 @@ event!(
         issuer: Option<String>,
     }
 );
+
+event!(
+    pub struct PlayerJoined {
+        proxy: String,
+        uuid: String,
+        name: String,
+        properties: Vec<PlayerProperty>,
+        backend: String,
+        session: Option<PlayerSession>,
+    }
+
+    pub struct PlayerLeft {
+        proxy: String,
+        uuid: String,
+        backend: String,
+    }
+
+    pub struct PlayerChat {
+        proxy: String,
+        uuid: String,
+        name: String,
+        group: String,
+        backend: String,
+        message: String,
+    }
+
+    pub struct ChatModerated {
+        proxy: String,
+        uuid: String,
+        name: String,
+        backend: Option<String>,
+        message: String,
+        violation: String,
+    }
+);
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct PlayerProperty {
+    pub name: String,
+    pub value: String,
+    pub signature: Option<String>,
+}
+
+/// Information about the client of a player, which is known to the proxy.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct PlayerSession {
+    pub protocol_version: i32,
+    /// unix timestamp (in seconds) of the connect to the proxy
+    pub connected_at: u64,
+    pub brand: Option<String>,
+    pub locale: Option<String>,
+    pub view_distance: Option<i8>,
+    /// round trip time of the last keep alive (in milliseconds)
+    #[serde(default)]
+    pub ping: Option<u64>,
+}
@@ -35,6 +35,11 @@ pub struct InternalV1Template {
     pub name: String,
     pub image: String,
     pub created_at: String,
+    // templates created before the storage was introduced
+    #[serde(default)]
+    pub storage: Option<Storage>,
+    #[serde(default)]
+    pub container: Option<Container>,
 }
 
 impl From<InternalV1Template> for Template {
@@ -44,6 +49,8 @@ impl From<InternalV1Template> for Template {
             image: value.image,
             name: value.name,
             created_at: value.created_at,
+            storage: value.storage,
+            container: value.container,
         }
     }
 }
@@ -122,6 +129,8 @@ mod tests {
         let request = tonic::Request::new(CreateTemplateRequest {
             name: "test-name".to_string(),
             image: "test-image".to_string(),
+            storage: None,
+            container: None,
         });
         client.create_template(request).await?;
 